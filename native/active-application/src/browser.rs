@@ -0,0 +1,86 @@
+//! Active tab URL for known browsers, behind `--with-url` since it needs
+//! Automation/UI Automation access and adds tens of milliseconds.
+
+use active_win_pos_rs::ActiveWindow;
+
+#[cfg(target_os = "macos")]
+pub fn active_tab_url(window: &ActiveWindow) -> Option<String> {
+    use std::process::Command;
+
+    let bundle_id = crate::macos::bundle_id(window.process_id)?;
+    let script = match bundle_id.as_str() {
+        "com.apple.Safari" | "com.apple.SafariTechnologyPreview" => format!(
+            "tell application id \"{}\" to get URL of front document",
+            bundle_id
+        ),
+        "com.google.Chrome"
+        | "com.google.Chrome.canary"
+        | "com.brave.Browser"
+        | "com.microsoft.edgemac"
+        | "com.vivaldi.Vivaldi"
+        | "com.operasoftware.Opera"
+        | "company.thebrowser.Browser" => format!(
+            "tell application id \"{}\" to get URL of active tab of front window",
+            bundle_id
+        ),
+        // Firefox has no AppleScript dictionary for tabs
+        _ => return None,
+    };
+
+    let output = Command::new("osascript")
+        .args(["-e", &script])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!url.is_empty() && url != "missing value").then_some(url)
+}
+
+#[cfg(target_os = "windows")]
+pub fn active_tab_url(window: &ActiveWindow) -> Option<String> {
+    use windows::Win32::System::Variant::VARIANT;
+    use windows::Win32::UI::Accessibility::{
+        IUIAutomationValuePattern, TreeScope_Descendants, UIA_ControlTypePropertyId,
+        UIA_EditControlTypeId, UIA_ValuePatternId,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+
+    let exe_name = window
+        .process_path
+        .file_name()?
+        .to_str()?
+        .to_ascii_lowercase();
+    if !matches!(
+        exe_name.as_str(),
+        "chrome.exe" | "msedge.exe" | "brave.exe" | "vivaldi.exe" | "opera.exe" | "firefox.exe"
+    ) {
+        return None;
+    }
+
+    let automation = crate::uia::automation()?;
+    unsafe {
+        // The address bar is the first edit control in these browsers
+        let browser = automation.ElementFromHandle(GetForegroundWindow()).ok()?;
+        let condition = automation
+            .CreatePropertyCondition(
+                UIA_ControlTypePropertyId,
+                &VARIANT::from(UIA_EditControlTypeId.0),
+            )
+            .ok()?;
+        let address_bar = browser.FindFirst(TreeScope_Descendants, &condition).ok()?;
+        let value: IUIAutomationValuePattern =
+            address_bar.GetCurrentPatternAs(UIA_ValuePatternId).ok()?;
+
+        let url = value.CurrentValue().ok()?.to_string();
+        (!url.is_empty()).then_some(url)
+    }
+}
+
+// No browser-independent way to read the URL on Linux
+#[cfg(target_os = "linux")]
+pub fn active_tab_url(_window: &ActiveWindow) -> Option<String> {
+    None
+}
@@ -0,0 +1,149 @@
+//! The focused UI element inside the active window, behind `--with-focus`, so
+//! the host can tell a text field from a button or plain web content before
+//! deciding whether and how to type. Values are never read, only what kind of
+//! element has focus.
+
+use serde::Serialize;
+
+// Never built on Linux, where focus can't be read yet
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusedElement {
+    /// Platform role name, e.g. "AXTextField" on macOS or "Edit" on Windows
+    pub role: String,
+    pub editable: bool,
+    /// Accessible label or title, falling back to the localized role
+    pub description: Option<String>,
+}
+
+// Labels can be whole paragraphs in web content
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+const MAX_DESCRIPTION_CHARS: usize = 80;
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn short_description(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.chars().take(MAX_DESCRIPTION_CHARS).collect())
+}
+
+#[cfg(target_os = "macos")]
+pub fn focused_element() -> Option<FocusedElement> {
+    use crate::macos::{
+        AX_ERROR_SUCCESS, AXUIElementCreateSystemWide, AXUIElementIsAttributeSettable,
+        AXUIElementRef, copy_attribute,
+    };
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::string::CFString;
+
+    unsafe fn string_attribute(element: AXUIElementRef, attribute: &str) -> Option<String> {
+        unsafe { copy_attribute(element, attribute) }?
+            .downcast::<CFString>()
+            .map(|value| value.to_string())
+    }
+
+    unsafe {
+        let system_wide = CFType::wrap_under_create_rule(AXUIElementCreateSystemWide());
+        let focused = copy_attribute(system_wide.as_CFTypeRef(), "AXFocusedUIElement")?;
+        let element = focused.as_CFTypeRef();
+
+        let role = string_attribute(element, "AXRole")?;
+        // Web views report contenteditable regions as groups with a settable
+        // value, so the role alone isn't enough
+        let mut settable = 0u8;
+        let value_settable = AXUIElementIsAttributeSettable(
+            element,
+            CFString::new("AXValue").as_concrete_TypeRef(),
+            &mut settable,
+        ) == AX_ERROR_SUCCESS
+            && settable != 0;
+        let editable = value_settable
+            || matches!(
+                role.as_str(),
+                "AXTextField" | "AXTextArea" | "AXComboBox" | "AXSearchField"
+            );
+
+        let description = ["AXDescription", "AXTitle", "AXRoleDescription"]
+            .iter()
+            .find_map(|attribute| {
+                string_attribute(element, attribute).and_then(|text| short_description(&text))
+            });
+
+        Some(FocusedElement {
+            role,
+            editable,
+            description,
+        })
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn focused_element() -> Option<FocusedElement> {
+    use windows::Win32::UI::Accessibility::*;
+
+    // Stable names rather than the localized control type, which depends on
+    // the display language
+    fn role_name(control_type: UIA_CONTROLTYPE_ID) -> Option<&'static str> {
+        Some(match control_type {
+            UIA_EditControlTypeId => "Edit",
+            UIA_DocumentControlTypeId => "Document",
+            UIA_ComboBoxControlTypeId => "ComboBox",
+            UIA_ButtonControlTypeId => "Button",
+            UIA_CheckBoxControlTypeId => "CheckBox",
+            UIA_HyperlinkControlTypeId => "Hyperlink",
+            UIA_ListControlTypeId => "List",
+            UIA_ListItemControlTypeId => "ListItem",
+            UIA_MenuItemControlTypeId => "MenuItem",
+            UIA_TabItemControlTypeId => "TabItem",
+            UIA_TextControlTypeId => "Text",
+            UIA_TreeItemControlTypeId => "TreeItem",
+            UIA_GroupControlTypeId => "Group",
+            UIA_PaneControlTypeId => "Pane",
+            UIA_WindowControlTypeId => "Window",
+            UIA_CustomControlTypeId => "Custom",
+            _ => return None,
+        })
+    }
+
+    let automation = crate::uia::automation()?;
+    unsafe {
+        let element = automation.GetFocusedElement().ok()?;
+
+        let control_type = element.CurrentControlType().ok()?;
+        let localized = element
+            .CurrentLocalizedControlType()
+            .map(|name| name.to_string())
+            .unwrap_or_default();
+        let role = role_name(control_type)
+            .map(str::to_string)
+            .unwrap_or_else(|| localized.clone());
+
+        // Rich edit controls (e.g. Word) only expose the text pattern, so an
+        // Edit without a value pattern still counts
+        let editable =
+            match element.GetCurrentPatternAs::<IUIAutomationValuePattern>(UIA_ValuePatternId) {
+                Ok(value) => value
+                    .CurrentIsReadOnly()
+                    .is_ok_and(|read_only| !read_only.as_bool()),
+                Err(_) => control_type == UIA_EditControlTypeId,
+            };
+
+        let description = element
+            .CurrentName()
+            .ok()
+            .and_then(|name| short_description(&name.to_string()))
+            .or_else(|| short_description(&localized));
+
+        Some(FocusedElement {
+            role,
+            editable,
+            description,
+        })
+    }
+}
+
+// AT-SPI would need a D-Bus connection to the accessibility bus
+#[cfg(target_os = "linux")]
+pub fn focused_element() -> Option<FocusedElement> {
+    None
+}
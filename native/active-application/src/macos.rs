@@ -0,0 +1,79 @@
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::string::{CFString, CFStringRef};
+use objc::rc::autoreleasepool;
+use objc::runtime::Object;
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::{CStr, c_char, c_void};
+use std::ptr;
+
+#[allow(non_camel_case_types)]
+pub type id = *mut Object;
+
+pub type AXUIElementRef = *const c_void;
+pub const AX_ERROR_SUCCESS: i32 = 0;
+
+#[link(name = "ApplicationServices", kind = "framework")]
+unsafe extern "C" {
+    pub fn AXUIElementCreateSystemWide() -> AXUIElementRef;
+    pub fn AXUIElementCreateApplication(pid: i32) -> AXUIElementRef;
+    pub fn AXUIElementCopyAttributeValue(
+        element: AXUIElementRef,
+        attribute: CFStringRef,
+        value: *mut CFTypeRef,
+    ) -> i32;
+    pub fn AXUIElementIsAttributeSettable(
+        element: AXUIElementRef,
+        attribute: CFStringRef,
+        settable: *mut u8,
+    ) -> i32;
+}
+
+/// Value of an accessibility attribute, owned so it's released when dropped
+pub unsafe fn copy_attribute(element: AXUIElementRef, attribute: &str) -> Option<CFType> {
+    let attribute = CFString::new(attribute);
+    let mut value: CFTypeRef = ptr::null();
+    let error = unsafe {
+        AXUIElementCopyAttributeValue(element, attribute.as_concrete_TypeRef(), &mut value)
+    };
+    (error == AX_ERROR_SUCCESS && !value.is_null())
+        .then(|| unsafe { CFType::wrap_under_create_rule(value) })
+}
+
+/// Bundle identifier (e.g. com.google.Chrome) of a running app, which unlike
+/// the display name is stable and unique
+pub fn bundle_id(process_id: u64) -> Option<String> {
+    autoreleasepool(|| unsafe {
+        let app: id = msg_send![
+            class!(NSRunningApplication),
+            runningApplicationWithProcessIdentifier: process_id as i32
+        ];
+        if app.is_null() {
+            return None;
+        }
+
+        let bundle_id: id = msg_send![app, bundleIdentifier];
+        if bundle_id.is_null() {
+            return None;
+        }
+
+        let utf8: *const c_char = msg_send![bundle_id, UTF8String];
+        if utf8.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(utf8).to_string_lossy().into_owned())
+    })
+}
+
+/// Process id of the app that has the menu bar, which is set even when that
+/// app has no window open
+pub fn frontmost_process_id() -> Option<u64> {
+    autoreleasepool(|| unsafe {
+        let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let app: id = msg_send![workspace, frontmostApplication];
+        if app.is_null() {
+            return None;
+        }
+        let process_id: i32 = msg_send![app, processIdentifier];
+        Some(process_id as u64)
+    })
+}
@@ -1,29 +1,168 @@
 use active_win_pos_rs::ActiveWindow;
+use clap::Parser;
+use ito_ipc::print_json_line;
 use serde_json::json;
+use std::time::Duration;
+
+mod browser;
+mod focus;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "windows")]
+mod uia;
+mod watch;
+mod window_list;
+mod window_state;
+
+#[derive(Parser)]
+#[command(name = "active-application")]
+#[command(about = "Reports the frontmost application and window")]
+#[command(version = ito_ipc::version_string!())]
+struct Args {
+    #[arg(
+        long,
+        help = "Keep running and print a line each time the active window changes"
+    )]
+    watch: bool,
+
+    #[arg(
+        long,
+        default_value_t = 250,
+        help = "How often --watch polls where no OS notification covers a change (milliseconds)"
+    )]
+    interval: u64,
+
+    #[arg(
+        long,
+        help = "Include the active tab's URL when the window is a known browser (needs extra permissions)"
+    )]
+    with_url: bool,
+
+    #[arg(
+        long,
+        help = "Include the role of the focused UI element and whether it's editable (needs Accessibility access)"
+    )]
+    with_focus: bool,
+
+    #[arg(
+        long,
+        help = "Print every visible top-level window, frontmost first, instead of only the active one"
+    )]
+    list_windows: bool,
+}
+
+/// Optional extras, each costing extra permissions or time
+#[derive(Clone, Copy, Default)]
+pub struct Extras {
+    pub with_url: bool,
+    pub with_focus: bool,
+}
 
 fn main() {
-    match active_win_pos_rs::get_active_window() {
-        Ok(active_window) => output_result(active_window),
-        Err(e) => {
-            eprintln!("{}", json!({ "error": e }));
-            std::process::exit(1);
+    ito_ipc::logging::init("active-application");
+    let args = Args::parse();
+    let extras = Extras {
+        with_url: args.with_url,
+        with_focus: args.with_focus,
+    };
+
+    if args.list_windows {
+        match window_list::visible_windows() {
+            Some(windows) => print_json_line(&json!(windows)),
+            None => fail("unsupported", "Can't list windows on this platform"),
         }
+        return;
+    }
+
+    if args.watch {
+        watch::run(Duration::from_millis(args.interval.max(1)), extras);
+        return;
+    }
+
+    match active_win_pos_rs::get_active_window() {
+        Ok(active_window) => output_result(active_window, extras),
+        Err(()) if nothing_focused() => fail("no-active-window", "No window has focus"),
+        Err(()) => fail("lookup-failed", "Couldn't read the active window"),
     }
 }
 
-fn output_result(active_window: ActiveWindow) {
-    let event_json = json!({
+// Failures go to stdout like results, so the host reads them the same way,
+// tagged with a kind to branch on
+fn fail(kind: &str, error: &str) -> ! {
+    print_json_line(&json!({ "error": error, "kind": kind }));
+    std::process::exit(1);
+}
+
+// active-win-pos-rs fails without saying why, so ask the OS whether anything
+// has focus at all
+#[cfg(target_os = "windows")]
+fn nothing_focused() -> bool {
+    use windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+
+    unsafe { GetForegroundWindow() }.is_invalid()
+}
+
+// Nothing has focus when the frontmost app has no window on screen, e.g.
+// Finder with just the desktop. If it does have one, the lookup itself failed
+#[cfg(target_os = "macos")]
+fn nothing_focused() -> bool {
+    let Some(process_id) = macos::frontmost_process_id() else {
+        return true;
+    };
+    window_list::visible_windows()
+        .is_some_and(|windows| !windows.iter().any(|window| window.process_id == process_id))
+}
+
+// A missing X connection looks the same as no active window here, so every
+// failure counts as a real one
+#[cfg(target_os = "linux")]
+fn nothing_focused() -> bool {
+    false
+}
+
+fn output_result(active_window: ActiveWindow, extras: Extras) {
+    let state = window_state::window_state(&active_window);
+    let mut event_json = json!({
         "title": active_window.title,
         "appName": active_window.app_name,
         "windowId": active_window.window_id,
         "processId": active_window.process_id,
+        // Stable identifiers for keying per-app behavior, since display names
+        // collide (e.g. several "Electron" apps)
+        "bundleId": bundle_id(active_window.process_id),
+        "execPath": active_window.process_path,
         "position": {
             "x": active_window.position.x,
             "y": active_window.position.y,
             "width": active_window.position.width,
             "height": active_window.position.height,
         },
+        "isFullscreen": state.is_fullscreen,
+        "isMinimized": state.is_minimized,
     });
 
-    println!("{}", event_json);
+    // Only present when it could be read
+    if extras.with_url
+        && let Some(url) = browser::active_tab_url(&active_window)
+    {
+        event_json["url"] = json!(url);
+    }
+    if extras.with_focus
+        && let Some(element) = focus::focused_element()
+    {
+        event_json["focusedElement"] = json!(element);
+    }
+
+    print_json_line(&event_json);
+}
+
+#[cfg(target_os = "macos")]
+fn bundle_id(process_id: u64) -> Option<String> {
+    macos::bundle_id(process_id)
+}
+
+// Bundle identifiers only exist on macOS
+#[cfg(not(target_os = "macos"))]
+fn bundle_id(_process_id: u64) -> Option<String> {
+    None
 }
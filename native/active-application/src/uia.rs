@@ -0,0 +1,15 @@
+//! UI Automation client shared by the Windows readers.
+
+use windows::Win32::System::Com::{
+    CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED, CoCreateInstance, CoInitializeEx,
+};
+use windows::Win32::UI::Accessibility::{CUIAutomation, IUIAutomation};
+
+/// Initializes COM on the calling thread and creates the automation client
+pub fn automation() -> Option<IUIAutomation> {
+    unsafe {
+        // Already initialized is fine, so the result is ignored
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER).ok()
+    }
+}
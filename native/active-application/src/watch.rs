@@ -0,0 +1,153 @@
+//! `--watch` mode: stay resident and print the active window each time it
+//! changes. OS focus notifications trigger an immediate check where available,
+//! polling covers everything else.
+
+use crate::{Extras, output_result};
+use active_win_pos_rs::ActiveWindow;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+// What identifies "the same window" for de-duplication. The title is part of
+// it so switching tabs inside one window is still reported
+#[derive(PartialEq)]
+struct WindowKey {
+    process_id: u64,
+    window_id: String,
+    title: String,
+}
+
+impl WindowKey {
+    fn new(window: &ActiveWindow) -> Self {
+        WindowKey {
+            process_id: window.process_id,
+            window_id: window.window_id.clone(),
+            title: window.title.clone(),
+        }
+    }
+}
+
+// Checks run from the poll loop and from OS callbacks, so the last emitted
+// window is shared
+static LAST_WINDOW: Mutex<Option<WindowKey>> = Mutex::new(None);
+static EXTRAS: OnceLock<Extras> = OnceLock::new();
+
+/// Print the active window if it differs from the last one printed
+fn check_active_window() {
+    // Nothing is focused (e.g. mid-switch), wait for the next change
+    let Ok(window) = active_win_pos_rs::get_active_window() else {
+        return;
+    };
+
+    let key = WindowKey::new(&window);
+    let mut last = LAST_WINDOW.lock().unwrap_or_else(|e| e.into_inner());
+    if last.as_ref() == Some(&key) {
+        return;
+    }
+    *last = Some(key);
+    output_result(window, EXTRAS.get().copied().unwrap_or_default());
+}
+
+pub fn run(interval: Duration, extras: Extras) {
+    let _ = EXTRAS.set(extras);
+    watch_for_changes(interval);
+}
+
+fn poll_loop(interval: Duration) -> ! {
+    loop {
+        check_active_window();
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn watch_for_changes(interval: Duration) {
+    // No portable focus notification across X11 and Wayland compositors
+    poll_loop(interval)
+}
+
+#[cfg(target_os = "windows")]
+fn watch_for_changes(interval: Duration) {
+    use windows::Win32::Foundation::{HMODULE, HWND};
+    use windows::Win32::UI::Accessibility::{HWINEVENTHOOK, SetWinEventHook, UnhookWinEvent};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, EVENT_SYSTEM_FOREGROUND, GetMessageW, MSG, TranslateMessage,
+        WINEVENT_OUTOFCONTEXT, WINEVENT_SKIPOWNPROCESS,
+    };
+
+    unsafe extern "system" fn on_foreground_change(
+        _hook: HWINEVENTHOOK,
+        _event: u32,
+        _hwnd: HWND,
+        _id_object: i32,
+        _id_child: i32,
+        _event_thread: u32,
+        _event_time: u32,
+    ) {
+        check_active_window();
+    }
+
+    check_active_window();
+
+    unsafe {
+        // Out-of-context hooks are delivered through this thread's message loop
+        let hook = SetWinEventHook(
+            EVENT_SYSTEM_FOREGROUND,
+            EVENT_SYSTEM_FOREGROUND,
+            HMODULE::default(),
+            Some(on_foreground_change),
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT | WINEVENT_SKIPOWNPROCESS,
+        );
+        if hook.is_invalid() {
+            log::warn!("Failed to install foreground hook, falling back to polling");
+            poll_loop(interval);
+        }
+
+        let mut msg: MSG = std::mem::zeroed();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).0 > 0 {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+
+        UnhookWinEvent(hook);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn watch_for_changes(interval: Duration) {
+    use crate::macos::id;
+    use block::ConcreteBlock;
+    use core_foundation::runloop::CFRunLoopRun;
+    use objc::runtime::Object;
+    use objc::{class, msg_send, sel, sel_impl};
+    use std::ptr;
+
+    #[link(name = "AppKit", kind = "framework")]
+    unsafe extern "C" {
+        static NSWorkspaceDidActivateApplicationNotification: id;
+    }
+
+    check_active_window();
+
+    // Activation notifications only cover switching apps, so keep polling for
+    // window and title changes within the frontmost app
+    thread::spawn(move || poll_loop(interval));
+
+    unsafe {
+        let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let center: id = msg_send![workspace, notificationCenter];
+        let block = ConcreteBlock::new(|_notification: id| check_active_window()).copy();
+        let _observer: id = msg_send![
+            center,
+            addObserverForName: NSWorkspaceDidActivateApplicationNotification
+            object: ptr::null_mut::<Object>()
+            queue: ptr::null_mut::<Object>()
+            usingBlock: &*block
+        ];
+
+        // Notifications are delivered on the main thread's run loop
+        CFRunLoopRun();
+    }
+}
@@ -0,0 +1,203 @@
+//! Every visible top-level window, behind `--list-windows`, for a window
+//! picker driven by the same binary. Windows are listed frontmost first.
+
+use serde::Serialize;
+
+// Never built on Linux, where windows can't be listed yet
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowInfo {
+    pub app_name: String,
+    /// Empty on macOS without Screen Recording access
+    pub title: String,
+    /// Same format as `windowId` in the active window output
+    pub window_id: String,
+    pub process_id: u64,
+    pub position: Position,
+}
+
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+#[derive(Serialize)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[cfg(target_os = "macos")]
+pub fn visible_windows() -> Option<Vec<WindowInfo>> {
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+    use core_foundation::number::CFNumber;
+    use core_foundation::string::CFString;
+    use core_graphics::geometry::CGRect;
+    use core_graphics::window::{
+        copy_window_info, kCGNullWindowID, kCGWindowListExcludeDesktopElements,
+        kCGWindowListOptionOnScreenOnly,
+    };
+
+    // The window server returns windows front to back
+    let windows = copy_window_info(
+        kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
+        kCGNullWindowID,
+    )?;
+
+    let list = windows
+        .get_all_values()
+        .into_iter()
+        .filter_map(|entry| {
+            let info: CFDictionary<CFString, CFType> =
+                unsafe { CFDictionary::wrap_under_get_rule(entry as CFDictionaryRef) };
+            let value = |key: &str| info.find(&CFString::new(key)).map(|value| value.clone());
+            let number = |key: &str| {
+                value(key)
+                    .and_then(|value| value.downcast::<CFNumber>())
+                    .and_then(|number| number.to_i64())
+            };
+            let string = |key: &str| {
+                value(key)
+                    .and_then(|value| value.downcast::<CFString>())
+                    .map(|text| text.to_string())
+                    .unwrap_or_default()
+            };
+
+            // App windows sit on layer 0; the menu bar, Dock and overlays
+            // are above it
+            if number("kCGWindowLayer") != Some(0) {
+                return None;
+            }
+            let bounds = value("kCGWindowBounds")
+                .and_then(|value| value.downcast::<CFDictionary>())
+                .and_then(|bounds| CGRect::from_dict_representation(&bounds))?;
+
+            Some(WindowInfo {
+                app_name: string("kCGWindowOwnerName"),
+                title: string("kCGWindowName"),
+                window_id: number("kCGWindowNumber")?.to_string(),
+                process_id: number("kCGWindowOwnerPID")? as u64,
+                position: Position {
+                    x: bounds.origin.x,
+                    y: bounds.origin.y,
+                    width: bounds.size.width,
+                    height: bounds.size.height,
+                },
+            })
+        })
+        .collect();
+    Some(list)
+}
+
+#[cfg(target_os = "windows")]
+pub fn visible_windows() -> Option<Vec<WindowInfo>> {
+    use std::path::Path;
+    use windows::Win32::Foundation::{BOOL, CloseHandle, HWND, LPARAM, RECT};
+    use windows::Win32::Graphics::Dwm::{DWMWA_CLOAKED, DwmGetWindowAttribute};
+    use windows::Win32::System::Threading::{
+        OpenProcess, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+        QueryFullProcessImageNameW,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        EnumWindows, GW_OWNER, GWL_EXSTYLE, GetWindow, GetWindowLongW, GetWindowRect,
+        GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible,
+        WS_EX_TOOLWINDOW,
+    };
+    use windows::core::PWSTR;
+
+    unsafe extern "system" fn collect(hwnd: HWND, handles: LPARAM) -> BOOL {
+        unsafe { (*(handles.0 as *mut Vec<HWND>)).push(hwnd) };
+        true.into()
+    }
+
+    // Executable name without extension, e.g. "Code" for Code.exe
+    fn process_name(process_id: u32) -> Option<String> {
+        unsafe {
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id).ok()?;
+            let mut path = [0u16; 1024];
+            let mut length = path.len() as u32;
+            let queried = QueryFullProcessImageNameW(
+                process,
+                PROCESS_NAME_WIN32,
+                PWSTR(path.as_mut_ptr()),
+                &mut length,
+            )
+            .is_ok();
+            let _ = CloseHandle(process);
+            let path = String::from_utf16_lossy(&path[..length as usize]);
+            queried
+                .then(|| Path::new(&path).file_stem())
+                .flatten()
+                .map(|name| name.to_string_lossy().into_owned())
+        }
+    }
+
+    // Enumerated top to bottom in z-order
+    let mut handles: Vec<HWND> = Vec::new();
+    let handles_param = LPARAM(&mut handles as *mut Vec<HWND> as isize);
+    if unsafe { EnumWindows(Some(collect), handles_param) }.is_err() {
+        return None;
+    }
+
+    let list = handles
+        .into_iter()
+        .filter_map(|hwnd| unsafe {
+            // Skip what the taskbar skips: hidden, tool and owned windows
+            // (dialogs), and ones DWM cloaks, like windows on other virtual
+            // desktops or suspended UWP apps
+            if !IsWindowVisible(hwnd).as_bool()
+                || GetWindow(hwnd, GW_OWNER).is_ok()
+                || GetWindowLongW(hwnd, GWL_EXSTYLE) as u32 & WS_EX_TOOLWINDOW.0 != 0
+            {
+                return None;
+            }
+            let mut cloaked: u32 = 0;
+            if DwmGetWindowAttribute(
+                hwnd,
+                DWMWA_CLOAKED,
+                &mut cloaked as *mut u32 as *mut _,
+                std::mem::size_of::<u32>() as u32,
+            )
+            .is_ok()
+                && cloaked != 0
+            {
+                return None;
+            }
+            // Untitled top-level windows are almost always invisible helpers
+            let length = GetWindowTextLengthW(hwnd);
+            if length <= 0 {
+                return None;
+            }
+            let mut title = vec![0u16; length as usize + 1];
+            let copied = GetWindowTextW(hwnd, &mut title);
+
+            let mut rect: RECT = std::mem::zeroed();
+            if GetWindowRect(hwnd, &mut rect).is_err() {
+                return None;
+            }
+            let mut process_id = 0u32;
+            GetWindowThreadProcessId(hwnd, Some(&mut process_id));
+
+            Some(WindowInfo {
+                app_name: process_name(process_id).unwrap_or_default(),
+                title: String::from_utf16_lossy(&title[..copied.max(0) as usize]),
+                window_id: format!("HWND({})", hwnd.0 as isize),
+                process_id: process_id as u64,
+                position: Position {
+                    x: rect.left as f64,
+                    y: rect.top as f64,
+                    width: (rect.right - rect.left) as f64,
+                    height: (rect.bottom - rect.top) as f64,
+                },
+            })
+        })
+        .collect();
+    Some(list)
+}
+
+// Needs an X11 connection, and Wayland compositors don't expose other
+// clients' windows at all
+#[cfg(target_os = "linux")]
+pub fn visible_windows() -> Option<Vec<WindowInfo>> {
+    None
+}
@@ -0,0 +1,135 @@
+//! Fullscreen and minimized state of the active window, for placing overlays
+//! without covering fullscreen apps.
+
+use active_win_pos_rs::ActiveWindow;
+
+/// None where the platform can't tell
+pub struct WindowState {
+    pub is_fullscreen: Option<bool>,
+    pub is_minimized: Option<bool>,
+}
+
+// Window rects can be off by a pixel from the display they fill
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn covers(window: (f64, f64, f64, f64), screen: (f64, f64, f64, f64)) -> bool {
+    let (x, y, width, height) = window;
+    let (screen_x, screen_y, screen_width, screen_height) = screen;
+    x <= screen_x + 1.0
+        && y <= screen_y + 1.0
+        && x + width >= screen_x + screen_width - 1.0
+        && y + height >= screen_y + screen_height - 1.0
+}
+
+// The app's own fullscreen flag for its focused window, None without
+// Accessibility access
+#[cfg(target_os = "macos")]
+fn ax_fullscreen(process_id: u64) -> Option<bool> {
+    use crate::macos::{AXUIElementCreateApplication, copy_attribute};
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::boolean::CFBoolean;
+
+    unsafe {
+        let app = CFType::wrap_under_create_rule(AXUIElementCreateApplication(process_id as i32));
+        let window = copy_attribute(app.as_CFTypeRef(), "AXFocusedWindow")?;
+        copy_attribute(window.as_CFTypeRef(), "AXFullScreen")?
+            .downcast::<CFBoolean>()
+            .map(bool::from)
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn window_state(window: &ActiveWindow) -> WindowState {
+    use core_graphics::display::CGDisplay;
+
+    // Without Accessibility, fall back to the rect: a fullscreen window gets
+    // its own space and fills its whole display, menu bar area included,
+    // which a zoomed window never does. A borderless window sized to the
+    // display looks the same, so this can report false positives
+    let is_fullscreen = ax_fullscreen(window.process_id).or_else(|| {
+        let position = &window.position;
+        let window_rect = (position.x, position.y, position.width, position.height);
+        CGDisplay::active_displays().ok().map(|displays| {
+            displays.into_iter().any(|id| {
+                let bounds = CGDisplay::new(id).bounds();
+                covers(
+                    window_rect,
+                    (
+                        bounds.origin.x,
+                        bounds.origin.y,
+                        bounds.size.width,
+                        bounds.size.height,
+                    ),
+                )
+            })
+        })
+    });
+
+    WindowState {
+        is_fullscreen,
+        // The active window comes from the on-screen window list, so it can't
+        // be minimized
+        is_minimized: Some(false),
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub fn window_state(_window: &ActiveWindow) -> WindowState {
+    use windows::Win32::Foundation::RECT;
+    use windows::Win32::Graphics::Gdi::{
+        GetMonitorInfoW, MONITOR_DEFAULTTONEAREST, MONITORINFO, MonitorFromWindow,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowRect, IsIconic};
+
+    fn rect_tuple(rect: &RECT) -> (f64, f64, f64, f64) {
+        (
+            rect.left as f64,
+            rect.top as f64,
+            (rect.right - rect.left) as f64,
+            (rect.bottom - rect.top) as f64,
+        )
+    }
+
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd.is_invalid() {
+            return WindowState {
+                is_fullscreen: None,
+                is_minimized: None,
+            };
+        }
+
+        let is_minimized = IsIconic(hwnd).as_bool();
+
+        // Compare against the full monitor rect rather than the work area: a
+        // maximized window fills the work area, only a fullscreen one also
+        // covers the taskbar
+        let mut window_rect: RECT = std::mem::zeroed();
+        let mut monitor_info: MONITORINFO = std::mem::zeroed();
+        monitor_info.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
+        let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST);
+        let is_fullscreen = (GetWindowRect(hwnd, &mut window_rect).is_ok()
+            && GetMonitorInfoW(monitor, &mut monitor_info).as_bool())
+        .then(|| {
+            !is_minimized
+                && covers(
+                    rect_tuple(&window_rect),
+                    rect_tuple(&monitor_info.rcMonitor),
+                )
+        });
+
+        WindowState {
+            is_fullscreen,
+            is_minimized: Some(is_minimized),
+        }
+    }
+}
+
+// Needs window manager hints (_NET_WM_STATE) that differ between X11 and
+// Wayland compositors
+#[cfg(target_os = "linux")]
+pub fn window_state(_window: &ActiveWindow) -> WindowState {
+    WindowState {
+        is_fullscreen: None,
+        is_minimized: None,
+    }
+}
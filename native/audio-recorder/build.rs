@@ -1,4 +1,6 @@
 fn main() {
+    ito_ipc::build_info::emit_git_sha();
+
     #[cfg(target_os = "windows")]
     {
         let mut res = tauri_winres::WindowsResource::new();
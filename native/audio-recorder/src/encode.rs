@@ -0,0 +1,120 @@
+//! Compression of the audio sent to the host.
+//!
+//! pcm16 chunks are the default. With `encoding: "opus"` the output is cut
+//! into fixed-length frames, each encoded to one Opus packet and sent as its
+//! own `MSG_TYPE_OPUS` message, so a host relaying audio to a remote service
+//! can forward packets without re-encoding.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioEncoding {
+    /// Little-endian i16 samples in `MSG_TYPE_AUDIO` messages
+    #[default]
+    Pcm16,
+    /// One Opus packet per `MSG_TYPE_OPUS` message
+    Opus,
+}
+
+impl AudioEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "pcm16" => Some(AudioEncoding::Pcm16),
+            "opus" => Some(AudioEncoding::Opus),
+            _ => None,
+        }
+    }
+}
+
+pub const OPUS_FRAME_MS: u32 = 20;
+// Comfortably transparent for speech at 16kHz, about a tenth of pcm16
+pub const OPUS_BITRATE: i32 = 24_000;
+// Upper bound the Opus docs recommend for a single packet
+const MAX_PACKET_BYTES: usize = 4000;
+
+/// Buffers mono or interleaved stereo samples into whole frames and encodes
+/// each to a packet
+pub struct OpusFramer {
+    encoder: opus::Encoder,
+    frame_samples: usize,
+    pending: Vec<f32>,
+}
+
+impl OpusFramer {
+    pub fn new(sample_rate: u32, channels: usize) -> Result<Self, opus::Error> {
+        let layout = if channels > 1 {
+            opus::Channels::Stereo
+        } else {
+            opus::Channels::Mono
+        };
+        let mut encoder = opus::Encoder::new(sample_rate, layout, opus::Application::Voip)?;
+        encoder.set_bitrate(opus::Bitrate::Bits(OPUS_BITRATE))?;
+        Ok(OpusFramer {
+            encoder,
+            frame_samples: (sample_rate * OPUS_FRAME_MS / 1000) as usize * channels.max(1),
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn frame_samples(&self) -> usize {
+        self.frame_samples
+    }
+
+    /// Encode every frame completed by `data`, keeping the remainder
+    pub fn push(&mut self, data: &[f32]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(data);
+        let mut packets = Vec::new();
+        while self.pending.len() >= self.frame_samples {
+            let frame: Vec<f32> = self.pending.drain(..self.frame_samples).collect();
+            match self.encoder.encode_vec_float(&frame, MAX_PACKET_BYTES) {
+                Ok(packet) => packets.push(packet),
+                Err(e) => log::error!("Opus encoding failed: {}", e),
+            }
+        }
+        packets
+    }
+
+    /// Encode what's left, zero-padded to a whole frame
+    pub fn flush(&mut self) -> Vec<Vec<u8>> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let padding = self.frame_samples - self.pending.len();
+        self.push(&vec![0.0; padding])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opus_framer_emits_whole_frames() {
+        let mut framer = OpusFramer::new(16_000, 1).unwrap();
+        assert_eq!(framer.frame_samples(), 320);
+
+        // 25ms twice is two 20ms frames with 10ms left over
+        assert_eq!(framer.push(&[0.1; 400]).len(), 1);
+        assert_eq!(framer.push(&[0.1; 400]).len(), 1);
+        let last = framer.flush();
+        assert_eq!(last.len(), 1);
+        assert!(!last[0].is_empty());
+        assert!(framer.flush().is_empty());
+
+        // Stereo frames hold both channels' samples
+        let mut framer = OpusFramer::new(16_000, 2).unwrap();
+        assert_eq!(framer.frame_samples(), 640);
+        assert_eq!(framer.push(&[0.1; 640]).len(), 1);
+    }
+
+    #[test]
+    fn test_encoding_from_name() {
+        assert_eq!(AudioEncoding::from_name("Opus"), Some(AudioEncoding::Opus));
+        assert_eq!(AudioEncoding::from_name("flac"), None);
+        assert_eq!(
+            serde_json::to_value(AudioEncoding::default()).unwrap(),
+            "pcm16"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Input gain applied to output audio before it is quantized to i16.
+//!
+//! A fixed linear multiplier can be combined with automatic gain control,
+//! which follows the block peak towards roughly -3dBFS. Gain drops quickly
+//! when a block would clip (attack) and rises slowly during quieter passages
+//! (release) so the level doesn't audibly pump.
+
+// -3dBFS
+const AUTO_TARGET_PEAK: f32 = 0.708;
+const AUTO_MAX_GAIN: f32 = 20.0;
+const AUTO_MIN_GAIN: f32 = 0.1;
+// Blocks quieter than this are treated as silence and never raise the gain,
+// otherwise background noise gets amplified between words
+const AUTO_NOISE_PEAK: f32 = 0.01;
+const ATTACK: f32 = 0.5;
+const RELEASE: f32 = 0.02;
+
+pub struct GainStage {
+    gain: f32,
+    auto: bool,
+    last_peak: f32,
+}
+
+impl GainStage {
+    pub fn new(gain: f32, auto: bool) -> Self {
+        GainStage {
+            gain: if gain.is_finite() && gain > 0.0 {
+                gain
+            } else {
+                1.0
+            },
+            auto,
+            last_peak: 0.0,
+        }
+    }
+
+    /// True when processing would leave samples untouched
+    pub fn is_unity(&self) -> bool {
+        !self.auto && self.gain == 1.0
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Peak of the most recent block after gain and clamping
+    pub fn last_peak(&self) -> f32 {
+        self.last_peak
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.auto {
+            let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+            if peak > 0.0 {
+                let desired = (AUTO_TARGET_PEAK / peak).clamp(AUTO_MIN_GAIN, AUTO_MAX_GAIN);
+                if desired < self.gain {
+                    self.gain += (desired - self.gain) * ATTACK;
+                } else if peak >= AUTO_NOISE_PEAK {
+                    self.gain += (desired - self.gain) * RELEASE;
+                }
+            }
+        }
+
+        let mut out_peak = 0.0f32;
+        for s in samples.iter_mut() {
+            // Clamp here as well so nothing downstream can overflow i16
+            *s = (*s * self.gain).clamp(-1.0, 1.0);
+            out_peak = out_peak.max(s.abs());
+        }
+        self.last_peak = out_peak;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_gain_is_applied_and_clamped() {
+        let mut stage = GainStage::new(2.0, false);
+        let mut samples = vec![0.25, -0.25, 0.75, -0.75];
+        stage.process(&mut samples);
+        assert_eq!(samples, vec![0.5, -0.5, 1.0, -1.0]);
+        assert_eq!(stage.last_peak(), 1.0);
+    }
+
+    #[test]
+    fn test_invalid_gain_falls_back_to_unity() {
+        assert!(GainStage::new(0.0, false).is_unity());
+        assert!(GainStage::new(f32::NAN, false).is_unity());
+        assert!(!GainStage::new(1.0, true).is_unity());
+    }
+
+    #[test]
+    fn test_auto_gain_attacks_fast_and_releases_slowly() {
+        let mut stage = GainStage::new(1.0, true);
+
+        // Quiet speech slowly raises the gain
+        let mut quiet = vec![0.1; 160];
+        stage.process(&mut quiet);
+        let raised = stage.gain();
+        assert!(raised > 1.0 && raised < 1.5);
+
+        for _ in 0..500 {
+            stage.process(&mut vec![0.1; 160]);
+        }
+        assert!((stage.gain() - 7.08).abs() < 0.1);
+
+        // A loud block pulls it down by a large step immediately
+        let before = stage.gain();
+        stage.process(&mut vec![0.9; 160]);
+        assert!(stage.gain() < before * 0.6);
+    }
+
+    #[test]
+    fn test_auto_gain_ignores_silence() {
+        let mut stage = GainStage::new(1.0, true);
+        for _ in 0..100 {
+            stage.process(&mut vec![0.001; 160]);
+        }
+        assert_eq!(stage.gain(), 1.0);
+    }
+}
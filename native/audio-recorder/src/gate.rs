@@ -0,0 +1,107 @@
+//! Optional noise gate on the outgoing signal.
+//!
+//! In always-listening setups the transcriber otherwise gets a steady stream
+//! of room hiss. The gate follows the short-window RMS and silences the
+//! signal while it stays below the threshold. It opens within a millisecond
+//! of speech and holds open briefly after it drops, so word onsets and the
+//! gaps between syllables aren't chopped.
+
+// RMS window, as the time constant of a running mean of squares
+const WINDOW_MS: f32 = 10.0;
+const ATTACK_MS: f32 = 1.0;
+const HOLD_MS: f32 = 150.0;
+const RELEASE_MS: f32 = 50.0;
+
+pub struct NoiseGate {
+    threshold: f32,
+    window_coeff: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    hold_samples: u32,
+    mean_square: f32,
+    // Samples since the level was last above the threshold
+    since_loud: u32,
+    gain: f32,
+}
+
+// Per-sample smoothing factor for a one-pole filter with this time constant
+fn coeff(ms: f32, sample_rate: u32) -> f32 {
+    1.0 - (-1000.0 / (ms * sample_rate as f32)).exp()
+}
+
+impl NoiseGate {
+    /// `threshold_db` is in dBFS, e.g. -50
+    pub fn new(threshold_db: f32, sample_rate: u32) -> Self {
+        let hold_samples = (HOLD_MS * sample_rate as f32 / 1000.0) as u32;
+        NoiseGate {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            window_coeff: coeff(WINDOW_MS, sample_rate),
+            attack_coeff: coeff(ATTACK_MS, sample_rate),
+            release_coeff: coeff(RELEASE_MS, sample_rate),
+            hold_samples,
+            mean_square: 0.0,
+            // Starts closed
+            since_loud: hold_samples,
+            gain: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            self.mean_square += self.window_coeff * (*sample * *sample - self.mean_square);
+            if self.mean_square.sqrt() >= self.threshold {
+                self.since_loud = 0;
+            } else {
+                self.since_loud = self.since_loud.saturating_add(1);
+            }
+
+            let (target, coeff) = if self.since_loud < self.hold_samples {
+                (1.0, self.attack_coeff)
+            } else {
+                (0.0, self.release_coeff)
+            };
+            self.gain += coeff * (target - self.gain);
+            *sample *= self.gain;
+        }
+    }
+
+    /// Whether the gate is currently letting the signal through
+    pub fn is_open(&self) -> bool {
+        self.since_loud < self.hold_samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_gate_passes_burst_and_suppresses_floor() {
+        let mut gate = NoiseGate::new(-40.0, 16000);
+
+        // Half a second of hiss around -60dBFS stays gated
+        let mut floor: Vec<f32> = (0..8000)
+            .map(|i| if i % 2 == 0 { 0.001 } else { -0.001 })
+            .collect();
+        gate.process(&mut floor);
+        assert!(!gate.is_open());
+        assert!(rms(&floor) < 1e-5, "{}", rms(&floor));
+
+        // A loud burst goes through nearly untouched past the first few ms
+        let burst: Vec<f32> = (0..1600).map(|i| 0.5 * (i as f32 * 0.1).sin()).collect();
+        let mut gated = burst.clone();
+        gate.process(&mut gated);
+        assert!(gate.is_open());
+        assert!(rms(&gated[160..]) > 0.99 * rms(&burst[160..]));
+
+        // Held open briefly after the burst, then closes back down
+        let mut tail: Vec<f32> = floor.iter().map(|_| 0.001).collect();
+        gate.process(&mut tail);
+        assert!(!gate.is_open());
+        assert!(rms(&tail[6000..]) < 1e-4);
+    }
+}
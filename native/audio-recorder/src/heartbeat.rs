@@ -0,0 +1,180 @@
+//! Liveness pings while a recording is active.
+//!
+//! The stream callback stamps every block it delivers and a heartbeat thread
+//! periodically reports how many have arrived and how long ago the last one
+//! did. A device that went to sleep or a wedged driver otherwise looks exactly
+//! like a quiet room to the host.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use serde::Serialize;
+
+use crate::write_json_message;
+
+/// Blocks delivered by the stream callback since the recording started
+pub struct FrameStats {
+    epoch: Instant,
+    frames: AtomicU64,
+    // Blocks that didn't fit in the writer's queue
+    dropped: AtomicU64,
+    // Frames per callback, kept across recordings on the same stream
+    block_frames: AtomicU64,
+    // Milliseconds after `epoch` of the latest block, or of the last reset
+    last_frame_ms: AtomicU64,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        FrameStats {
+            epoch: Instant::now(),
+            frames: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            block_frames: AtomicU64::new(0),
+            last_frame_ms: AtomicU64::new(0),
+        }
+    }
+}
+
+impl FrameStats {
+    fn now_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    /// Called from the stream callback for each delivered block
+    pub fn record_frame(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Called for every callback, including between recordings
+    pub fn record_block(&self, frames: usize) {
+        self.block_frames.store(frames as u64, Ordering::Relaxed);
+    }
+
+    pub fn block_frames(&self) -> u64 {
+        self.block_frames.load(Ordering::Relaxed)
+    }
+
+    pub fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Start counting afresh for a new recording
+    pub fn reset(&self) {
+        self.frames.store(0, Ordering::Relaxed);
+        self.dropped.store(0, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Restart the stall clock without clearing the count, e.g. on resume,
+    /// so time spent paused isn't reported as a stall
+    pub fn touch(&self) {
+        self.last_frame_ms.store(self.now_ms(), Ordering::Relaxed);
+    }
+
+    pub fn heartbeat(&self, stall_after: Duration) -> HeartbeatMessage {
+        let last_frame_ms_ago = self
+            .now_ms()
+            .saturating_sub(self.last_frame_ms.load(Ordering::Relaxed));
+        HeartbeatMessage {
+            response_type: "heartbeat",
+            frames_processed: self.frames.load(Ordering::Relaxed),
+            last_frame_ms_ago,
+            stalled: last_frame_ms_ago >= stall_after.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct HeartbeatMessage {
+    #[serde(rename = "type")]
+    response_type: &'static str,
+    frames_processed: u64,
+    last_frame_ms_ago: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stalled: bool,
+}
+
+/// Emits a heartbeat every `interval` until dropped
+pub struct Heartbeat {
+    _stop_tx: Sender<()>,
+}
+
+impl Heartbeat {
+    pub fn spawn(
+        stats: Arc<FrameStats>,
+        paused: Arc<AtomicBool>,
+        stdout: Arc<Mutex<io::Stdout>>,
+        interval: Duration,
+        stall_after: Duration,
+    ) -> Self {
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+        thread::spawn(move || {
+            let mut was_stalled = false;
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                // A paused stream delivers nothing on purpose
+                if paused.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let message = stats.heartbeat(stall_after);
+                if message.stalled != was_stalled {
+                    was_stalled = message.stalled;
+                    if was_stalled {
+                        log::warn!(
+                            "No audio for {}ms, stream looks stalled",
+                            message.last_frame_ms_ago
+                        );
+                    } else {
+                        log::info!("Audio resumed after stall");
+                    }
+                }
+                write_json_message(&stdout, &message);
+            }
+        });
+        Heartbeat { _stop_tx: stop_tx }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_counts_frames_and_flags_stall() {
+        let stats = FrameStats::default();
+        stats.record_frame();
+        stats.record_frame();
+
+        let message = stats.heartbeat(Duration::from_secs(60));
+        assert_eq!(message.frames_processed, 2);
+        assert!(!message.stalled);
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "type": "heartbeat",
+                "frames_processed": 2,
+                "last_frame_ms_ago": message.last_frame_ms_ago,
+            })
+        );
+
+        thread::sleep(Duration::from_millis(20));
+        let message = stats.heartbeat(Duration::from_millis(10));
+        assert!(message.stalled);
+        assert!(message.last_frame_ms_ago >= 20);
+        assert_eq!(serde_json::to_value(&message).unwrap()["stalled"], true);
+
+        stats.reset();
+        let message = stats.heartbeat(Duration::from_secs(60));
+        assert_eq!(message.frames_processed, 0);
+        assert!(!message.stalled);
+    }
+}
@@ -0,0 +1,73 @@
+//! Optional high-pass filter on the captured mono signal.
+//!
+//! Some USB mics add a DC offset or low-frequency rumble that hurts
+//! transcription. A first-order filter with a cutoff well below speech
+//! removes it cheaply. State carries over between blocks so the output has no
+//! discontinuities at block edges.
+
+use std::f32::consts::TAU;
+
+#[derive(Clone)]
+pub struct HighPass {
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl HighPass {
+    /// None unless `cutoff_hz` is positive and below the Nyquist frequency
+    pub fn new(cutoff_hz: f32, sample_rate: u32) -> Option<Self> {
+        if !(cutoff_hz > 0.0 && cutoff_hz < sample_rate as f32 / 2.0) {
+            return None;
+        }
+        let rc = 1.0 / (TAU * cutoff_hz);
+        let dt = 1.0 / sample_rate as f32;
+        Some(HighPass {
+            alpha: rc / (rc + dt),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        })
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let input = *sample;
+            self.prev_out = self.alpha * (self.prev_out + input - self.prev_in);
+            self.prev_in = input;
+            *sample = self.prev_out;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highpass_removes_dc_offset() {
+        let mut filter = HighPass::new(80.0, 16000).unwrap();
+        // One second of a constant offset, in 10ms blocks
+        let mut last = Vec::new();
+        for _ in 0..100 {
+            let mut block = vec![0.5; 160];
+            filter.process(&mut block);
+            last = block;
+        }
+        let mean = last.iter().sum::<f32>() / last.len() as f32;
+        assert!(mean.abs() < 1e-3, "{}", mean);
+
+        // Splitting the input into blocks doesn't change the output
+        let signal: Vec<f32> = (0..1000).map(|i| 0.3 + (i as f32 * 0.05).sin()).collect();
+        let mut whole = signal.clone();
+        HighPass::new(80.0, 16000).unwrap().process(&mut whole);
+        let mut chunked = signal;
+        let mut filter = HighPass::new(80.0, 16000).unwrap();
+        for block in chunked.chunks_mut(37) {
+            filter.process(block);
+        }
+        assert_eq!(whole, chunked);
+
+        assert!(HighPass::new(0.0, 16000).is_none());
+        assert!(HighPass::new(8000.0, 16000).is_none());
+    }
+}
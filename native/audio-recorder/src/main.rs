@@ -1,26 +1,132 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, Write};
+use std::io;
+use std::ops::ControlFlow;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use cpal::{Sample, SampleFormat, StreamConfig};
+use cpal::{Sample, SampleFormat, StreamConfig, SupportedStreamConfig, SupportedStreamConfigRange};
 use dasp_sample::FromSample;
-use rubato::{FftFixedIn, Resampler};
+use ito_ipc::{
+    read_stdin_commands, write_framed_json, write_framed_message, MSG_TYPE_AUDIO, MSG_TYPE_OPUS,
+};
 
+mod encode;
+mod gain;
+mod gate;
+mod heartbeat;
+mod highpass;
+mod playback;
+mod preroll;
+mod resample;
+mod vad;
+mod wav;
+use encode::{AudioEncoding, OpusFramer, OPUS_BITRATE, OPUS_FRAME_MS};
+use gain::GainStage;
+use gate::NoiseGate;
+use heartbeat::{FrameStats, Heartbeat};
+use highpass::HighPass;
+use playback::{Clip, PlaybackDone};
+use preroll::{CaptureRoute, DropPolicy, PreRollBuffer};
+use resample::{MonoResampler, ResampleQuality, CHUNK_SIZE_DEFAULT, CHUNK_SIZE_MAX};
+use vad::{VadEvent, VoiceActivityDetector};
+use wav::WavWriter;
+
+// Commands are parsed one at a time off stdin, so the size of Start's
+// options doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "command")]
 enum Command {
     #[serde(rename = "start")]
-    Start { device_name: Option<String> },
+    Start {
+        device_name: Option<String>,
+        vad: Option<bool>,
+        vad_hangover_ms: Option<u32>,
+        loopback: Option<bool>,
+        record_to: Option<String>,
+        downmix: Option<String>,
+        preroll_ms: Option<u32>,
+        gain: Option<f32>,
+        auto_gain: Option<bool>,
+        timestamps: Option<bool>,
+        bit_depth: Option<u16>,
+        heartbeat_ms: Option<u32>,
+        stall_ms: Option<u32>,
+        drop_policy: Option<String>,
+        // Audio API to capture through, e.g. "ASIO" or "JACK", by cpal's
+        // host name. Defaults to WASAPI on Windows and the platform default
+        // elsewhere
+        host_api: Option<String>,
+        // "fast", "balanced" (default) or "high"
+        resample_quality: Option<String>,
+        // Input samples per resampler call, 1024 by default. Smaller cuts
+        // latency, larger is cheaper
+        resampler_chunk_size: Option<usize>,
+        // Cutoff of a high-pass filter removing DC offset and rumble, off by
+        // default
+        highpass_hz: Option<f32>,
+        // "pcm16" (default) or "opus"
+        encoding: Option<String>,
+        // Stop on our own after this much audio, guarding against a
+        // recording that is never stopped
+        max_duration_ms: Option<u64>,
+        // Silence the output while its level stays below this many dBFS,
+        // off by default
+        noise_gate_db: Option<f32>,
+        // 2 keeps the first two input channels as interleaved stereo instead
+        // of downmixing, for processing that tells speakers apart. Default 1
+        channels_out: Option<u8>,
+    },
     #[serde(rename = "stop")]
-    Stop,
+    Stop {
+        // false discards whatever is still queued and returns without the
+        // final flush; true (default) drains everything first
+        drain: Option<bool>,
+    },
+    #[serde(rename = "pause")]
+    Pause,
+    #[serde(rename = "resume")]
+    Resume,
+    #[serde(rename = "set-muted")]
+    SetMuted { muted: bool },
     #[serde(rename = "list-devices")]
-    ListDevices,
+    ListDevices { host_api: Option<String> },
+    #[serde(rename = "list-devices-detailed")]
+    ListDevicesDetailed { host_api: Option<String> },
     #[serde(rename = "get-device-config")]
     GetDeviceConfig { device_name: Option<String> },
+    #[serde(rename = "get-state")]
+    GetState,
+    // Writes the last few seconds of output audio to a WAV file, for
+    // attaching to a problem report
+    #[serde(rename = "dump-debug-audio")]
+    DumpDebugAudio { path: String },
+    // Opens the device ahead of a start with default options, so the first
+    // recording doesn't pay for opening it cold
+    #[serde(rename = "warmup")]
+    Warmup { device_name: Option<String> },
+    // Plays a WAV file at `path`, or base64 pcm16 mono at `sample_rate`
+    // (default 16kHz) given as `pcm`, on an output device. Replaces any
+    // playback still running
+    #[serde(rename = "play")]
+    Play {
+        path: Option<String>,
+        pcm: Option<String>,
+        sample_rate: Option<u32>,
+        device_name: Option<String>,
+        host_api: Option<String>,
+    },
+    #[serde(rename = "stop-playback")]
+    StopPlayback,
+    #[serde(rename = "list-output-devices")]
+    ListOutputDevices { host_api: Option<String> },
+    #[serde(rename = "version")]
+    Version,
 }
 #[derive(Serialize)]
 struct DeviceList {
@@ -30,45 +136,213 @@ struct DeviceList {
 }
 
 #[derive(Serialize)]
+struct DeviceInfo {
+    name: String,
+    default_sample_rate: Option<u32>,
+    min_sample_rate: Option<u32>,
+    max_sample_rate: Option<u32>,
+    max_channels: u16,
+    is_default: bool,
+}
+
+#[derive(Serialize)]
+struct DetailedDeviceList {
+    #[serde(rename = "type")]
+    response_type: String,
+    devices: Vec<DeviceInfo>,
+}
+
+#[derive(Serialize, Clone)]
 struct AudioConfig {
     #[serde(rename = "type")]
     response_type: String,
     input_sample_rate: u32,
     output_sample_rate: u32,
     channels: u8,
+    // Duration of one device buffer, from a fixed buffer size or measured
+    // from the stream's callbacks. Until a callback has been seen this is
+    // left out, and a second audio-config follows once it's known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_latency_ms: Option<f64>,
+    // cpal host the stream was opened with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resample_quality: Option<ResampleQuality>,
+    // Chunk size the resampler ended up with, which is half the requested
+    // one if that couldn't be built. Left out when nothing is resampled or
+    // resampling is linear
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resampler_chunk_size: Option<usize>,
+    encoding: AudioEncoding,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    opus_frame_ms: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    opus_bitrate: Option<i32>,
+    // Whether the device delivers the output rate itself, so nothing is
+    // resampled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    native_16k: Option<bool>,
+}
+
+// Answer to get-state, so a restarted host can catch up with the recorder
+#[derive(Serialize)]
+struct RecorderState {
+    #[serde(rename = "type")]
+    response_type: &'static str,
+    recording: bool,
+    paused: bool,
+    muted: bool,
+    // Device and rate of the open stream, None when no stream is open. The
+    // stream stays open between recordings when pre-roll is on
+    device: Option<String>,
+    input_sample_rate: Option<u32>,
+    output_sample_rate: u32,
 }
 
-const MSG_TYPE_JSON: u8 = 1;
-const MSG_TYPE_AUDIO: u8 = 2;
+// One callback's worth of frames in milliseconds, to 0.1ms
+fn frames_to_ms(frames: u64, sample_rate: u32) -> Option<f64> {
+    if frames == 0 || sample_rate == 0 {
+        return None;
+    }
+    Some((frames * 10_000 / sample_rate as u64) as f64 / 10.0)
+}
 
-fn write_framed_message(writer: &mut impl Write, msg_type: u8, data: &[u8]) -> io::Result<()> {
-    let len = data.len() as u32;
-    writer.write_all(&[msg_type])?;
-    writer.write_all(&len.to_le_bytes())?;
-    writer.write_all(data)?;
-    writer.flush()
+/// Per-recording options supplied with the `start` command
+#[derive(Debug, Clone)]
+struct CaptureOptions {
+    vad: bool,
+    vad_hangover_ms: Option<u32>,
+    loopback: bool,
+    record_to: Option<String>,
+    downmix: DownmixMode,
+    // Keeps the input stream open between recordings so the last
+    // `preroll_ms` of audio can be prepended to the next one. The trade-off
+    // is that the device stays in use (and the OS mic indicator stays on)
+    // until a different device/config is requested or the process exits.
+    preroll_ms: u32,
+    gain: f32,
+    auto_gain: bool,
+    timestamps: bool,
+    // Prefer a supported config with this many bits per sample over the
+    // device default, e.g. 16 to avoid 24/32-bit conversion overhead
+    bit_depth: Option<u16>,
+    // How often to report liveness while recording, 0 to disable, and how
+    // long without audio before a heartbeat says the stream stalled
+    heartbeat_ms: u32,
+    stall_ms: u32,
+    drop_policy: DropPolicy,
+    host_api: Option<String>,
+    resample_quality: ResampleQuality,
+    resampler_chunk_size: usize,
+    highpass_hz: Option<f32>,
+    encoding: AudioEncoding,
+    max_duration_ms: Option<u64>,
+    noise_gate_db: Option<f32>,
+    // 1 for mono, 2 for interleaved L/R
+    channels_out: u8,
+}
+
+// What `start` uses when no options are given
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            vad: false,
+            vad_hangover_ms: None,
+            loopback: false,
+            record_to: None,
+            downmix: DownmixMode::default(),
+            preroll_ms: 0,
+            gain: 1.0,
+            auto_gain: false,
+            timestamps: false,
+            bit_depth: None,
+            heartbeat_ms: DEFAULT_HEARTBEAT_MS,
+            stall_ms: DEFAULT_STALL_MS,
+            drop_policy: DropPolicy::default(),
+            host_api: None,
+            resample_quality: ResampleQuality::default(),
+            resampler_chunk_size: CHUNK_SIZE_DEFAULT,
+            highpass_hz: None,
+            encoding: AudioEncoding::default(),
+            max_duration_ms: None,
+            noise_gate_db: None,
+            channels_out: 1,
+        }
+    }
+}
+
+impl CaptureOptions {
+    // Whether a stream opened with `self` can be reused for `other`; only the
+    // settings baked into the stream callback matter
+    fn same_stream(&self, other: &CaptureOptions) -> bool {
+        self.loopback == other.loopback
+            && self.downmix == other.downmix
+            && self.preroll_ms == other.preroll_ms
+            && self.bit_depth == other.bit_depth
+            && self.host_api == other.host_api
+            && self.channels_out == other.channels_out
+    }
+}
+
+// Rate of the audio sent to the host, whatever the device captures at
+const TARGET_SAMPLE_RATE: u32 = 16000;
+
+const DEFAULT_HEARTBEAT_MS: u32 = 3000;
+const DEFAULT_STALL_MS: u32 = 2000;
+
+/// How multi-channel input is folded down to mono
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum DownmixMode {
+    /// Use only the highest-energy channel
+    #[default]
+    Dominant,
+    /// Classic sum / channel count
+    Average,
+}
+
+impl DownmixMode {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "dominant" => Some(DownmixMode::Dominant),
+            "average" => Some(DownmixMode::Average),
+            _ => None,
+        }
+    }
+}
+
+// Wire format on stdout: framed ito_ipc messages. Audio payloads are 16kHz
+// mono i16 LE samples, or with `channels_out: 2` interleaved stereo frames
+// (L, R, L, R, ...), as given by audio-config's `channels`. Stereo Opus
+// packets carry both channels. When `timestamps` is set on start, each audio
+// frame is immediately preceded by a JSON
+// {"type":"audio-ts","offset_ms","offset_ns"} giving the position of its
+// first sample relative to the start of the recording. The timestamps don't
+// change the audio payload layout.
+fn write_json_message(stdout: &Arc<Mutex<io::Stdout>>, value: &impl Serialize) {
+    let mut writer = stdout.lock().unwrap();
+    let _ = write_framed_json(&mut *writer, value);
 }
 
 fn main() {
+    ito_ipc::logging::init("audio-recorder");
+
     let stdout = Arc::new(Mutex::new(io::stdout()));
     let (cmd_tx, cmd_rx) = crossbeam_channel::unbounded::<Command>();
 
     let mut command_processor = CommandProcessor::new(cmd_rx, Arc::clone(&stdout));
 
     thread::spawn(move || {
-        let stdin = io::stdin();
-        for l in stdin.lock().lines().map_while(Result::ok) {
-            if l.trim().is_empty() {
-                continue;
-            }
-            if let Ok(command) = serde_json::from_str::<Command>(&l) {
-                cmd_tx
-                    .send(command)
-                    .expect("Failed to send command to processor");
-            }
-        }
+        read_stdin_commands(|command: Command| {
+            cmd_tx
+                .send(command)
+                .expect("Failed to send command to processor");
+            ControlFlow::Continue(())
+        });
     });
 
+    // Commands sent from here on queue on stdin until the loop picks them up
+    write_json_message(&stdout, &ito_ipc::ready!());
     command_processor.run();
 }
 
@@ -77,24 +351,149 @@ struct CommandProcessor {
     active_stream: Option<cpal::Stream>,
     stdout: Arc<Mutex<io::Stdout>>,
     cached_host: Option<Rc<cpal::Host>>,
+    // Hosts opened by name through `host_api`
+    cached_api_hosts: Vec<Rc<cpal::Host>>,
+    // Name of the host behind the open stream
+    stream_host: Option<&'static str>,
     // Offloaded writer thread state
     audio_tx: Option<crossbeam_channel::Sender<Vec<f32>>>,
-    writer_handle: Option<std::thread::JoinHandle<()>>,
+    writer_handle: Option<std::thread::JoinHandle<Option<ChunkWriter>>>,
+    // Shared with the writer thread so paused frames are dropped without
+    // tearing down the resampler
+    paused: Arc<AtomicBool>,
+    // Unlike paused, muted frames are replaced with silence rather than
+    // dropped, so the output timeline and resampler state stay continuous
+    muted: Arc<AtomicBool>,
+    // Set by a non-draining stop so the writer abandons its queue. Replaced
+    // for every writer so one left detached can't be revived by the next.
+    discard: Arc<AtomicBool>,
+    // Set when a stream rebuild moves the recording to a new writer, so the
+    // old one drains and hands its chunk writer back instead of ending it
+    handoff: Arc<AtomicBool>,
+    // Signalled from the stream error callback when the device goes away
+    device_lost_tx: crossbeam_channel::Sender<()>,
+    device_lost_rx: crossbeam_channel::Receiver<()>,
+    // Signalled by the writer once the recording reaches max_duration_ms
+    auto_stop_tx: crossbeam_channel::Sender<()>,
+    auto_stop_rx: crossbeam_channel::Receiver<()>,
+    // What's left of the current recording's max_duration_ms, handed to
+    // every writer it goes through
+    max_duration: Option<Arc<DurationBudget>>,
+    // What the current recording was started with, so it can be restarted
+    // after a disconnect
+    active_request: Option<(Option<String>, CaptureOptions)>,
+    // Where the stream callback delivers frames, and what the open stream was
+    // created with. The stream can outlive a recording when pre-roll is on.
+    capture_route: Option<Arc<Mutex<CaptureRoute>>>,
+    stream_request: Option<(Option<String>, CaptureOptions)>,
+    input_sample_rate: u32,
+    // Device behind the open stream and its default rate at the time, polled
+    // to catch renegotiations (e.g. Bluetooth HFP <-> A2DP) and default
+    // device switches that cpal doesn't report as errors
+    stream_device: Option<(cpal::Device, u32)>,
+    // Stops the heartbeat thread when dropped
+    heartbeat: Option<Heartbeat>,
+    // The most recent output audio across recordings, for dump-debug-audio
+    debug_audio: Arc<Mutex<PreRollBuffer>>,
+    // Output stream of the current play command and its generation, which
+    // tells its end apart from one of a playback it replaced
+    playback: Option<(cpal::Stream, u64)>,
+    playback_generation: u64,
+    playback_done_tx: crossbeam_channel::Sender<PlaybackDone>,
+    playback_done_rx: crossbeam_channel::Receiver<PlaybackDone>,
 }
 
+// Enough to hear the problem a user is reporting, about 640KB of samples
+const DEBUG_AUDIO_MS: u32 = 10_000;
+
 impl CommandProcessor {
     fn new(cmd_rx: crossbeam_channel::Receiver<Command>, stdout: Arc<Mutex<io::Stdout>>) -> Self {
+        let (device_lost_tx, device_lost_rx) = crossbeam_channel::unbounded::<()>();
+        let (auto_stop_tx, auto_stop_rx) = crossbeam_channel::unbounded::<()>();
+        let (playback_done_tx, playback_done_rx) = crossbeam_channel::unbounded::<PlaybackDone>();
         CommandProcessor {
             cmd_rx,
             active_stream: None,
             stdout,
             cached_host: None,
+            cached_api_hosts: Vec::new(),
+            stream_host: None,
             audio_tx: None,
             writer_handle: None,
+            paused: Arc::new(AtomicBool::new(false)),
+            muted: Arc::new(AtomicBool::new(false)),
+            discard: Arc::new(AtomicBool::new(false)),
+            handoff: Arc::new(AtomicBool::new(false)),
+            device_lost_tx,
+            device_lost_rx,
+            auto_stop_tx,
+            auto_stop_rx,
+            max_duration: None,
+            active_request: None,
+            capture_route: None,
+            stream_request: None,
+            input_sample_rate: 0,
+            stream_device: None,
+            heartbeat: None,
+            debug_audio: Arc::new(Mutex::new(PreRollBuffer::with_duration(
+                TARGET_SAMPLE_RATE,
+                1,
+                DEBUG_AUDIO_MS,
+            ))),
+            playback: None,
+            playback_generation: 0,
+            playback_done_tx,
+            playback_done_rx,
+        }
+    }
+
+    // The host named by `host_api`, or the default host with a warning when
+    // it isn't compiled in or can't be opened
+    fn get_or_create_host(&mut self, host_api: Option<&str>) -> Rc<cpal::Host> {
+        let Some(name) = host_api else {
+            return self.get_or_create_default_host();
+        };
+        let Some(id) = host_id_from_name(name) else {
+            self.warn_host_fallback(format!(
+                "Audio host '{}' is not available in this build (available: {}), using default",
+                name,
+                cpal::available_hosts()
+                    .iter()
+                    .map(|id| id.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            return self.get_or_create_default_host();
+        };
+        if let Some(host) = self.cached_api_hosts.iter().find(|host| host.id() == id) {
+            return host.clone();
+        }
+        match cpal::host_from_id(id) {
+            Ok(host) => {
+                let host = Rc::new(host);
+                self.cached_api_hosts.push(host.clone());
+                host
+            }
+            Err(e) => {
+                self.warn_host_fallback(format!(
+                    "Audio host '{}' is unavailable ({}), using default",
+                    id.name(),
+                    e
+                ));
+                self.get_or_create_default_host()
+            }
         }
     }
 
-    fn get_or_create_host(&mut self) -> Rc<cpal::Host> {
+    fn warn_host_fallback(&self, message: String) {
+        log::warn!("{}", message);
+        write_json_message(
+            &self.stdout,
+            &serde_json::json!({ "type": "warning", "message": message }),
+        );
+    }
+
+    fn get_or_create_default_host(&mut self) -> Rc<cpal::Host> {
         if let Some(ref host) = self.cached_host {
             return host.clone();
         }
@@ -106,14 +505,11 @@ impl CommandProcessor {
                 // DirectSound's 50-80ms)
                 match cpal::host_from_id(cpal::platform::HostId::Wasapi) {
                     Ok(wasapi_host) => {
-                        eprintln!("[audio-recorder] Using WASAPI host (optimal for Windows)");
+                        log::info!("Using WASAPI host (optimal for Windows)");
                         wasapi_host
                     }
                     Err(e) => {
-                        eprintln!(
-                            "[audio-recorder] WASAPI unavailable ({}), falling back to default",
-                            e
-                        );
+                        log::warn!("WASAPI unavailable ({}), falling back to default", e);
                         cpal::default_host()
                     }
                 }
@@ -130,18 +526,211 @@ impl CommandProcessor {
     }
 
     fn run(&mut self) {
-        while let Ok(command) = self.cmd_rx.recv() {
-            match command {
-                Command::ListDevices => self.list_devices(),
-                Command::Start { device_name } => self.start_recording(device_name),
-                Command::Stop => self.stop_recording(),
-                Command::GetDeviceConfig { device_name } => self.get_device_config(device_name),
+        const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+        let cmd_rx = self.cmd_rx.clone();
+        let device_lost_rx = self.device_lost_rx.clone();
+        let auto_stop_rx = self.auto_stop_rx.clone();
+        let playback_done_rx = self.playback_done_rx.clone();
+        let config_check = crossbeam_channel::tick(CONFIG_CHECK_INTERVAL);
+        loop {
+            crossbeam_channel::select! {
+                recv(cmd_rx) -> command => match command {
+                    Ok(command) => self.handle_command(command),
+                    Err(_) => break,
+                },
+                recv(device_lost_rx) -> _ => self.reconnect(),
+                // Drains like a normal stop
+                recv(auto_stop_rx) -> _ => self.stop_recording(),
+                recv(config_check) -> _ => self.check_device_config(),
+                recv(playback_done_rx) -> done => if let Ok(done) = done {
+                    self.playback_ended(done);
+                },
             }
         }
     }
 
-    fn list_devices(&mut self) {
-        let host = self.get_or_create_host();
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::ListDevices { host_api } => self.list_devices(host_api.as_deref()),
+            Command::ListDevicesDetailed { host_api } => {
+                self.list_devices_detailed(host_api.as_deref())
+            }
+            Command::Start {
+                device_name,
+                vad,
+                vad_hangover_ms,
+                loopback,
+                record_to,
+                downmix,
+                preroll_ms,
+                gain,
+                auto_gain,
+                timestamps,
+                bit_depth,
+                heartbeat_ms,
+                stall_ms,
+                drop_policy,
+                host_api,
+                resample_quality,
+                resampler_chunk_size,
+                highpass_hz,
+                encoding,
+                max_duration_ms,
+                noise_gate_db,
+                channels_out,
+            } => {
+                let downmix = match downmix.as_deref() {
+                    None => DownmixMode::default(),
+                    Some(name) => DownmixMode::from_name(name).unwrap_or_else(|| {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("Unknown downmix mode '{}', using dominant", name),
+                            }),
+                        );
+                        DownmixMode::default()
+                    }),
+                };
+                let drop_policy = match drop_policy.as_deref() {
+                    None => DropPolicy::default(),
+                    Some(name) => DropPolicy::from_name(name).unwrap_or_else(|| {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("Unknown drop policy '{}', using drop", name),
+                            }),
+                        );
+                        DropPolicy::default()
+                    }),
+                };
+                let resample_quality = match resample_quality.as_deref() {
+                    None => ResampleQuality::default(),
+                    Some(name) => ResampleQuality::from_name(name).unwrap_or_else(|| {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("Unknown resample quality '{}', using balanced", name),
+                            }),
+                        );
+                        ResampleQuality::default()
+                    }),
+                };
+                let resampler_chunk_size = match resampler_chunk_size {
+                    None => CHUNK_SIZE_DEFAULT,
+                    Some(size) if size > CHUNK_SIZE_MAX => {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("resampler_chunk_size {} is too large, using {}", size, CHUNK_SIZE_MAX),
+                            }),
+                        );
+                        CHUNK_SIZE_MAX
+                    }
+                    Some(size) if size >= 2 => size,
+                    Some(size) => {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("Unsupported resampler_chunk_size {}, using {}", size, CHUNK_SIZE_DEFAULT),
+                            }),
+                        );
+                        CHUNK_SIZE_DEFAULT
+                    }
+                };
+                let encoding = match encoding.as_deref() {
+                    None => AudioEncoding::default(),
+                    Some(name) => AudioEncoding::from_name(name).unwrap_or_else(|| {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("Unknown encoding '{}', using pcm16", name),
+                            }),
+                        );
+                        AudioEncoding::default()
+                    }),
+                };
+                let channels_out = match channels_out {
+                    None | Some(1) => 1,
+                    Some(2) => 2,
+                    Some(channels) => {
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({
+                                "type": "warning",
+                                "message": format!("Unsupported channels_out {}, using 1", channels),
+                            }),
+                        );
+                        1
+                    }
+                };
+                self.start_recording(
+                    device_name,
+                    CaptureOptions {
+                        vad: vad.unwrap_or(false),
+                        vad_hangover_ms,
+                        loopback: loopback.unwrap_or(false),
+                        record_to,
+                        downmix,
+                        preroll_ms: preroll_ms.unwrap_or(0),
+                        gain: gain.unwrap_or(1.0),
+                        auto_gain: auto_gain.unwrap_or(false),
+                        timestamps: timestamps.unwrap_or(false),
+                        bit_depth,
+                        heartbeat_ms: heartbeat_ms.unwrap_or(DEFAULT_HEARTBEAT_MS),
+                        stall_ms: stall_ms.unwrap_or(DEFAULT_STALL_MS),
+                        drop_policy,
+                        host_api,
+                        resample_quality,
+                        resampler_chunk_size,
+                        highpass_hz,
+                        encoding,
+                        max_duration_ms: max_duration_ms.filter(|&ms| ms > 0),
+                        noise_gate_db: noise_gate_db.filter(|db| db.is_finite()),
+                        channels_out,
+                    },
+                )
+            }
+            Command::Stop { drain } => {
+                let drain = drain.unwrap_or(true);
+                self.stop_recording_with(drain);
+                if !drain {
+                    write_json_message(
+                        &self.stdout,
+                        &serde_json::json!({ "type": "stopped", "drained": false }),
+                    );
+                }
+            }
+            Command::Pause => self.pause_recording(),
+            Command::Resume => self.resume_recording(),
+            Command::SetMuted { muted } => self.set_muted(muted),
+            Command::GetDeviceConfig { device_name } => self.get_device_config(device_name),
+            Command::GetState => self.report_state(),
+            Command::DumpDebugAudio { path } => self.dump_debug_audio(path),
+            Command::Warmup { device_name } => self.warmup(device_name),
+            Command::Play {
+                path,
+                pcm,
+                sample_rate,
+                device_name,
+                host_api,
+            } => self.play(path, pcm, sample_rate, device_name, host_api),
+            Command::StopPlayback => self.stop_playback(),
+            Command::ListOutputDevices { host_api } => {
+                self.list_output_devices(host_api.as_deref())
+            }
+            Command::Version => write_json_message(&self.stdout, &ito_ipc::version_info!()),
+        }
+    }
+
+    fn list_devices(&mut self, host_api: Option<&str>) {
+        let host = self.get_or_create_host(host_api);
         let device_names: Vec<String> = match host.input_devices() {
             Ok(devices) => devices
                 .map(|d| d.name().unwrap_or_else(|_| "Unknown Device".to_string()))
@@ -152,45 +741,742 @@ impl CommandProcessor {
             response_type: "device-list".to_string(),
             devices: device_names,
         };
-        if let Ok(json_string) = serde_json::to_string(&response) {
-            let mut writer = self.stdout.lock().unwrap();
-            let _ = write_framed_message(&mut *writer, MSG_TYPE_JSON, json_string.as_bytes());
+        write_json_message(&self.stdout, &response);
+    }
+
+    fn list_output_devices(&mut self, host_api: Option<&str>) {
+        let host = self.get_or_create_host(host_api);
+        let device_names: Vec<String> = match host.output_devices() {
+            Ok(devices) => devices
+                .map(|d| d.name().unwrap_or_else(|_| "Unknown Device".to_string()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let response = DeviceList {
+            response_type: "output-device-list".to_string(),
+            devices: device_names,
+        };
+        write_json_message(&self.stdout, &response);
+    }
+
+    fn list_devices_detailed(&mut self, host_api: Option<&str>) {
+        let host = self.get_or_create_host(host_api);
+        let default_name = host.default_input_device().and_then(|d| d.name().ok());
+
+        let devices: Vec<DeviceInfo> = match host.input_devices() {
+            Ok(devices) => devices
+                .map(|d| {
+                    let name = d.name().unwrap_or_else(|_| "Unknown Device".to_string());
+                    let default_sample_rate =
+                        d.default_input_config().ok().map(|c| c.sample_rate().0);
+
+                    let mut min_sample_rate: Option<u32> = None;
+                    let mut max_sample_rate: Option<u32> = None;
+                    let mut max_channels: u16 = 0;
+                    if let Ok(configs) = d.supported_input_configs() {
+                        for cfg in configs {
+                            let lo = cfg.min_sample_rate().0;
+                            let hi = cfg.max_sample_rate().0;
+                            min_sample_rate = Some(min_sample_rate.map_or(lo, |m| m.min(lo)));
+                            max_sample_rate = Some(max_sample_rate.map_or(hi, |m| m.max(hi)));
+                            max_channels = max_channels.max(cfg.channels());
+                        }
+                    }
+
+                    DeviceInfo {
+                        is_default: default_name.as_deref() == Some(name.as_str()),
+                        name,
+                        default_sample_rate,
+                        min_sample_rate,
+                        max_sample_rate,
+                        max_channels,
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        write_json_message(
+            &self.stdout,
+            &DetailedDeviceList {
+                response_type: "device-list-detailed".to_string(),
+                devices,
+            },
+        );
+    }
+
+    fn start_recording(&mut self, device_name: Option<String>, options: CaptureOptions) {
+        // Leaves the stream open, so a warm one can be reused below
+        self.end_recording(true);
+        // Every recording starts unmuted with its full max_duration_ms,
+        // while reconnects and rebuilds keep the current state
+        self.set_muted(false);
+        self.max_duration = options
+            .max_duration_ms
+            .map(|ms| Arc::new(DurationBudget::new(ms)));
+
+        log::debug!("Starting recording on {:?}", device_name);
+        if let Err(e) = self.try_start(device_name, options, &mut None) {
+            log::error!("Failed to create audio stream: {}", e);
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({ "type": "error", "error": e.to_string() }),
+            );
         }
     }
 
-    fn start_recording(&mut self, device_name: Option<String>) {
-        self.stop_recording();
+    // `carried` is the chunk writer of a recording moving to a rebuilt
+    // stream, taken once the new writer is spawned
+    fn try_start(
+        &mut self,
+        device_name: Option<String>,
+        options: CaptureOptions,
+        carried: &mut Option<ChunkWriter>,
+    ) -> Result<()> {
+        self.paused.store(false, Ordering::SeqCst);
+
+        let reuse = self.active_stream.is_some()
+            && self
+                .stream_request
+                .as_ref()
+                .is_some_and(|(name, opts)| *name == device_name && opts.same_stream(&options));
+        if !reuse {
+            self.close_stream();
+            let host = self.get_or_create_host(options.host_api.as_deref());
+            self.stream_host = Some(host.id().name());
+            let capture = open_stream(
+                device_name.clone(),
+                Arc::clone(&self.stdout),
+                host,
+                &options,
+                self.device_lost_tx.clone(),
+            )?;
+            self.active_stream = Some(capture.stream);
+            self.capture_route = Some(capture.route);
+            self.input_sample_rate = capture.input_sample_rate;
+            self.stream_device = Some((capture.device, capture.native_sample_rate));
+            self.stream_request = Some((device_name.clone(), options.clone()));
+        }
 
-        let host = self.get_or_create_host();
-        if let Ok(handles) = start_capture(device_name, Arc::clone(&self.stdout), host) {
-            if handles.stream.play().is_ok() {
-                self.audio_tx = Some(handles.audio_tx);
-                self.writer_handle = Some(handles.writer_handle);
-                self.active_stream = Some(handles.stream);
+        let stats = self
+            .capture_route
+            .as_ref()
+            .map(|route| route.lock().unwrap().stats())
+            .unwrap_or_default();
+
+        // Built here rather than on the writer thread so audio-config can
+        // report the chunk size they settled on. Stereo frames are split up
+        // so each channel gets its own resampler
+        let resamplers: Vec<MonoResampler> = (0..options.channels_out)
+            .map(|_| {
+                MonoResampler::new(
+                    options.resample_quality,
+                    self.input_sample_rate,
+                    TARGET_SAMPLE_RATE,
+                    options.resampler_chunk_size,
+                )
+            })
+            .collect();
+
+        // Notify JS about input and effective output audio configuration
+        let config = AudioConfig {
+            response_type: "audio-config".to_string(),
+            input_sample_rate: self.input_sample_rate,
+            output_sample_rate: TARGET_SAMPLE_RATE,
+            channels: options.channels_out,
+            input_latency_ms: frames_to_ms(stats.block_frames(), self.input_sample_rate),
+            host: self.stream_host,
+            resample_quality: Some(options.resample_quality),
+            resampler_chunk_size: resamplers.first().and_then(MonoResampler::chunk_size),
+            encoding: options.encoding,
+            opus_frame_ms: (options.encoding == AudioEncoding::Opus).then_some(OPUS_FRAME_MS),
+            opus_bitrate: (options.encoding == AudioEncoding::Opus).then_some(OPUS_BITRATE),
+            native_16k: Some(self.input_sample_rate == TARGET_SAMPLE_RATE),
+        };
+        write_json_message(&self.stdout, &config);
+        self.discard = Arc::new(AtomicBool::new(false));
+        self.handoff = Arc::new(AtomicBool::new(false));
+        let (audio_tx, writer_handle) = spawn_writer(
+            Arc::clone(&self.stdout),
+            Arc::clone(&self.debug_audio),
+            config,
+            Arc::clone(&self.paused),
+            Arc::clone(&self.muted),
+            Arc::clone(&self.discard),
+            Arc::clone(&self.handoff),
+            carried.take(),
+            self.auto_stop_tx.clone(),
+            stats,
+            resamplers,
+            self.max_duration.clone(),
+            options.clone(),
+        );
+        if let Some(route) = self.capture_route.as_ref() {
+            let mut route = route.lock().unwrap();
+            route.attach(audio_tx.clone(), options.drop_policy);
+            if options.heartbeat_ms > 0 {
+                self.heartbeat = Some(Heartbeat::spawn(
+                    route.stats(),
+                    Arc::clone(&self.paused),
+                    Arc::clone(&self.stdout),
+                    Duration::from_millis(options.heartbeat_ms as u64),
+                    Duration::from_millis(options.stall_ms as u64),
+                ));
+            }
+        }
+        self.audio_tx = Some(audio_tx);
+        self.writer_handle = Some(writer_handle);
+
+        if let Some(stream) = self.active_stream.as_ref() {
+            if let Err(e) = stream.play() {
+                self.stop_recording();
+                self.close_stream();
+                return Err(e.into());
+            }
+        }
+        self.active_request = Some((device_name, options));
+        Ok(())
+    }
+
+    // Restart the stream after the device disappeared, falling back to the
+    // default device if the requested one is gone for good
+    fn reconnect(&mut self) {
+        const RECONNECT_ATTEMPTS: u32 = 5;
+        const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+        // A single disconnect can produce several errors; handle it once
+        while self.device_lost_rx.try_recv().is_ok() {}
+
+        let Some((device_name, options)) = self.active_request.clone() else {
+            // A warm pre-roll stream died between recordings; the next start
+            // will open a fresh one
+            self.close_stream();
+            return;
+        };
+        write_json_message(
+            &self.stdout,
+            &serde_json::json!({ "type": "device-reconnecting" }),
+        );
+        let previous_rate = self.input_sample_rate;
+        let mut carried = self.hand_off_writer();
+        self.close_stream();
+
+        let is_named = device_name
+            .as_ref()
+            .is_some_and(|name| !(name.to_lowercase() == "default" || name.is_empty()));
+
+        for attempt in 0..RECONNECT_ATTEMPTS {
+            if attempt > 0 {
+                thread::sleep(RECONNECT_DELAY);
+            }
+            if self
+                .try_start(device_name.clone(), options.clone(), &mut carried)
+                .is_ok()
+            {
+                write_json_message(
+                    &self.stdout,
+                    &serde_json::json!({
+                        "type": "device-reconnected",
+                        "device_name": device_name.as_deref().unwrap_or("default"),
+                    }),
+                );
+                self.report_config_change(previous_rate);
+                return;
             }
+            if is_named && self.try_start(None, options.clone(), &mut carried).is_ok() {
+                write_json_message(
+                    &self.stdout,
+                    &serde_json::json!({
+                        "type": "device-reconnected",
+                        "device_name": "default",
+                        "requested_device": device_name,
+                        "fallback": true,
+                    }),
+                );
+                self.report_config_change(previous_rate);
+                return;
+            }
+        }
+
+        write_json_message(
+            &self.stdout,
+            &serde_json::json!({
+                "type": "stream-error",
+                "error": "Failed to reconnect audio device",
+            }),
+        );
+        self.end_carried(carried);
+    }
+
+    // Rebuild the stream, and with it the writer's resampler, when the device
+    // has switched to a different native rate mid-recording. Left alone, the
+    // resampler keeps converting from the old rate and the audio comes out
+    // pitch-shifted
+    fn check_device_config(&mut self) {
+        if self.follow_default_device() {
+            return;
+        }
+        let Some((device, native_rate)) = self.stream_device.as_ref() else {
+            return;
+        };
+        let Some((device_name, options)) = self.active_request.clone() else {
+            return;
+        };
+        let current = if options.loopback {
+            device.default_output_config()
         } else {
-            eprintln!("[audio-recorder] CRITICAL: Failed to create audio stream");
+            device.default_input_config()
+        };
+        let Ok(current) = current else {
+            // A device that went away is handled by the error callback
+            return;
+        };
+        if current.sample_rate().0 == *native_rate {
+            return;
+        }
+
+        log::info!(
+            "Device rate changed from {} to {}, rebuilding stream",
+            native_rate,
+            current.sample_rate().0
+        );
+        let previous_rate = self.input_sample_rate;
+        let mut carried = self.hand_off_writer();
+        self.close_stream();
+        match self.try_start(device_name, options, &mut carried) {
+            Ok(()) => self.report_config_change(previous_rate),
+            Err(e) => write_json_message(
+                &self.stdout,
+                &serde_json::json!({
+                    "type": "stream-error",
+                    "error": format!("Failed to rebuild stream after config change: {}", e),
+                }),
+            ),
+        }
+        self.end_carried(carried);
+    }
+
+    // Move a stream on the implicit default device over to the new default
+    // when the user switches it in the OS settings (e.g. plugs in a headset).
+    // cpal doesn't report this, and the old stream carries on capturing
+    // silence. Returns whether the stream was rebuilt or closed
+    fn follow_default_device(&mut self) -> bool {
+        let Some((device_name, options)) = self.stream_request.clone() else {
+            return false;
+        };
+        // A device the user picked by name stays picked
+        if device_name
+            .as_ref()
+            .is_some_and(|name| !(name.to_lowercase() == "default" || name.is_empty()))
+        {
+            return false;
+        }
+        let Some(current) = self
+            .stream_device
+            .as_ref()
+            .and_then(|(device, _)| device.name().ok())
+        else {
+            return false;
+        };
+        let host = self.get_or_create_host(options.host_api.as_deref());
+        let default = if options.loopback {
+            host.default_output_device()
+        } else {
+            host.default_input_device()
+        };
+        let Some(default) = default.and_then(|device| device.name().ok()) else {
+            // No device at all is left to the error callback
+            return false;
+        };
+        if default == current {
+            return false;
+        }
+
+        log::info!("Default device changed, moving the stream to it");
+        let Some((device_name, options)) = self.active_request.clone() else {
+            // A warm pre-roll stream between recordings; the next start opens
+            // one on the new default
+            self.close_stream();
+            return true;
+        };
+        let previous_rate = self.input_sample_rate;
+        let mut carried = self.hand_off_writer();
+        self.close_stream();
+        match self.try_start(device_name, options, &mut carried) {
+            Ok(()) => {
+                write_json_message(
+                    &self.stdout,
+                    &serde_json::json!({ "type": "device-switched", "to": default }),
+                );
+                self.report_config_change(previous_rate);
+            }
+            Err(e) => write_json_message(
+                &self.stdout,
+                &serde_json::json!({
+                    "type": "stream-error",
+                    "error": format!("Failed to switch to the new default device: {}", e),
+                }),
+            ),
+        }
+        self.end_carried(carried);
+        true
+    }
+
+    fn report_config_change(&self, previous_rate: u32) {
+        if self.input_sample_rate != previous_rate {
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({
+                    "type": "audio-config-changed",
+                    "input_sample_rate": self.input_sample_rate,
+                    "previous_input_sample_rate": previous_rate,
+                }),
+            );
         }
     }
 
     fn stop_recording(&mut self) {
-        if let Some(stream) = self.active_stream.take() {
-            let _ = stream.pause();
-            drop(stream);
+        self.stop_recording_with(true);
+    }
+
+    fn stop_recording_with(&mut self, drain: bool) {
+        self.end_recording(drain);
+        let keep_warm = self
+            .stream_request
+            .as_ref()
+            .is_some_and(|(_, options)| options.preroll_ms > 0);
+        if !keep_warm {
+            self.close_stream();
+        }
+    }
+
+    // Ends the current recording but leaves the stream open
+    fn end_recording(&mut self, drain: bool) {
+        if !drain {
+            self.discard.store(true, Ordering::SeqCst);
+        }
+        self.stop_writer();
+        // A limit hit while this stop was underway mustn't end the next
+        // recording
+        while self.auto_stop_rx.try_recv().is_ok() {}
+        self.paused.store(false, Ordering::SeqCst);
+        self.active_request = None;
+    }
+
+    // Drains the writer for a stream rebuild without ending the recording:
+    // no drain-complete goes out, and the returned chunk writer carries the
+    // Opus framing, audio-ts offsets and recording file over to the next
+    // writer. None if the writer couldn't be joined in time
+    fn hand_off_writer(&mut self) -> Option<ChunkWriter> {
+        self.handoff.store(true, Ordering::SeqCst);
+        let carried = self.stop_writer();
+        self.active_request = None;
+        carried
+    }
+
+    // Ends a recording whose stream couldn't be rebuilt, the way its writer
+    // would have
+    fn end_carried(&self, carried: Option<ChunkWriter>) {
+        if let Some(chunk_writer) = carried {
+            chunk_writer.complete();
+        }
+    }
+
+    fn stop_writer(&mut self) -> Option<ChunkWriter> {
+        self.heartbeat = None;
+        // Stop feeding the writer before closing its channel so it can drain
+        if let Some(route) = self.capture_route.as_ref() {
+            route.lock().unwrap().detach();
         }
         // Close audio channel to signal writer thread to exit
         if let Some(tx) = self.audio_tx.take() {
             drop(tx);
         }
-        if let Some(handle) = self.writer_handle.take() {
-            let _ = handle.join();
+        let handle = self.writer_handle.take()?;
+        log::debug!("Stopping recording");
+        self.join_writer(handle)
+    }
+
+    // Bounded join so a wedged resampler flush can't hang Stop; on timeout
+    // the writer is detached and left to finish (or not) on its own
+    fn join_writer(
+        &self,
+        handle: std::thread::JoinHandle<Option<ChunkWriter>>,
+    ) -> Option<ChunkWriter> {
+        const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+        let (done_tx, done_rx) = crossbeam_channel::bounded::<Option<ChunkWriter>>(1);
+        thread::spawn(move || {
+            let _ = done_tx.send(handle.join().ok().flatten());
+        });
+        let joined = done_rx.recv_timeout(DRAIN_TIMEOUT);
+        if joined.is_err() {
+            log::warn!(
+                "Writer thread did not drain within {:?}, detaching",
+                DRAIN_TIMEOUT
+            );
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({
+                    "type": "warning",
+                    "message": "Audio drain timed out; writer thread detached",
+                }),
+            );
         }
+        joined.ok().flatten()
     }
 
-    fn get_device_config(&mut self, device_name: Option<String>) {
-        const TARGET_SAMPLE_RATE: u32 = 16000;
+    // Opens the stream a plain start on `device_name` would use and leaves it
+    // paused, so the device isn't capturing until then. That start reuses it
+    // through the same path as a warm pre-roll stream
+    fn warmup(&mut self, device_name: Option<String>) {
+        // A recording already has its stream running
+        if self.active_request.is_none() {
+            let options = CaptureOptions::default();
+            let warm = self.active_stream.is_some()
+                && self
+                    .stream_request
+                    .as_ref()
+                    .is_some_and(|(name, opts)| *name == device_name && opts.same_stream(&options));
+            if !warm {
+                self.close_stream();
+                let host = self.get_or_create_host(None);
+                let opened = open_stream(
+                    device_name.clone(),
+                    Arc::clone(&self.stdout),
+                    host.clone(),
+                    &options,
+                    self.device_lost_tx.clone(),
+                );
+                let capture = match opened {
+                    Ok(capture) => capture,
+                    Err(e) => {
+                        log::error!("Failed to warm up audio stream: {}", e);
+                        write_json_message(
+                            &self.stdout,
+                            &serde_json::json!({ "type": "error", "error": e.to_string() }),
+                        );
+                        return;
+                    }
+                };
+                // Some hosts start a stream as soon as it's built
+                let _ = capture.stream.pause();
+                self.stream_host = Some(host.id().name());
+                self.active_stream = Some(capture.stream);
+                self.capture_route = Some(capture.route);
+                self.input_sample_rate = capture.input_sample_rate;
+                self.stream_device = Some((capture.device, capture.native_sample_rate));
+                self.stream_request = Some((device_name, options));
+            }
+        }
+        let device = self
+            .stream_device
+            .as_ref()
+            .and_then(|(device, _)| device.name().ok());
+        write_json_message(
+            &self.stdout,
+            &serde_json::json!({ "type": "warmed", "device": device }),
+        );
+    }
+
+    // Every play is answered by exactly one playback-finished, with
+    // completed false when it failed or was cut short
+    fn play(
+        &mut self,
+        path: Option<String>,
+        pcm: Option<String>,
+        sample_rate: Option<u32>,
+        device_name: Option<String>,
+        host_api: Option<String>,
+    ) {
+        self.stop_playback();
+        self.playback_generation += 1;
+        let generation = self.playback_generation;
+        if let Err(e) = self.try_play(path, pcm, sample_rate, device_name, host_api, generation) {
+            log::error!("Failed to start playback: {}", e);
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({
+                    "type": "playback-finished",
+                    "completed": false,
+                    "error": e.to_string(),
+                }),
+            );
+        }
+    }
+
+    fn try_play(
+        &mut self,
+        path: Option<String>,
+        pcm: Option<String>,
+        sample_rate: Option<u32>,
+        device_name: Option<String>,
+        host_api: Option<String>,
+        generation: u64,
+    ) -> Result<()> {
+        const DEFAULT_PCM_RATE: u32 = 16000;
+
+        let clip = match (path, pcm) {
+            (Some(path), None) => {
+                let wav =
+                    wav::read_wav(&path).map_err(|e| anyhow!("Failed to read {}: {}", path, e))?;
+                Clip::from_interleaved(&wav.samples, wav.channels as usize, wav.sample_rate)
+            }
+            (None, Some(pcm)) => Clip::from_pcm16_base64(
+                &pcm,
+                sample_rate
+                    .filter(|&rate| rate > 0)
+                    .unwrap_or(DEFAULT_PCM_RATE),
+            )?,
+            _ => return Err(anyhow!("play needs exactly one of path or pcm")),
+        };
+
+        let host = self.get_or_create_host(host_api.as_deref());
+        let device = match device_name {
+            Some(name) if !(name.to_lowercase() == "default" || name.is_empty()) => host
+                .output_devices()?
+                .find(|d| d.name().unwrap_or_default() == name),
+            _ => host.default_output_device(),
+        }
+        .ok_or_else(|| anyhow!("[audio-recorder] Failed to find output device"))?;
+
+        let stream = playback::play(&device, clip, generation, self.playback_done_tx.clone())?;
+        self.playback = Some((stream, generation));
+        write_json_message(
+            &self.stdout,
+            &serde_json::json!({
+                "type": "playback-started",
+                "device": device.name().ok(),
+            }),
+        );
+        Ok(())
+    }
+
+    fn stop_playback(&mut self) {
+        if let Some((stream, _)) = self.playback.take() {
+            let _ = stream.pause();
+            drop(stream);
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({ "type": "playback-finished", "completed": false }),
+            );
+        }
+    }
+
+    fn playback_ended(&mut self, done: PlaybackDone) {
+        if self
+            .playback
+            .as_ref()
+            .is_none_or(|(_, generation)| *generation != done.generation)
+        {
+            return;
+        }
+        self.playback = None;
+        let mut message = serde_json::json!({
+            "type": "playback-finished",
+            "completed": done.error.is_none(),
+        });
+        if let Some(error) = done.error {
+            message["error"] = serde_json::json!(error);
+        }
+        write_json_message(&self.stdout, &message);
+    }
+
+    fn close_stream(&mut self) {
+        if let Some(stream) = self.active_stream.take() {
+            let _ = stream.pause();
+            drop(stream);
+        }
+        self.capture_route = None;
+        self.stream_request = None;
+        self.stream_device = None;
+        self.stream_host = None;
+    }
+
+    // Pausing keeps the writer thread and resampler alive so resuming avoids
+    // the cold-start latency of rebuilding the stream
+    fn pause_recording(&mut self) {
+        // A warm pre-roll stream between recordings has nothing to pause
+        if self.active_request.is_none() {
+            return;
+        }
+        if let Some(stream) = self.active_stream.as_ref() {
+            log::debug!("Pausing recording");
+            self.paused.store(true, Ordering::SeqCst);
+            if let Err(e) = stream.pause() {
+                log::error!("Failed to pause stream: {}", e);
+            }
+        }
+    }
 
-        let host = self.get_or_create_host();
+    fn resume_recording(&mut self) {
+        if self.active_request.is_none() {
+            return;
+        }
+        if let Some(stream) = self.active_stream.as_ref() {
+            log::debug!("Resuming recording");
+            if let Err(e) = stream.play() {
+                log::error!("Failed to resume stream: {}", e);
+            }
+            if let Some(route) = self.capture_route.as_ref() {
+                route.lock().unwrap().stats().touch();
+            }
+            self.paused.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        if self.muted.swap(muted, Ordering::SeqCst) != muted {
+            log::debug!("Muted: {}", muted);
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({ "type": "mute-state", "muted": muted }),
+            );
+        }
+    }
+
+    fn report_state(&self) {
+        let stream_open = self.active_stream.is_some();
+        let state = RecorderState {
+            response_type: "state",
+            recording: self.active_request.is_some(),
+            paused: self.paused.load(Ordering::SeqCst),
+            muted: self.muted.load(Ordering::SeqCst),
+            device: self
+                .stream_device
+                .as_ref()
+                .and_then(|(device, _)| device.name().ok()),
+            input_sample_rate: stream_open.then_some(self.input_sample_rate),
+            output_sample_rate: TARGET_SAMPLE_RATE,
+        };
+        write_json_message(&self.stdout, &state);
+    }
+
+    // The buffer is only locked while it's copied, and the file is written on
+    // its own thread, so a live recording carries on undisturbed
+    fn dump_debug_audio(&self, path: String) {
+        let samples = self.debug_audio.lock().unwrap().snapshot();
+        let stdout = Arc::clone(&self.stdout);
+        std::thread::spawn(move || {
+            let written = WavWriter::create(&path, TARGET_SAMPLE_RATE, 1).and_then(|mut wav| {
+                wav.write_pcm(&encode_pcm16(&samples))?;
+                wav.finalize()
+            });
+            let mut message = serde_json::json!({
+                "type": "debug-audio-dumped",
+                "path": path,
+                "success": written.is_ok(),
+                "duration_ms": samples.len() as u64 * 1000 / TARGET_SAMPLE_RATE as u64,
+            });
+            if let Err(e) = written {
+                message["error"] = serde_json::json!(e.to_string());
+            }
+            write_json_message(&stdout, &message);
+        });
+    }
+
+    fn get_device_config(&mut self, device_name: Option<String>) {
+        let host = self.get_or_create_host(None);
 
         let device = if let Some(name) = device_name {
             if name.to_lowercase() == "default" || name.is_empty() {
@@ -215,36 +1501,280 @@ impl CommandProcessor {
             input_sample_rate: input_rate,
             output_sample_rate: TARGET_SAMPLE_RATE,
             channels: 1,
+            input_latency_ms: None,
+            host: Some(host.id().name()),
+            resample_quality: None,
+            resampler_chunk_size: None,
+            encoding: AudioEncoding::default(),
+            opus_frame_ms: None,
+            opus_bitrate: None,
+            native_16k: None,
         };
-        if let Ok(json_string) = serde_json::to_string(&cfg) {
-            let mut writer = self.stdout.lock().unwrap();
-            let _ = write_framed_message(&mut *writer, MSG_TYPE_JSON, json_string.as_bytes());
+        write_json_message(&self.stdout, &cfg);
+    }
+}
+
+fn encode_pcm16(data: &[f32]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(data.len() * 2);
+    for s in data {
+        buffer.extend_from_slice(&((s.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+    }
+    buffer
+}
+
+fn write_audio_chunk(msg_type: u8, payload: &[u8], stdout: &Arc<Mutex<io::Stdout>>) {
+    let mut writer = stdout.lock().unwrap();
+    if let Err(e) = write_framed_message(&mut *writer, msg_type, payload) {
+        log::error!("Failed to write to stdout: {}", e);
+    }
+}
+
+/// Applies gain, frames output audio to stdout (as pcm16 or Opus packets) and
+/// mirrors it to an optional WAV file. File I/O happens after the chunk has been sent so it never
+/// delays streaming.
+struct ChunkWriter {
+    stdout: Arc<Mutex<io::Stdout>>,
+    wav: Option<WavWriter>,
+    debug_audio: Arc<Mutex<PreRollBuffer>>,
+    // Set when the host asked for Opus, otherwise chunks go out as pcm16
+    opus: Option<OpusFramer>,
+    gate: Option<NoiseGate>,
+    gain: GainStage,
+    // Level reports are only sent when gain is in play, roughly 4x per second
+    level_interval: usize,
+    level_samples: usize,
+    level_peak: f32,
+    // Output samples written so far, the basis for audio-ts offsets. Derived
+    // from sample count rather than wall clock so host read delays don't skew it.
+    timestamps: bool,
+    samples_written: u64,
+    sample_rate: u32,
+    // Samples per frame, 2 when writing interleaved stereo
+    channels: usize,
+}
+
+impl ChunkWriter {
+    fn new(
+        stdout: Arc<Mutex<io::Stdout>>,
+        debug_audio: Arc<Mutex<PreRollBuffer>>,
+        options: &CaptureOptions,
+        sample_rate: u32,
+    ) -> Self {
+        let channels = options.channels_out as usize;
+        let record_to = options.record_to.as_deref();
+        let wav = record_to.and_then(|path| {
+            match WavWriter::create(path, sample_rate, channels as u16) {
+                Ok(wav) => Some(wav),
+                Err(e) => {
+                    write_json_message(
+                        &stdout,
+                        &serde_json::json!({
+                            "type": "warning",
+                            "message": format!("Failed to open recording file {}: {}", path, e),
+                        }),
+                    );
+                    None
+                }
+            }
+        });
+        let opus = (options.encoding == AudioEncoding::Opus)
+            .then(|| OpusFramer::new(sample_rate, channels));
+        let opus = match opus {
+            Some(Err(e)) => {
+                write_json_message(
+                    &stdout,
+                    &serde_json::json!({
+                        "type": "warning",
+                        "message": format!("Failed to create Opus encoder, sending pcm16: {}", e),
+                    }),
+                );
+                None
+            }
+            opus => opus.and_then(Result::ok),
+        };
+        ChunkWriter {
+            stdout,
+            wav,
+            debug_audio,
+            opus,
+            // Stereo is gated on the level of both channels together, as if
+            // it were one signal at twice the rate
+            gate: options
+                .noise_gate_db
+                .map(|db| NoiseGate::new(db, sample_rate * channels as u32)),
+            gain: GainStage::new(options.gain, options.auto_gain),
+            level_interval: sample_rate as usize / 4 * channels,
+            level_samples: 0,
+            level_peak: 0.0,
+            timestamps: options.timestamps,
+            samples_written: 0,
+            sample_rate,
+            channels,
+        }
+    }
+
+    fn write(&mut self, data: &[f32]) {
+        let gated;
+        let data = match self.gate.as_mut() {
+            Some(gate) => {
+                gated = {
+                    let mut samples = data.to_vec();
+                    gate.process(&mut samples);
+                    samples
+                };
+                &gated
+            }
+            None => data,
+        };
+        let gained;
+        let samples = if self.gain.is_unity() {
+            data
+        } else {
+            gained = {
+                let mut samples = data.to_vec();
+                self.gain.process(&mut samples);
+                samples
+            };
+            self.report_level(gained.len());
+            &gained
+        };
+        let pcm = encode_pcm16(samples);
+        match self.opus.as_mut() {
+            Some(opus) => {
+                let frame_samples = opus.frame_samples();
+                for packet in opus.push(samples) {
+                    self.send(MSG_TYPE_OPUS, &packet, frame_samples);
+                }
+            }
+            None => self.send(MSG_TYPE_AUDIO, &pcm, samples.len()),
+        }
+        // Debug dumps stay mono whatever is being streamed
+        if self.channels > 1 {
+            let mono = downmix_to_mono_vec(samples, self.channels, DownmixMode::Average);
+            self.debug_audio.lock().unwrap().push(&mono);
+        } else {
+            self.debug_audio.lock().unwrap().push(samples);
+        }
+
+        if let Some(wav) = self.wav.as_mut() {
+            if let Err(e) = wav.write_pcm(&pcm) {
+                self.wav = None;
+                write_json_message(
+                    &self.stdout,
+                    &serde_json::json!({
+                        "type": "warning",
+                        "message": format!("Stopped writing recording file: {}", e),
+                    }),
+                );
+            }
+        }
+    }
+
+    // Sends one message of `samples` output samples, preceded by its offset
+    // when timestamps are on
+    fn send(&mut self, msg_type: u8, payload: &[u8], samples: usize) {
+        if self.timestamps {
+            let frames_written = self.samples_written / self.channels as u64;
+            let offset_ns = frames_written * 1_000_000_000 / self.sample_rate as u64;
+            write_json_message(
+                &self.stdout,
+                &serde_json::json!({
+                    "type": "audio-ts",
+                    "offset_ms": offset_ns / 1_000_000,
+                    "offset_ns": offset_ns,
+                }),
+            );
         }
+        self.samples_written += samples as u64;
+        write_audio_chunk(msg_type, payload, &self.stdout);
     }
-}
 
-fn write_audio_chunk(data: &[f32], stdout: &Arc<Mutex<io::Stdout>>) {
-    let mut writer = stdout.lock().unwrap();
-    let mut buffer = Vec::with_capacity(data.len() * 2);
-    for s in data {
-        buffer.extend_from_slice(&((s.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+    fn report_level(&mut self, samples: usize) {
+        self.level_peak = self.level_peak.max(self.gain.last_peak());
+        self.level_samples += samples;
+        if self.level_samples >= self.level_interval {
+            let mut level = serde_json::json!({
+                "type": "audio-level",
+                "peak": self.level_peak,
+                "gain": self.gain.gain(),
+            });
+            if let Some(gate) = self.gate.as_ref() {
+                level["gate"] = serde_json::json!(if gate.is_open() { "open" } else { "closed" });
+            }
+            write_json_message(&self.stdout, &level);
+            self.level_samples = 0;
+            self.level_peak = 0.0;
+        }
     }
 
-    if let Err(e) = write_framed_message(&mut *writer, MSG_TYPE_AUDIO, &buffer) {
-        eprintln!(
-            "[audio-recorder] CRITICAL: Failed to write to stdout: {}",
-            e
-        );
+    // Finishes a drained recording and tells the host it's over
+    fn complete(self) {
+        let stdout = Arc::clone(&self.stdout);
+        self.finish(true);
+        write_json_message(&stdout, &serde_json::json!({ "type": "drain-complete" }));
+    }
+
+    // Without flush, a partial Opus frame is dropped rather than padded out,
+    // but a recording file is still finalized so it stays readable
+    fn finish(mut self, flush: bool) {
+        if let Some(opus) = self.opus.as_mut().filter(|_| flush) {
+            let frame_samples = opus.frame_samples();
+            for packet in opus.flush() {
+                self.send(MSG_TYPE_OPUS, &packet, frame_samples);
+            }
+        }
+        if let Some(wav) = self.wav {
+            if let Err(e) = wav.finalize() {
+                write_json_message(
+                    &self.stdout,
+                    &serde_json::json!({
+                        "type": "warning",
+                        "message": format!("Failed to finalize recording file: {}", e),
+                    }),
+                );
+            }
+        }
     }
 }
 
-struct CaptureHandles {
+struct CaptureStream {
     stream: cpal::Stream,
-    audio_tx: crossbeam_channel::Sender<Vec<f32>>,
-    writer_handle: std::thread::JoinHandle<()>,
+    route: Arc<Mutex<CaptureRoute>>,
+    input_sample_rate: u32,
+    device: cpal::Device,
+    // The device's default rate when the stream was opened, which can differ
+    // from input_sample_rate when the device offers 16kHz mono or a bit depth
+    // was requested
+    native_sample_rate: u32,
+}
+
+// What the stream callback hands on: a mono downmix, or with stereo output
+// the first two input channels interleaved, a mono input feeding both
+fn capture_block<T>(
+    data: &[T],
+    num_channels: usize,
+    mode: DownmixMode,
+    channels_out: u8,
+) -> Vec<f32>
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    if channels_out < 2 {
+        return downmix_to_mono_vec(data, num_channels, mode);
+    }
+    if num_channels <= 1 {
+        return data
+            .iter()
+            .flat_map(|s| [s.to_sample::<f32>(); 2])
+            .collect();
+    }
+    data.chunks_exact(num_channels)
+        .flat_map(|frame| [frame[0].to_sample::<f32>(), frame[1].to_sample::<f32>()])
+        .collect()
 }
 
-fn downmix_to_mono_vec<T>(data: &[T], num_channels: usize) -> Vec<f32>
+fn downmix_to_mono_vec<T>(data: &[T], num_channels: usize, mode: DownmixMode) -> Vec<f32>
 where
     T: Sample,
     f32: FromSample<T>,
@@ -252,13 +1782,22 @@ where
     if num_channels <= 1 {
         return data.iter().map(|s| s.to_sample::<f32>()).collect();
     }
-    // Select the dominant channel to avoid amplitude loss when one channel is
-    // near-silent
     let frames = data.len() / num_channels;
     if frames == 0 {
         return Vec::new();
     }
 
+    if mode == DownmixMode::Average {
+        return data[..frames * num_channels]
+            .chunks_exact(num_channels)
+            .map(|frame| {
+                frame.iter().map(|s| s.to_sample::<f32>()).sum::<f32>() / num_channels as f32
+            })
+            .collect();
+    }
+
+    // Select the dominant channel to avoid amplitude loss when one channel is
+    // near-silent
     let mut energy_per_channel: Vec<f32> = vec![0.0; num_channels];
     for frame_idx in 0..frames {
         let base = frame_idx * num_channels;
@@ -285,315 +1824,618 @@ where
     out
 }
 
+// Splits interleaved samples into one Vec per channel
+fn deinterleave(data: Vec<f32>, channels: usize) -> Vec<Vec<f32>> {
+    if channels <= 1 {
+        return vec![data];
+    }
+    (0..channels)
+        .map(|c| data.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
+
+// Channels resampled separately can in principle come out a sample apart, so
+// only whole frames are kept
+fn interleave(mut planes: Vec<Vec<f32>>) -> Vec<f32> {
+    if planes.len() == 1 {
+        return planes.pop().unwrap_or_default();
+    }
+    let frames = planes.iter().map(Vec::len).min().unwrap_or(0);
+    (0..frames)
+        .flat_map(|i| planes.iter().map(move |plane| plane[i]))
+        .collect()
+}
+
+fn average_planes(planes: &[Vec<f32>]) -> Vec<f32> {
+    let frames = planes.iter().map(Vec::len).min().unwrap_or(0);
+    (0..frames)
+        .map(|i| planes.iter().map(|plane| plane[i]).sum::<f32>() / planes.len() as f32)
+        .collect()
+}
+
+/// What's left of a recording's max_duration_ms, shared by every writer the
+/// recording goes through so a reconnect or rebuild doesn't restart the clock
+struct DurationBudget {
+    // Microseconds rather than frames, since a rebuilt stream can run at
+    // another rate
+    remaining_us: AtomicU64,
+}
+
+impl DurationBudget {
+    fn new(ms: u64) -> Self {
+        DurationBudget {
+            remaining_us: AtomicU64::new(ms.saturating_mul(1000)),
+        }
+    }
+
+    fn is_spent(&self) -> bool {
+        self.remaining_us.load(Ordering::Relaxed) == 0
+    }
+
+    /// Spends up to `frames` frames at `sample_rate` and returns how many fit
+    fn spend(&self, frames: usize, sample_rate: u32) -> usize {
+        let remaining_us = self.remaining_us.load(Ordering::Relaxed);
+        let fits = (remaining_us * sample_rate as u64).div_ceil(1_000_000) as usize;
+        if frames >= fits {
+            self.remaining_us.store(0, Ordering::Relaxed);
+            return fits;
+        }
+        let spent_us = frames as u64 * 1_000_000 / sample_rate.max(1) as u64;
+        self.remaining_us
+            .store(remaining_us.saturating_sub(spent_us), Ordering::Relaxed);
+        frames
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn writer_loop(
     audio_rx: crossbeam_channel::Receiver<Vec<f32>>,
     stdout: Arc<Mutex<io::Stdout>>,
-    input_sample_rate: u32,
-) {
-    const TARGET_SAMPLE_RATE: u32 = 16000;
-    const RESAMPLER_CHUNK_SIZE_DEFAULT: usize = 1024;
-    const RESAMPLER_CHUNK_SIZE_FALLBACK: usize = 512;
-
-    // Try FFT resampler with default size, then fallback chunk size
-    let mut chosen_chunk_size: usize = RESAMPLER_CHUNK_SIZE_DEFAULT;
-    let mut resampler_opt = if input_sample_rate != TARGET_SAMPLE_RATE {
-        match FftFixedIn::new(
-            input_sample_rate as usize,
-            TARGET_SAMPLE_RATE as usize,
-            chosen_chunk_size,
-            1,
-            1,
-        ) {
-            Ok(r) => Some(r),
-            Err(e) => {
-                eprintln!(
-                    "[audio-recorder] CRITICAL: Failed to create resampler ({}), trying fallback chunk size",
-                    e
-                );
-                chosen_chunk_size = RESAMPLER_CHUNK_SIZE_FALLBACK;
-                match FftFixedIn::new(
-                    input_sample_rate as usize,
-                    TARGET_SAMPLE_RATE as usize,
-                    chosen_chunk_size,
-                    1,
-                    1,
-                ) {
-                    Ok(r2) => Some(r2),
-                    Err(e2) => {
-                        eprintln!(
-                            "[audio-recorder] CRITICAL: Fallback resampler creation failed ({}), using linear fallback",
-                            e2
-                        );
-                        None
-                    }
-                }
-            }
+    debug_audio: Arc<Mutex<PreRollBuffer>>,
+    config: AudioConfig,
+    paused: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    discard: Arc<AtomicBool>,
+    handoff: Arc<AtomicBool>,
+    carried: Option<ChunkWriter>,
+    auto_stop_tx: crossbeam_channel::Sender<()>,
+    stats: Arc<FrameStats>,
+    mut resamplers: Vec<MonoResampler>,
+    max_duration: Option<Arc<DurationBudget>>,
+    options: CaptureOptions,
+) -> Option<ChunkWriter> {
+    let input_sample_rate = config.input_sample_rate;
+    // Stereo frames are split up so each channel gets its own resampler and
+    // filter state
+    let channels = options.channels_out as usize;
+    let mut chunk_writer = carried.unwrap_or_else(|| {
+        ChunkWriter::new(
+            Arc::clone(&stdout),
+            debug_audio,
+            &options,
+            TARGET_SAMPLE_RATE,
+        )
+    });
+    let mut highpass = options.highpass_hz.and_then(|cutoff| {
+        let filter = HighPass::new(cutoff, input_sample_rate);
+        if filter.is_none() {
+            log::warn!(
+                "Ignoring highpass_hz {} outside 0..{}Hz",
+                cutoff,
+                input_sample_rate / 2
+            );
         }
-    } else {
-        None
+        filter.map(|filter| vec![filter; channels])
+    });
+
+    // VAD runs on the captured mono signal and only emits boundary events;
+    // audio keeps flowing regardless so the host decides what to do with it
+    let mut vad = options.vad.then(|| {
+        VoiceActivityDetector::new(
+            input_sample_rate,
+            options.vad_hangover_ms.unwrap_or(vad::DEFAULT_HANGOVER_MS),
+        )
+    });
+
+    // Drops are reported as a running total for the recording, at most once
+    // per interval so a sustained stall doesn't flood stdout
+    const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+    let mut reported_drops = 0;
+    let mut last_drop_report = Instant::now();
+    let report_drops = |count: u64| {
+        log::warn!("{} audio frames dropped so far", count);
+        write_json_message(
+            &stdout,
+            &serde_json::json!({ "type": "frames-dropped", "count": count }),
+        );
     };
 
-    let mut in_buffer: Vec<f32> = Vec::new();
-
-    // Linear resampler fallback for mono when FFT resampler isn't available
-    fn linear_resample_mono(input: &[f32], in_rate: u32, out_rate: u32) -> Vec<f32> {
-        if input.is_empty() || in_rate == 0 || in_rate == out_rate {
-            return input.to_vec();
-        }
-        let in_len = input.len();
-        let ratio = out_rate as f32 / in_rate as f32;
-        let out_len = ((in_len as f32) * ratio).round().max(0.0) as usize;
-        if out_len <= 1 {
-            return Vec::new();
-        }
-        let step = in_rate as f32 / out_rate as f32;
-        let mut out = Vec::with_capacity(out_len);
-        let mut pos: f32 = 0.0;
-        for _ in 0..out_len {
-            let idx = pos.floor() as usize;
-            if idx >= in_len - 1 {
-                out.push(input[in_len - 1]);
-            } else {
-                let frac = pos - (idx as f32);
-                let a = input[idx];
-                let b = input[idx + 1];
-                out.push(a + (b - a) * frac);
-            }
-            pos += step;
-        }
-        out
-    }
-
-    while let Ok(frame) = audio_rx.recv() {
-        if let Some(resampler) = resampler_opt.as_mut() {
-            in_buffer.extend_from_slice(&frame);
-            while in_buffer.len() >= chosen_chunk_size {
-                let chunk_to_process: Vec<f32> =
-                    in_buffer.drain(..chosen_chunk_size).collect::<Vec<_>>();
-                match resampler.process(&[chunk_to_process], None) {
-                    Ok(mut resampled) => {
-                        if !resampled.is_empty() {
-                            write_audio_chunk(&resampled.remove(0), &stdout);
-                        }
-                    }
-                    Err(e) => eprintln!(
-                        "[audio-recorder] CRITICAL: Resampling failed in writer: {}",
-                        e
-                    ),
-                }
+    // A fresh stream hasn't run a callback yet when audio-config goes out
+    let mut latency_pending = stats.block_frames() == 0;
+
+    while let Ok(mut frame) = audio_rx.recv() {
+        if discard.load(Ordering::Relaxed) {
+            break;
+        }
+        if latency_pending {
+            if let Some(latency) = frames_to_ms(stats.block_frames(), input_sample_rate) {
+                latency_pending = false;
+                write_json_message(
+                    &stdout,
+                    &AudioConfig {
+                        input_latency_ms: Some(latency),
+                        ..config.clone()
+                    },
+                );
             }
-        } else if input_sample_rate != TARGET_SAMPLE_RATE {
-            let resampled = linear_resample_mono(&frame, input_sample_rate, TARGET_SAMPLE_RATE);
-            if !resampled.is_empty() {
-                write_audio_chunk(&resampled, &stdout);
+        }
+        let dropped = stats.dropped();
+        if dropped > reported_drops && last_drop_report.elapsed() >= DROP_REPORT_INTERVAL {
+            report_drops(dropped);
+            reported_drops = dropped;
+            last_drop_report = Instant::now();
+        }
+        // Drop anything the stream delivers while paused (some hosts keep
+        // invoking the callback briefly after pause())
+        if paused.load(Ordering::Relaxed) {
+            continue;
+        }
+        // Elapsed time is counted in captured frames, so paused stretches
+        // don't count towards max_duration_ms. Past the limit, frames are
+        // dropped until the stop closes the channel
+        if let Some(budget) = max_duration.as_deref() {
+            if budget.is_spent() {
+                continue;
+            }
+            let fits = budget.spend(frame.len() / channels, input_sample_rate);
+            if budget.is_spent() {
+                frame.truncate(fits * channels);
+                log::info!("Reached max_duration_ms, stopping");
+                write_json_message(
+                    &stdout,
+                    &serde_json::json!({ "type": "auto-stopped", "reason": "max-duration" }),
+                );
+                let _ = auto_stop_tx.send(());
             }
-        } else {
-            write_audio_chunk(&frame, &stdout);
         }
-    }
-
-    // Channel closed; flush any remaining buffered samples through resampler
-    if let Some(mut resampler) = resampler_opt.take() {
-        while !in_buffer.is_empty() {
-            let take = if in_buffer.len() >= chosen_chunk_size {
-                chosen_chunk_size
-            } else {
-                in_buffer.len()
+        if muted.load(Ordering::Relaxed) {
+            frame.fill(0.0);
+        }
+        let mut planes = deinterleave(frame, channels);
+        if let Some(filters) = highpass.as_mut() {
+            for (filter, plane) in filters.iter_mut().zip(planes.iter_mut()) {
+                filter.process(plane);
+            }
+        }
+        if let Some(detector) = vad.as_mut() {
+            let event = match planes.as_slice() {
+                [mono] => detector.process(mono),
+                planes => detector.process(&average_planes(planes)),
             };
-            let mut chunk = in_buffer.drain(..take).collect::<Vec<_>>();
-            if chunk.len() < chosen_chunk_size {
-                // zero-pad final chunk to meet resampler size
-                chunk.resize(chosen_chunk_size, 0.0);
-            }
-            if let Ok(mut resampled) = resampler.process(&[chunk], None) {
-                if !resampled.is_empty() {
-                    write_audio_chunk(&resampled.remove(0), &stdout);
+            match event {
+                Some(VadEvent::SpeechStart) => {
+                    write_json_message(&stdout, &serde_json::json!({ "type": "speech-start" }))
                 }
+                Some(VadEvent::SpeechEnd { silence_ms }) => write_json_message(
+                    &stdout,
+                    &serde_json::json!({ "type": "speech-end", "silence_ms": silence_ms }),
+                ),
+                None => {}
             }
         }
-    } else if !in_buffer.is_empty() {
-        if input_sample_rate != TARGET_SAMPLE_RATE {
-            let resampled = linear_resample_mono(&in_buffer, input_sample_rate, TARGET_SAMPLE_RATE);
-            if !resampled.is_empty() {
-                write_audio_chunk(&resampled, &stdout);
-            }
-        } else {
-            write_audio_chunk(&in_buffer, &stdout);
+        let resampled = interleave(
+            resamplers
+                .iter_mut()
+                .zip(&planes)
+                .map(|(resampler, plane)| resampler.process(plane))
+                .collect(),
+        );
+        if !resampled.is_empty() {
+            chunk_writer.write(&resampled);
         }
     }
 
-    // Signal drain complete to the host via a JSON message
-    let response = serde_json::json!({
-        "type": "drain-complete"
-    });
-    if let Ok(json_string) = serde_json::to_string(&response) {
-        let mut writer = stdout.lock().unwrap();
-        let _ = write_framed_message(&mut *writer, MSG_TYPE_JSON, json_string.as_bytes());
+    if stats.dropped() > reported_drops {
+        report_drops(stats.dropped());
+    }
+
+    // A non-draining stop reports for itself once the writer is gone
+    if discard.load(Ordering::SeqCst) {
+        chunk_writer.finish(false);
+        return None;
     }
+
+    // Channel closed; flush any remaining buffered samples through resampler
+    let resampled = interleave(resamplers.iter_mut().map(MonoResampler::flush).collect());
+    if !resampled.is_empty() {
+        chunk_writer.write(&resampled);
+    }
+
+    // The recording carries on in the next writer, which ends it
+    if handoff.load(Ordering::SeqCst) {
+        return Some(chunk_writer);
+    }
+    chunk_writer.complete();
+    None
 }
 
-fn start_capture(
+// WASAPI captures whatever is rendered to an output device when an input
+// stream is built on it, so loopback just means picking an output device
+#[cfg(target_os = "windows")]
+fn find_loopback_device(
+    host: &cpal::Host,
     device_name: Option<String>,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    let device = match device_name {
+        Some(name) if !(name.to_lowercase() == "default" || name.is_empty()) => host
+            .output_devices()?
+            .find(|d| d.name().unwrap_or_default() == name),
+        _ => host.default_output_device(),
+    }
+    .ok_or_else(|| anyhow!("[audio-recorder] Failed to find output device for loopback"))?;
+
+    let default_config = device
+        .default_output_config()
+        .map_err(|_| anyhow!("[audio-recorder] No default output config found for loopback"))?;
+    Ok((device, default_config))
+}
+
+// Other hosts have no loopback capture; system audio has to be routed through
+// a virtual/aggregate input device and selected by name instead
+#[cfg(not(target_os = "windows"))]
+fn find_loopback_device(
+    _host: &cpal::Host,
+    _device_name: Option<String>,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    Err(anyhow!(
+        "[audio-recorder] Loopback capture is not supported on this platform; select a virtual input device instead"
+    ))
+}
+
+// Match a host by cpal's name for it, e.g. "WASAPI", "ASIO", "ALSA", "JACK"
+// or "CoreAudio", ignoring case. None when it isn't compiled into this build
+fn host_id_from_name(name: &str) -> Option<cpal::HostId> {
+    cpal::ALL_HOSTS
+        .iter()
+        .copied()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+}
+
+fn sample_format_bits(format: SampleFormat) -> u16 {
+    (format.sample_size() * 8) as u16
+}
+
+// Picks a mono config at `rate` when the device already runs at it, in the
+// default config's sample format where possible. Opening a shared device at
+// any other rate makes CoreAudio change its nominal rate system-wide, for
+// every app using it. None when the default already is one, the device runs
+// at another rate, or no mono config is offered
+fn prefer_native_rate(
+    configs: Vec<SupportedStreamConfigRange>,
+    default_config: &SupportedStreamConfig,
+    rate: u32,
+) -> Option<SupportedStreamConfig> {
+    if default_config.sample_rate().0 != rate || default_config.channels() == 1 {
+        return None;
+    }
+    let rate = cpal::SampleRate(rate);
+    configs
+        .into_iter()
+        .filter(|c| c.channels() == 1 && c.min_sample_rate() <= rate && rate <= c.max_sample_rate())
+        .min_by_key(|c| c.sample_format() != default_config.sample_format())
+        .map(|c| c.with_sample_rate(rate))
+}
+
+// Picks a supported config with the requested bit depth, keeping the default
+// config's channel count and sample rate where possible
+fn prefer_bit_depth(
+    configs: Vec<SupportedStreamConfigRange>,
+    default_config: &SupportedStreamConfig,
+    bits: u16,
+) -> Option<SupportedStreamConfig> {
+    let rate = default_config.sample_rate();
+    let mut matching: Vec<SupportedStreamConfigRange> = configs
+        .into_iter()
+        .filter(|c| sample_format_bits(c.sample_format()) == bits)
+        .collect();
+    // Same channel count first, then ranges covering the default rate
+    matching.sort_by_key(|c| {
+        (
+            c.channels() != default_config.channels(),
+            !(c.min_sample_rate() <= rate && rate <= c.max_sample_rate()),
+        )
+    });
+    let best = matching.into_iter().next()?;
+    if best.min_sample_rate() <= rate && rate <= best.max_sample_rate() {
+        Some(best.with_sample_rate(rate))
+    } else {
+        Some(best.with_max_sample_rate())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_writer(
     stdout: Arc<Mutex<io::Stdout>>,
-    host: Rc<cpal::Host>,
-) -> Result<CaptureHandles> {
-    const TARGET_SAMPLE_RATE: u32 = 16000;
+    debug_audio: Arc<Mutex<PreRollBuffer>>,
+    config: AudioConfig,
+    paused: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    discard: Arc<AtomicBool>,
+    handoff: Arc<AtomicBool>,
+    carried: Option<ChunkWriter>,
+    auto_stop_tx: crossbeam_channel::Sender<()>,
+    stats: Arc<FrameStats>,
+    resamplers: Vec<MonoResampler>,
+    max_duration: Option<Arc<DurationBudget>>,
+    options: CaptureOptions,
+) -> (
+    crossbeam_channel::Sender<Vec<f32>>,
+    std::thread::JoinHandle<Option<ChunkWriter>>,
+) {
     const QUEUE_CAPACITY: usize = 512;
 
-    let device = if let Some(name) = device_name {
-        if name.to_lowercase() == "default" || name.is_empty() {
-            host.default_input_device()
+    let (audio_tx, audio_rx) = crossbeam_channel::bounded::<Vec<f32>>(QUEUE_CAPACITY);
+    let writer_handle = std::thread::spawn(move || {
+        writer_loop(
+            audio_rx,
+            stdout,
+            debug_audio,
+            config,
+            paused,
+            muted,
+            discard,
+            handoff,
+            carried,
+            auto_stop_tx,
+            stats,
+            resamplers,
+            max_duration,
+            options,
+        )
+    });
+    (audio_tx, writer_handle)
+}
+
+fn open_stream(
+    device_name: Option<String>,
+    stdout: Arc<Mutex<io::Stdout>>,
+    host: Rc<cpal::Host>,
+    options: &CaptureOptions,
+    device_lost_tx: crossbeam_channel::Sender<()>,
+) -> Result<CaptureStream> {
+    let (device, default_config) = if options.loopback {
+        find_loopback_device(&host, device_name)?
+    } else {
+        let device = if let Some(name) = device_name {
+            if name.to_lowercase() == "default" || name.is_empty() {
+                host.default_input_device()
+            } else {
+                host.input_devices()?
+                    .find(|d| d.name().unwrap_or_default() == name)
+            }
         } else {
-            host.input_devices()?
-                .find(|d| d.name().unwrap_or_default() == name)
+            host.default_input_device()
         }
-    } else {
-        host.default_input_device()
-    }
-    .ok_or_else(|| anyhow!("[audio-recorder] Failed to find input device"))?;
+        .ok_or_else(|| anyhow!("[audio-recorder] Failed to find input device"))?;
 
-    // Prefer the device's default input configuration instead of max rate to
-    // better align with other apps (e.g., Zoom) and reduce host resampling.
-    let default_config = device
-        .default_input_config()
-        .map_err(|_| anyhow!("[audio-recorder] No default input config found"))?;
+        // Prefer the device's default input configuration instead of max rate to
+        // better align with other apps (e.g., Zoom) and reduce host resampling.
+        let default_config = device
+            .default_input_config()
+            .map_err(|_| anyhow!("[audio-recorder] No default input config found"))?;
+        (device, default_config)
+    };
+
+    let native_sample_rate = default_config.sample_rate().0;
+    // Capture mono directly when the device already runs at 16kHz, skipping
+    // the downmix and resampler. Loopback keeps the output device's own format, and stereo
+    // output needs the device's channels
+    let default_config = if options.loopback || options.channels_out > 1 {
+        default_config
+    } else {
+        device
+            .supported_input_configs()
+            .ok()
+            .and_then(|configs| {
+                prefer_native_rate(configs.collect(), &default_config, TARGET_SAMPLE_RATE)
+            })
+            .unwrap_or(default_config)
+    };
+    let default_config = match options.bit_depth {
+        Some(bits) if sample_format_bits(default_config.sample_format()) != bits => {
+            let configs: Vec<SupportedStreamConfigRange> = if options.loopback {
+                device
+                    .supported_output_configs()
+                    .map(|c| c.collect())
+                    .unwrap_or_default()
+            } else {
+                device
+                    .supported_input_configs()
+                    .map(|c| c.collect())
+                    .unwrap_or_default()
+            };
+            prefer_bit_depth(configs, &default_config, bits).unwrap_or_else(|| {
+                write_json_message(
+                    &stdout,
+                    &serde_json::json!({
+                        "type": "warning",
+                        "message": format!(
+                            "No {}-bit config available, using default {}",
+                            bits,
+                            default_config.sample_format()
+                        ),
+                    }),
+                );
+                default_config
+            })
+        }
+        _ => default_config,
+    };
 
     let input_sample_rate = default_config.sample_rate().0;
     let input_sample_format = default_config.sample_format();
     let channels_count: usize = default_config.channels() as usize;
+    let downmix = options.downmix;
+    let channels_out = options.channels_out;
 
-    let err_fn = |err| eprintln!("[audio-recorder] Stream error: {}", err);
+    let stdout_for_errors = Arc::clone(&stdout);
+    let err_fn = move |err: cpal::StreamError| {
+        log::error!("Stream error: {}", err);
+        write_json_message(
+            &stdout_for_errors,
+            &serde_json::json!({ "type": "stream-error", "error": err.to_string() }),
+        );
+        if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+            let _ = device_lost_tx.send(());
+        }
+    };
     let stream_config: StreamConfig = default_config.clone().into();
 
-    // Writer thread and queue
-    let (audio_tx, audio_rx) = crossbeam_channel::bounded::<Vec<f32>>(QUEUE_CAPACITY);
-    let stdout_for_writer = Arc::clone(&stdout);
-    let writer_handle = std::thread::spawn(move || {
-        writer_loop(audio_rx, stdout_for_writer, input_sample_rate);
-    });
-
-    // Notify JS about input and effective output audio configuration
-    {
-        let cfg = AudioConfig {
-            response_type: "audio-config".to_string(),
+    let preroll = (options.preroll_ms > 0).then(|| {
+        PreRollBuffer::with_duration(
             input_sample_rate,
-            output_sample_rate: TARGET_SAMPLE_RATE,
-            channels: 1,
-        };
-        if let Ok(json_string) = serde_json::to_string(&cfg) {
-            let mut writer = stdout.lock().unwrap();
-            let _ = write_framed_message(&mut *writer, MSG_TYPE_JSON, json_string.as_bytes());
-        }
+            options.channels_out as usize,
+            options.preroll_ms,
+        )
+    });
+    let route = Arc::new(Mutex::new(CaptureRoute::new(
+        preroll,
+        options.channels_out as usize,
+    )));
+    if let cpal::BufferSize::Fixed(frames) = stream_config.buffer_size {
+        route.lock().unwrap().stats().record_block(frames as usize);
     }
 
     let stream = match input_sample_format {
         SampleFormat::F32 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[f32], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         SampleFormat::I16 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[i16], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         SampleFormat::U16 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[u16], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         SampleFormat::U8 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[u8], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         SampleFormat::I32 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[i32], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         SampleFormat::F64 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[f64], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         SampleFormat::U32 => {
-            let tx = audio_tx.clone();
+            let route = Arc::clone(&route);
             device.build_input_stream(
                 &stream_config,
                 move |data: &[u32], _| {
-                    let mono = downmix_to_mono_vec(data, channels_count);
-                    let _ = tx.try_send(mono);
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
+                },
+                err_fn,
+                None,
+            )?
+        }
+        SampleFormat::I24 => {
+            // 24-bit samples arrive packed in i32 storage; dasp scales them
+            // by the 24-bit range when converting to f32
+            let route = Arc::clone(&route);
+            device.build_input_stream(
+                &stream_config,
+                move |data: &[cpal::I24], _| {
+                    let block = capture_block(data, channels_count, downmix, channels_out);
+                    route.lock().unwrap().deliver(block);
                 },
                 err_fn,
                 None,
             )?
         }
         format => {
+            log::warn!(
+                "Rejected sample format {:?} ({}-bit)",
+                format,
+                sample_format_bits(format)
+            );
             return Err(anyhow!(
                 "[audio-recorder] Unsupported sample format {}",
                 format
-            ))
+            ));
         }
     };
 
-    Ok(CaptureHandles {
+    Ok(CaptureStream {
         stream,
-        audio_tx,
-        writer_handle,
+        route,
+        input_sample_rate,
+        device,
+        native_sample_rate,
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ito_ipc::MSG_TYPE_JSON;
 
     #[test]
     fn test_downmix_to_mono_single_channel() {
         let mono_samples: Vec<f32> = vec![0.5, -0.5, 1.0, -1.0];
-        let result = downmix_to_mono_vec(&mono_samples, 1);
+        let result = downmix_to_mono_vec(&mono_samples, 1, DownmixMode::Dominant);
 
         assert_eq!(result.len(), 4);
         assert_eq!(result, vec![0.5, -0.5, 1.0, -1.0]);
@@ -603,7 +2445,7 @@ mod tests {
     fn test_downmix_to_mono_stereo() {
         // Stereo: L,R,L,R pattern
         let stereo_samples: Vec<f32> = vec![0.8, 0.2, -0.6, -0.4];
-        let result = downmix_to_mono_vec(&stereo_samples, 2);
+        let result = downmix_to_mono_vec(&stereo_samples, 2, DownmixMode::Dominant);
 
         assert_eq!(result.len(), 2);
         assert_eq!(result[0], 0.8); // Left channel sample 1
@@ -614,7 +2456,7 @@ mod tests {
     fn test_downmix_to_mono_quad() {
         // 4 channels: one frame with values [1.0, 0.5, 0.25, 0.25]
         let quad_samples: Vec<f32> = vec![1.0, 0.5, 0.25, 0.25]; // One frame
-        let result = downmix_to_mono_vec(&quad_samples, 4);
+        let result = downmix_to_mono_vec(&quad_samples, 4, DownmixMode::Dominant);
 
         assert_eq!(result.len(), 1);
         assert_eq!(result[0], 1.0); // Channel 0 sample
@@ -624,13 +2466,221 @@ mod tests {
     fn test_downmix_partial_frame() {
         // 5 samples with 2 channels - last sample incomplete, should be ignored
         let samples: Vec<f32> = vec![0.8, 0.2, -0.6, -0.4, 1.0];
-        let result = downmix_to_mono_vec(&samples, 2);
+        let result = downmix_to_mono_vec(&samples, 2, DownmixMode::Dominant);
 
         assert_eq!(result.len(), 2); // Only 2 complete frames
         assert_eq!(result[0], 0.8); // Left channel sample 1
         assert_eq!(result[1], -0.6); // Left channel sample 2
     }
 
+    #[test]
+    fn test_downmix_average_stereo() {
+        let stereo_samples: Vec<f32> = vec![0.8, 0.2, -0.6, -0.4];
+        let result = downmix_to_mono_vec(&stereo_samples, 2, DownmixMode::Average);
+
+        assert_eq!(result.len(), 2);
+        assert!((result[0] - 0.5).abs() < 1e-6);
+        assert!((result[1] + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_downmix_average_partial_frame() {
+        let samples: Vec<f32> = vec![1.0, 0.0, 0.5, 0.5, 1.0];
+        let result = downmix_to_mono_vec(&samples, 2, DownmixMode::Average);
+
+        assert_eq!(result, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_downmix_average_single_channel_passthrough() {
+        let mono_samples: Vec<f32> = vec![0.5, -0.5];
+        let result = downmix_to_mono_vec(&mono_samples, 1, DownmixMode::Average);
+
+        assert_eq!(result, vec![0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_downmix_mode_from_name() {
+        assert_eq!(
+            DownmixMode::from_name("Average"),
+            Some(DownmixMode::Average)
+        );
+        assert_eq!(
+            DownmixMode::from_name("dominant"),
+            Some(DownmixMode::Dominant)
+        );
+        assert_eq!(DownmixMode::from_name("loudest"), None);
+    }
+
+    #[test]
+    fn test_downmix_i24_scaling() {
+        let samples = vec![
+            cpal::I24::new(1 << 22).unwrap(),
+            cpal::I24::new(-(1 << 23)).unwrap(),
+        ];
+        let result = downmix_to_mono_vec(&samples, 1, DownmixMode::Dominant);
+
+        assert!((result[0] - 0.5).abs() < 1e-6);
+        assert!((result[1] + 1.0).abs() < 1e-6);
+    }
+
+    // Min, midpoint and max of each format should land on -1.0, 0.0 and just
+    // under 1.0
+    fn assert_scaled<T>(samples: &[T], expected: &[f32])
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        let result = downmix_to_mono_vec(samples, 1, DownmixMode::Dominant);
+        assert_eq!(result.len(), expected.len());
+        for (got, want) in result.iter().zip(expected) {
+            assert!((got - want).abs() < 1e-4, "got {}, want {}", got, want);
+        }
+    }
+
+    #[test]
+    fn test_downmix_unsigned_scaling() {
+        assert_scaled(&[u8::MIN, 128, u8::MAX], &[-1.0, 0.0, 127.0 / 128.0]);
+        assert_scaled(&[u16::MIN, 1 << 15, u16::MAX], &[-1.0, 0.0, 1.0]);
+        assert_scaled(&[u32::MIN, 1 << 31, u32::MAX], &[-1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_downmix_signed_and_float_scaling() {
+        assert_scaled(&[i16::MIN, 0, i16::MAX], &[-1.0, 0.0, 1.0]);
+        assert_scaled(&[i32::MIN, 0, i32::MAX], &[-1.0, 0.0, 1.0]);
+        assert_scaled(&[-1.0f64, 0.0, 0.5, 1.0], &[-1.0, 0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_downmix_integer_formats_stereo() {
+        // Dominant picks the loud right channel; averaging halves it
+        let samples = [128u8, 255, 128, 0];
+        let dominant = downmix_to_mono_vec(&samples, 2, DownmixMode::Dominant);
+        assert!((dominant[0] - 127.0 / 128.0).abs() < 1e-4);
+        assert!((dominant[1] + 1.0).abs() < 1e-4);
+        let average = downmix_to_mono_vec(&samples, 2, DownmixMode::Average);
+        assert!((average[0] - 127.0 / 256.0).abs() < 1e-4);
+        assert!((average[1] + 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_stereo_capture_round_trips_channels() {
+        // Channels past the first two are dropped, mono input feeds both
+        let quad: Vec<i16> = vec![100, -200, 300, 400, 500, -600, 700, 800];
+        let stereo = capture_block(&quad, 4, DownmixMode::Dominant, 2);
+        assert_eq!(stereo.len(), 4);
+        assert_eq!(stereo[1], (-200i16).to_sample::<f32>());
+        assert_eq!(stereo[2], 500i16.to_sample::<f32>());
+        assert_eq!(
+            capture_block(&[0.5f32, 0.25], 1, DownmixMode::Dominant, 2),
+            [0.5, 0.5, 0.25, 0.25]
+        );
+        assert_eq!(
+            capture_block(&[0.5f32, 0.25], 2, DownmixMode::Average, 1),
+            [0.375]
+        );
+
+        let planes = deinterleave(vec![1.0, -1.0, 2.0, -2.0, 3.0, -3.0], 2);
+        assert_eq!(planes, [vec![1.0, 2.0, 3.0], vec![-1.0, -2.0, -3.0]]);
+        assert_eq!(average_planes(&planes), [0.0, 0.0, 0.0]);
+        assert_eq!(interleave(planes), [1.0, -1.0, 2.0, -2.0, 3.0, -3.0]);
+        // A channel a sample short only loses the incomplete frame
+        assert_eq!(interleave(vec![vec![1.0, 2.0], vec![-1.0]]), [1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_host_id_from_name() {
+        for id in cpal::ALL_HOSTS {
+            assert_eq!(host_id_from_name(id.name()), Some(*id));
+            assert_eq!(host_id_from_name(&id.name().to_uppercase()), Some(*id));
+        }
+        assert_eq!(host_id_from_name("NotAnAudioApi"), None);
+    }
+
+    #[test]
+    fn test_frames_to_ms() {
+        assert_eq!(frames_to_ms(480, 48000), Some(10.0));
+        assert_eq!(frames_to_ms(512, 44100), Some(11.6));
+        assert_eq!(frames_to_ms(0, 48000), None);
+    }
+
+    #[test]
+    fn test_prefer_bit_depth() {
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                cpal::SampleRate(min),
+                cpal::SampleRate(max),
+                cpal::SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let default_config = SupportedStreamConfig::new(
+            2,
+            cpal::SampleRate(48000),
+            cpal::SupportedBufferSize::Unknown,
+            SampleFormat::I24,
+        );
+        let configs = vec![
+            range(2, 44100, 96000, SampleFormat::I24),
+            range(1, 8000, 48000, SampleFormat::I16),
+            range(2, 8000, 44100, SampleFormat::I16),
+            range(2, 8000, 96000, SampleFormat::I16),
+        ];
+
+        let chosen = prefer_bit_depth(configs.clone(), &default_config, 16).unwrap();
+        assert_eq!(chosen.sample_format(), SampleFormat::I16);
+        assert_eq!(chosen.channels(), 2);
+        assert_eq!(chosen.sample_rate(), cpal::SampleRate(48000));
+
+        assert!(prefer_bit_depth(configs, &default_config, 64).is_none());
+    }
+
+    #[test]
+    fn test_prefer_native_rate() {
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                cpal::SampleRate(min),
+                cpal::SampleRate(max),
+                cpal::SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let config = |channels, rate, format| {
+            SupportedStreamConfig::new(
+                channels,
+                cpal::SampleRate(rate),
+                cpal::SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let default_config = config(2, 16000, SampleFormat::F32);
+        let configs = vec![
+            range(2, 8000, 48000, SampleFormat::F32),
+            range(1, 8000, 48000, SampleFormat::I16),
+            range(1, 8000, 48000, SampleFormat::F32),
+        ];
+
+        let chosen = prefer_native_rate(configs.clone(), &default_config, 16000).unwrap();
+        assert_eq!(chosen.channels(), 1);
+        assert_eq!(chosen.sample_rate(), cpal::SampleRate(16000));
+        assert_eq!(chosen.sample_format(), SampleFormat::F32);
+
+        // Only stereo, or no range reaching down to 16kHz
+        assert!(prefer_native_rate(configs[..1].to_vec(), &default_config, 16000).is_none());
+        let high = vec![range(1, 44100, 48000, SampleFormat::F32)];
+        assert!(prefer_native_rate(high, &default_config, 16000).is_none());
+        // A device running at 48kHz is never moved to 16kHz, even though it
+        // offers it
+        let shared = config(2, 48000, SampleFormat::F32);
+        assert!(prefer_native_rate(configs.clone(), &shared, 16000).is_none());
+        // Already native
+        let native = config(1, 16000, SampleFormat::I16);
+        assert!(prefer_native_rate(configs, &native, 16000).is_none());
+    }
+
     #[test]
     fn test_write_framed_message_structure() {
         let mut buffer = Vec::new();
@@ -661,4 +2711,72 @@ mod tests {
         let length = u32::from_le_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]);
         assert_eq!(length, 100);
     }
+
+    #[test]
+    fn test_list_devices_parses() {
+        // Hosts that predate host_api send the bare command
+        let command: Command = serde_json::from_str(r#"{"command":"list-devices"}"#).unwrap();
+        assert!(matches!(command, Command::ListDevices { host_api: None }));
+        let command: Command =
+            serde_json::from_str(r#"{"command":"list-devices","host_api":"asio"}"#).unwrap();
+        assert!(matches!(command, Command::ListDevices { host_api: Some(name) } if name == "asio"));
+    }
+
+    #[test]
+    fn test_dump_debug_audio_parses() {
+        let command: Command =
+            serde_json::from_str(r#"{"command":"dump-debug-audio","path":"/tmp/last.wav"}"#)
+                .unwrap();
+        assert!(matches!(command, Command::DumpDebugAudio { path } if path == "/tmp/last.wav"));
+    }
+
+    #[test]
+    fn test_warmup_parses() {
+        let command: Command =
+            serde_json::from_str(r#"{"command":"warmup","device_name":"USB Mic"}"#).unwrap();
+        assert!(
+            matches!(command, Command::Warmup { device_name: Some(name) } if name == "USB Mic")
+        );
+        let command: Command = serde_json::from_str(r#"{"command":"warmup"}"#).unwrap();
+        assert!(matches!(command, Command::Warmup { device_name: None }));
+    }
+
+    #[test]
+    fn test_play_parses() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"play","path":"/tmp/speech.wav","device_name":"Speakers"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::Play { path: Some(path), pcm: None, device_name: Some(device), .. }
+                if path == "/tmp/speech.wav" && device == "Speakers"
+        ));
+        let command: Command = serde_json::from_str(r#"{"command":"stop-playback"}"#).unwrap();
+        assert!(matches!(command, Command::StopPlayback));
+    }
+
+    #[test]
+    fn test_duration_budget_carries_across_rates() {
+        let budget = DurationBudget::new(100);
+        // 50ms at 16kHz, then a rebuilt stream at 48kHz gets the other 50ms
+        assert_eq!(budget.spend(800, 16_000), 800);
+        assert!(!budget.is_spent());
+        assert_eq!(budget.spend(48_000, 48_000), 2400);
+        assert!(budget.is_spent());
+        assert_eq!(budget.spend(480, 48_000), 0);
+
+        // Filling it exactly spends it too
+        let budget = DurationBudget::new(10);
+        assert_eq!(budget.spend(160, 16_000), 160);
+        assert!(budget.is_spent());
+    }
+
+    #[test]
+    fn test_stop_drain_defaults() {
+        let stop: Command = serde_json::from_str(r#"{"command":"stop"}"#).unwrap();
+        assert!(matches!(stop, Command::Stop { drain: None }));
+        let stop: Command = serde_json::from_str(r#"{"command":"stop","drain":false}"#).unwrap();
+        assert!(matches!(stop, Command::Stop { drain: Some(false) }));
+    }
 }
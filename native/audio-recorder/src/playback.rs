@@ -0,0 +1,199 @@
+//! Playback of generated audio (local TTS) through a chosen output device.
+//!
+//! The clip is decoded, downmixed and resampled to the device rate up front,
+//! then fed to a cpal output stream with every output channel carrying the
+//! same signal. The stream reports back over a channel once it has played the
+//! last sample, or failed, so the command loop can drop it and tell the host.
+
+use anyhow::{anyhow, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, StreamConfig};
+use crossbeam_channel::Sender;
+
+use crate::resample::{MonoResampler, ResampleQuality, CHUNK_SIZE_DEFAULT};
+
+/// Sent from the stream when a playback ends on its own
+pub struct PlaybackDone {
+    /// Which `play` this was, so a late message from a replaced stream is
+    /// ignored
+    pub generation: u64,
+    pub error: Option<String>,
+}
+
+/// Mono audio and its rate, as given by the host
+pub struct Clip {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+impl Clip {
+    /// Averages interleaved channels down to mono
+    pub fn from_interleaved(samples: &[f32], channels: usize, sample_rate: u32) -> Self {
+        let samples = if channels <= 1 {
+            samples.to_vec()
+        } else {
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect()
+        };
+        Clip {
+            samples,
+            sample_rate,
+        }
+    }
+
+    /// Little-endian i16 samples, base64 encoded
+    pub fn from_pcm16_base64(text: &str, sample_rate: u32) -> Result<Self> {
+        let bytes = decode_base64(text).ok_or_else(|| anyhow!("pcm is not valid base64"))?;
+        let samples = bytes
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+            .collect();
+        Ok(Clip {
+            samples,
+            sample_rate,
+        })
+    }
+
+    fn resampled(self, rate: u32) -> Vec<f32> {
+        if self.sample_rate == rate {
+            return self.samples;
+        }
+        let mut resampler = MonoResampler::new(
+            ResampleQuality::default(),
+            self.sample_rate,
+            rate,
+            CHUNK_SIZE_DEFAULT,
+        );
+        let mut out = resampler.process(&self.samples);
+        out.extend(resampler.flush());
+        // The zero-padded final chunk adds trailing silence
+        out.truncate((self.samples.len() as u64 * rate as u64 / self.sample_rate as u64) as usize);
+        out
+    }
+}
+
+// Standard alphabet, padding optional. Whitespace is skipped so wrapped
+// encodings work
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b if b.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Starts playing `clip` on `device`, returning the stream, which must be
+/// kept alive until `done_tx` reports the end
+pub fn play(
+    device: &cpal::Device,
+    clip: Clip,
+    generation: u64,
+    done_tx: Sender<PlaybackDone>,
+) -> Result<cpal::Stream> {
+    let default_config = device
+        .default_output_config()
+        .map_err(|_| anyhow!("[audio-recorder] No default output config found"))?;
+    let config: StreamConfig = default_config.clone().into();
+    let samples = clip.resampled(config.sample_rate.0);
+
+    let stream = match default_config.sample_format() {
+        SampleFormat::F32 => build::<f32>(device, &config, samples, generation, done_tx),
+        SampleFormat::I16 => build::<i16>(device, &config, samples, generation, done_tx),
+        SampleFormat::U16 => build::<u16>(device, &config, samples, generation, done_tx),
+        SampleFormat::I32 => build::<i32>(device, &config, samples, generation, done_tx),
+        format => Err(anyhow!(
+            "[audio-recorder] Unsupported output sample format {}",
+            format
+        )),
+    }?;
+    stream.play()?;
+    Ok(stream)
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    samples: Vec<f32>,
+    generation: u64,
+    done_tx: Sender<PlaybackDone>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut position = 0;
+    let mut finished = false;
+    let error_tx = done_tx.clone();
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            // Only report once a whole buffer of silence is due, so the tail
+            // handed over last time has been played
+            if position >= samples.len() && !finished {
+                finished = true;
+                let _ = done_tx.send(PlaybackDone {
+                    generation,
+                    error: None,
+                });
+            }
+            for frame in data.chunks_mut(channels) {
+                let sample = samples.get(position).copied().unwrap_or(0.0);
+                position += 1;
+                frame.fill(T::from_sample(sample));
+            }
+        },
+        move |err| {
+            log::error!("Playback stream error: {}", err);
+            let _ = error_tx.send(PlaybackDone {
+                generation,
+                error: Some(err.to_string()),
+            });
+        },
+        None,
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip_decoding_and_resampling() {
+        assert_eq!(decode_base64("AQD//w==").unwrap(), [1, 0, 255, 255]);
+        assert_eq!(decode_base64("AQD/\n/w").unwrap(), [1, 0, 255, 255]);
+        assert!(decode_base64("AQ*D").is_none());
+
+        let clip = Clip::from_pcm16_base64("AEAAwA==", 16000).unwrap();
+        assert_eq!(clip.samples, [0.5, -0.5]);
+
+        let stereo = Clip::from_interleaved(&[0.5, 0.1, -0.5, -0.1], 2, 24000);
+        assert_eq!(stereo.samples, [0.3, -0.3]);
+
+        // Half a second at 16kHz comes out as half a second at 48kHz
+        let clip = Clip {
+            samples: vec![0.1; 8000],
+            sample_rate: 16000,
+        };
+        assert_eq!(clip.resampled(48000).len(), 24000);
+    }
+}
@@ -0,0 +1,242 @@
+//! Routing of captured frames from the stream callback.
+//!
+//! While a recording is active frames go straight to the writer thread.
+//! With pre-roll enabled the stream stays open between recordings and frames
+//! fill a ring buffer instead, which is flushed to the next writer before any
+//! live audio so speech that began just before `start` isn't clipped.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::Sender;
+
+use crate::heartbeat::FrameStats;
+
+/// Fixed-capacity buffer holding the most recent samples, mono or
+/// interleaved
+pub struct PreRollBuffer {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    // Samples per frame, so the buffer only ever drops whole frames and
+    // always starts on the first channel
+    channels: usize,
+}
+
+impl PreRollBuffer {
+    /// Holds `frames` interleaved frames of `channels` samples each
+    pub fn with_channels(frames: usize, channels: usize) -> Self {
+        let channels = channels.max(1);
+        PreRollBuffer {
+            samples: VecDeque::with_capacity(frames * channels),
+            capacity: frames * channels,
+            channels,
+        }
+    }
+
+    pub fn with_duration(sample_rate: u32, channels: usize, duration_ms: u32) -> Self {
+        let frames = (sample_rate as u64 * duration_ms as u64 / 1000) as usize;
+        Self::with_channels(frames, channels)
+    }
+
+    pub fn push(&mut self, data: &[f32]) {
+        if self.capacity == 0 {
+            return;
+        }
+        // Only the tail of an oversized block can survive
+        let skip = data
+            .len()
+            .saturating_sub(self.capacity)
+            .next_multiple_of(self.channels);
+        let data = &data[skip.min(data.len())..];
+        let overflow = (self.samples.len() + data.len())
+            .saturating_sub(self.capacity)
+            .next_multiple_of(self.channels)
+            .min(self.samples.len());
+        self.samples.drain(..overflow);
+        self.samples.extend(data.iter().copied());
+    }
+
+    pub fn take(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+
+    /// Copies the buffered samples, leaving them in place
+    pub fn snapshot(&self) -> Vec<f32> {
+        self.samples.iter().copied().collect()
+    }
+}
+
+/// What the stream callback does when the writer's queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DropPolicy {
+    /// Drop the block rather than ever block the realtime audio thread
+    #[default]
+    Drop,
+    /// Wait up to `BLOCK_BRIEF_TIMEOUT` for room before dropping, trading
+    /// callback latency for fewer gaps
+    BlockBrief,
+}
+
+impl DropPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "drop" => Some(DropPolicy::Drop),
+            "block-brief" => Some(DropPolicy::BlockBrief),
+            _ => None,
+        }
+    }
+}
+
+// Well under a typical 10ms+ callback period at the queue's depth, so a
+// briefly stalled writer gets a chance to catch up
+const BLOCK_BRIEF_TIMEOUT: Duration = Duration::from_millis(5);
+
+pub struct CaptureRoute {
+    audio_tx: Option<Sender<Vec<f32>>>,
+    drop_policy: DropPolicy,
+    preroll: Option<PreRollBuffer>,
+    // Blocks delivered to the current writer, for heartbeats
+    stats: Arc<FrameStats>,
+    // Samples per frame in delivered blocks
+    channels: usize,
+}
+
+impl CaptureRoute {
+    pub fn new(preroll: Option<PreRollBuffer>, channels: usize) -> Self {
+        CaptureRoute {
+            audio_tx: None,
+            drop_policy: DropPolicy::default(),
+            preroll,
+            stats: Arc::new(FrameStats::default()),
+            channels: channels.max(1),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<FrameStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Called from the stream callback with each downmixed (or interleaved
+    /// stereo) block
+    pub fn deliver(&mut self, block: Vec<f32>) {
+        self.stats.record_block(block.len() / self.channels);
+        if let Some(tx) = self.audio_tx.as_ref() {
+            self.stats.record_frame();
+            let sent = match self.drop_policy {
+                DropPolicy::Drop => tx.try_send(block).is_ok(),
+                DropPolicy::BlockBrief => tx.send_timeout(block, BLOCK_BRIEF_TIMEOUT).is_ok(),
+            };
+            if !sent {
+                self.stats.record_drop();
+            }
+        } else if let Some(preroll) = self.preroll.as_mut() {
+            preroll.push(&block);
+        }
+    }
+
+    /// Routes frames to a new writer, sending any buffered pre-roll first
+    pub fn attach(&mut self, tx: Sender<Vec<f32>>, drop_policy: DropPolicy) {
+        if let Some(preroll) = self.preroll.as_mut() {
+            let buffered = preroll.take();
+            if !buffered.is_empty() {
+                let _ = tx.try_send(buffered);
+            }
+        }
+        self.stats.reset();
+        self.drop_policy = drop_policy;
+        self.audio_tx = Some(tx);
+    }
+
+    pub fn detach(&mut self) {
+        self.audio_tx = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preroll_keeps_most_recent_samples() {
+        let mut preroll = PreRollBuffer::with_channels(4, 1);
+        preroll.push(&[1.0, 2.0, 3.0]);
+        preroll.push(&[4.0, 5.0]);
+        assert_eq!(preroll.snapshot(), vec![2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(preroll.take(), vec![2.0, 3.0, 4.0, 5.0]);
+        assert!(preroll.take().is_empty());
+    }
+
+    #[test]
+    fn test_preroll_oversized_block() {
+        let mut preroll = PreRollBuffer::with_channels(2, 1);
+        preroll.push(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(preroll.take(), vec![4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_preroll_duration_capacity() {
+        let mut preroll = PreRollBuffer::with_duration(16000, 1, 150);
+        preroll.push(&vec![0.0; 10000]);
+        assert_eq!(preroll.take().len(), 2400);
+    }
+
+    #[test]
+    fn test_stereo_preroll_keeps_whole_frames() {
+        // 1.5 frames' worth of samples at 1ms rounds down to one whole frame
+        let mut preroll = PreRollBuffer::with_duration(1500, 2, 1);
+        preroll.push(&[1.0, -1.0, 2.0, -2.0, 3.0, -3.0]);
+        assert_eq!(preroll.snapshot(), vec![3.0, -3.0]);
+
+        let mut preroll = PreRollBuffer::with_duration(1000, 2, 3);
+        preroll.push(&[1.0, -1.0, 2.0, -2.0]);
+        preroll.push(&[3.0, -3.0, 4.0, -4.0]);
+        assert_eq!(preroll.take(), vec![2.0, -2.0, 3.0, -3.0, 4.0, -4.0]);
+    }
+
+    #[test]
+    fn test_route_flushes_preroll_before_live_frames() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut route = CaptureRoute::new(Some(PreRollBuffer::with_channels(8, 1)), 1);
+
+        route.deliver(vec![1.0, 2.0]);
+        assert!(rx.try_recv().is_err());
+
+        route.attach(tx, DropPolicy::Drop);
+        route.deliver(vec![3.0]);
+        assert_eq!(rx.try_recv().unwrap(), vec![1.0, 2.0]);
+        assert_eq!(rx.try_recv().unwrap(), vec![3.0]);
+
+        route.detach();
+        route.deliver(vec![4.0]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_route_counts_frames_dropped_on_a_full_queue() {
+        for policy in [DropPolicy::Drop, DropPolicy::BlockBrief] {
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            let mut route = CaptureRoute::new(None, 1);
+            route.attach(tx, policy);
+            route.deliver(vec![1.0]);
+            route.deliver(vec![2.0]);
+            route.deliver(vec![3.0]);
+            assert_eq!(rx.try_recv().unwrap(), vec![1.0]);
+            assert_eq!(route.stats().dropped(), 2);
+        }
+        assert_eq!(
+            DropPolicy::from_name("block-brief"),
+            Some(DropPolicy::BlockBrief)
+        );
+        assert_eq!(DropPolicy::from_name("wait"), None);
+    }
+
+    #[test]
+    fn test_route_without_preroll_drops_idle_frames() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut route = CaptureRoute::new(None, 1);
+        route.deliver(vec![1.0]);
+        route.attach(tx, DropPolicy::Drop);
+        assert!(rx.try_recv().is_err());
+    }
+}
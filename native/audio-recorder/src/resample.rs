@@ -0,0 +1,280 @@
+//! Conversion of captured mono audio to the rate sent to the host.
+//!
+//! `resample_quality` trades CPU for fidelity: linear interpolation is cheap
+//! enough for battery, the FFT resampler is the default, and a long windowed
+//! sinc is there for recordings that are kept.
+
+use rubato::{
+    FftFixedIn, SincFixedIn, SincInterpolationParameters, SincInterpolationType, VecResampler,
+    WindowFunction,
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleQuality {
+    /// Linear interpolation, per block
+    Fast,
+    /// FFT resampler
+    #[default]
+    Balanced,
+    /// Windowed sinc with a long filter
+    High,
+}
+
+impl ResampleQuality {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "fast" => Some(ResampleQuality::Fast),
+            "balanced" => Some(ResampleQuality::Balanced),
+            "high" => Some(ResampleQuality::High),
+            _ => None,
+        }
+    }
+}
+
+/// Input samples per resampler call, unless `start` asks for another size.
+/// Smaller chunks cut latency, larger ones are cheaper per sample
+pub const CHUNK_SIZE_DEFAULT: usize = 1024;
+
+/// Largest chunk `start` may ask for: about a third of a second at 48kHz,
+/// past which the added latency and FFT buffers buy nothing
+pub const CHUNK_SIZE_MAX: usize = 16384;
+
+/// Resampler for one mono stream, buffering input into the fixed chunks the
+/// rubato resamplers need
+pub struct MonoResampler {
+    in_rate: u32,
+    out_rate: u32,
+    // None for the linear path, or when the rates already match
+    resampler: Option<Box<dyn VecResampler<f32>>>,
+    chunk_size: usize,
+    in_buffer: Vec<f32>,
+}
+
+impl MonoResampler {
+    /// Falls back to half the chunk size, then to the next cheaper quality,
+    /// if a resampler can't be built
+    pub fn new(quality: ResampleQuality, in_rate: u32, out_rate: u32, chunk_size: usize) -> Self {
+        let mut resampler = MonoResampler {
+            in_rate,
+            out_rate,
+            resampler: None,
+            chunk_size,
+            in_buffer: Vec::new(),
+        };
+        if in_rate == out_rate {
+            return resampler;
+        }
+        if quality == ResampleQuality::High {
+            match sinc_resampler(in_rate, out_rate, chunk_size) {
+                Ok(sinc) => {
+                    resampler.resampler = Some(sinc);
+                    return resampler;
+                }
+                Err(e) => log::error!("Failed to create sinc resampler ({}), using FFT", e),
+            }
+        }
+        if quality != ResampleQuality::Fast {
+            for chunk_size in [chunk_size, chunk_size / 2] {
+                match FftFixedIn::<f32>::new(in_rate as usize, out_rate as usize, chunk_size, 1, 1)
+                {
+                    Ok(fft) => {
+                        resampler.resampler = Some(Box::new(fft));
+                        resampler.chunk_size = chunk_size;
+                        return resampler;
+                    }
+                    Err(e) => log::error!(
+                        "Failed to create resampler with chunk size {} ({})",
+                        chunk_size,
+                        e
+                    ),
+                }
+            }
+            log::error!("Using linear resampling fallback");
+        }
+        resampler
+    }
+
+    /// Input samples per call of the rubato resampler, None for the linear
+    /// path or when nothing is resampled
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.resampler.as_ref().map(|_| self.chunk_size)
+    }
+
+    /// Resample `frame`, returning whatever output is ready
+    pub fn process(&mut self, frame: &[f32]) -> Vec<f32> {
+        let Some(resampler) = self.resampler.as_mut() else {
+            return linear_resample_mono(frame, self.in_rate, self.out_rate);
+        };
+        self.in_buffer.extend_from_slice(frame);
+        let mut out = Vec::new();
+        while self.in_buffer.len() >= self.chunk_size {
+            let chunk: Vec<f32> = self.in_buffer.drain(..self.chunk_size).collect();
+            match resampler.process(&[chunk], None) {
+                Ok(mut resampled) => {
+                    if !resampled.is_empty() {
+                        out.append(&mut resampled[0]);
+                    }
+                }
+                Err(e) => log::error!("Resampling failed in writer: {}", e),
+            }
+        }
+        out
+    }
+
+    /// Push out anything still buffered, zero-padding the final chunk
+    pub fn flush(&mut self) -> Vec<f32> {
+        let mut out = Vec::new();
+        if let Some(resampler) = self.resampler.as_mut() {
+            while !self.in_buffer.is_empty() {
+                let take = self.in_buffer.len().min(self.chunk_size);
+                let mut chunk: Vec<f32> = self.in_buffer.drain(..take).collect();
+                chunk.resize(self.chunk_size, 0.0);
+                if let Ok(mut resampled) = resampler.process(&[chunk], None) {
+                    if !resampled.is_empty() {
+                        out.append(&mut resampled[0]);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn sinc_resampler(
+    in_rate: u32,
+    out_rate: u32,
+    chunk_size: usize,
+) -> Result<Box<dyn VecResampler<f32>>, rubato::ResamplerConstructionError> {
+    let parameters = SincInterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        oversampling_factor: 256,
+        interpolation: SincInterpolationType::Cubic,
+        window: WindowFunction::BlackmanHarris2,
+    };
+    let sinc = SincFixedIn::<f32>::new(
+        out_rate as f64 / in_rate as f64,
+        1.0,
+        parameters,
+        chunk_size,
+        1,
+    )?;
+    Ok(Box::new(sinc))
+}
+
+// Linear resampling of a single block, used for the fast path and when the
+// FFT resampler isn't available
+fn linear_resample_mono(input: &[f32], in_rate: u32, out_rate: u32) -> Vec<f32> {
+    if input.is_empty() || in_rate == 0 || in_rate == out_rate {
+        return input.to_vec();
+    }
+    let in_len = input.len();
+    let ratio = out_rate as f32 / in_rate as f32;
+    let out_len = ((in_len as f32) * ratio).round().max(0.0) as usize;
+    if out_len <= 1 {
+        return Vec::new();
+    }
+    let step = in_rate as f32 / out_rate as f32;
+    let mut out = Vec::with_capacity(out_len);
+    let mut pos: f32 = 0.0;
+    for _ in 0..out_len {
+        let idx = pos.floor() as usize;
+        if idx >= in_len - 1 {
+            out.push(input[in_len - 1]);
+        } else {
+            let frac = pos - (idx as f32);
+            let a = input[idx];
+            let b = input[idx + 1];
+            out.push(a + (b - a) * frac);
+        }
+        pos += step;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // Two seconds of a 440Hz tone at 48kHz, in 10ms callback-sized blocks
+    fn synthetic_stream() -> Vec<Vec<f32>> {
+        let samples: Vec<f32> = (0..96_000)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48_000.0).sin() * 0.5)
+            .collect();
+        samples.chunks(480).map(|block| block.to_vec()).collect()
+    }
+
+    fn run(quality: ResampleQuality, stream: &[Vec<f32>]) -> (usize, Duration) {
+        let mut resampler = MonoResampler::new(quality, 48_000, 16_000, CHUNK_SIZE_DEFAULT);
+        let started = Instant::now();
+        let mut produced = 0;
+        for block in stream {
+            produced += resampler.process(block).len();
+        }
+        produced += resampler.flush().len();
+        (produced, started.elapsed())
+    }
+
+    #[test]
+    fn test_every_quality_keeps_pace() {
+        let stream = synthetic_stream();
+        // Every path produces about two seconds at 16kHz, give or take
+        // filter delay and the zero-padded final chunk
+        for quality in [
+            ResampleQuality::Fast,
+            ResampleQuality::Balanced,
+            ResampleQuality::High,
+        ] {
+            let (len, _) = run(quality, &stream);
+            assert!(len.abs_diff(32_000) <= CHUNK_SIZE_DEFAULT, "{}", len);
+        }
+    }
+
+    // Wall-clock timing depends on the machine, so this only runs on request
+    // with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_fast_path_is_cheaper() {
+        let stream = synthetic_stream();
+        let (_, fast_time) = run(ResampleQuality::Fast, &stream);
+        let (_, balanced_time) = run(ResampleQuality::Balanced, &stream);
+        let (_, high_time) = run(ResampleQuality::High, &stream);
+        assert!(fast_time * 4 < balanced_time);
+        assert!(fast_time * 4 < high_time);
+    }
+
+    #[test]
+    fn test_chunk_size_is_reported() {
+        let resampler = MonoResampler::new(ResampleQuality::Balanced, 48_000, 16_000, 256);
+        assert_eq!(resampler.chunk_size(), Some(256));
+        let resampler = MonoResampler::new(ResampleQuality::Fast, 48_000, 16_000, 256);
+        assert_eq!(resampler.chunk_size(), None);
+        let resampler = MonoResampler::new(ResampleQuality::High, 16_000, 16_000, 256);
+        assert_eq!(resampler.chunk_size(), None);
+
+        // Output keeps pace with input at any chunk size
+        let mut resampler = MonoResampler::new(ResampleQuality::Balanced, 48_000, 16_000, 4096);
+        let produced: usize = synthetic_stream()
+            .iter()
+            .map(|block| resampler.process(block).len())
+            .sum::<usize>()
+            + resampler.flush().len();
+        assert!(produced.abs_diff(32_000) <= 4096, "{}", produced);
+    }
+
+    #[test]
+    fn test_quality_from_name() {
+        assert_eq!(
+            ResampleQuality::from_name("High"),
+            Some(ResampleQuality::High)
+        );
+        assert_eq!(ResampleQuality::from_name("best"), None);
+        assert_eq!(
+            serde_json::to_value(ResampleQuality::default()).unwrap(),
+            "balanced"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Energy-based voice activity detection.
+//!
+//! Tracks a rolling noise floor and reports speech boundaries using two
+//! thresholds (hysteresis) so the signal hovering around a single level
+//! doesn't flap between states. Speech only ends after the signal has stayed
+//! below the end threshold for the hangover window, which keeps brief pauses
+//! inside a sentence from splitting it.
+
+pub const DEFAULT_HANGOVER_MS: u32 = 800;
+
+// Signal must exceed floor * START_RATIO to begin speech and drop below
+// floor * END_RATIO to count as silence
+const START_RATIO: f32 = 3.0;
+const END_RATIO: f32 = 2.0;
+// Absolute RMS below which nothing is considered speech, regardless of floor
+const MIN_SPEECH_RMS: f32 = 0.01;
+const MIN_NOISE_FLOOR: f32 = 1e-4;
+// Floor rises slowly towards louder background noise and falls quickly
+const FLOOR_RISE: f32 = 0.05;
+const FLOOR_FALL: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VadEvent {
+    SpeechStart,
+    SpeechEnd { silence_ms: u32 },
+}
+
+pub struct VoiceActivityDetector {
+    sample_rate: u32,
+    hangover_ms: u32,
+    noise_floor: f32,
+    in_speech: bool,
+    silence_samples: u64,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(sample_rate: u32, hangover_ms: u32) -> Self {
+        VoiceActivityDetector {
+            sample_rate: sample_rate.max(1),
+            hangover_ms,
+            noise_floor: MIN_NOISE_FLOOR,
+            in_speech: false,
+            silence_samples: 0,
+        }
+    }
+
+    /// Feeds a block of mono samples and returns a boundary event if the
+    /// speech state changed
+    pub fn process(&mut self, samples: &[f32]) -> Option<VadEvent> {
+        if samples.is_empty() {
+            return None;
+        }
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+
+        if self.in_speech {
+            if rms < self.noise_floor * END_RATIO || rms < MIN_SPEECH_RMS {
+                self.silence_samples += samples.len() as u64;
+                let silence_ms = (self.silence_samples * 1000 / self.sample_rate as u64) as u32;
+                if silence_ms >= self.hangover_ms {
+                    self.in_speech = false;
+                    self.silence_samples = 0;
+                    self.update_floor(rms);
+                    return Some(VadEvent::SpeechEnd { silence_ms });
+                }
+            } else {
+                self.silence_samples = 0;
+            }
+            None
+        } else if rms > self.noise_floor * START_RATIO && rms > MIN_SPEECH_RMS {
+            self.in_speech = true;
+            self.silence_samples = 0;
+            Some(VadEvent::SpeechStart)
+        } else {
+            self.update_floor(rms);
+            None
+        }
+    }
+
+    fn update_floor(&mut self, rms: f32) {
+        let rate = if rms < self.noise_floor {
+            FLOOR_FALL
+        } else {
+            FLOOR_RISE
+        };
+        self.noise_floor += (rms - self.noise_floor) * rate;
+        self.noise_floor = self.noise_floor.max(MIN_NOISE_FLOOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    // 10ms block of a constant-amplitude square wave
+    fn block(amplitude: f32) -> Vec<f32> {
+        (0..RATE / 100)
+            .map(|i| if i % 2 == 0 { amplitude } else { -amplitude })
+            .collect()
+    }
+
+    #[test]
+    fn test_vad_silence_emits_nothing() {
+        let mut vad = VoiceActivityDetector::new(RATE, DEFAULT_HANGOVER_MS);
+        for _ in 0..100 {
+            assert_eq!(vad.process(&block(0.001)), None);
+        }
+    }
+
+    #[test]
+    fn test_vad_speech_start_and_end() {
+        let mut vad = VoiceActivityDetector::new(RATE, 100);
+        for _ in 0..20 {
+            vad.process(&block(0.001));
+        }
+        assert_eq!(vad.process(&block(0.3)), Some(VadEvent::SpeechStart));
+
+        let mut end = None;
+        for _ in 0..20 {
+            if let Some(event) = vad.process(&block(0.001)) {
+                end = Some(event);
+                break;
+            }
+        }
+        assert_eq!(end, Some(VadEvent::SpeechEnd { silence_ms: 100 }));
+    }
+
+    #[test]
+    fn test_vad_short_pause_within_hangover_keeps_speech() {
+        let mut vad = VoiceActivityDetector::new(RATE, 300);
+        assert_eq!(vad.process(&block(0.3)), Some(VadEvent::SpeechStart));
+        // 200ms pause is shorter than the 300ms hangover
+        for _ in 0..20 {
+            assert_eq!(vad.process(&block(0.001)), None);
+        }
+        assert_eq!(vad.process(&block(0.3)), None);
+        for _ in 0..29 {
+            assert_eq!(vad.process(&block(0.001)), None);
+        }
+        assert_eq!(
+            vad.process(&block(0.001)),
+            Some(VadEvent::SpeechEnd { silence_ms: 300 })
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Minimal 16-bit PCM WAV writer used to mirror the streamed audio to
+//! disk for debugging. The header is written with placeholder sizes up front
+//! and patched in `finalize` once the total data length is known. A reader
+//! for the same format (and 32-bit float) loads files for playback.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_LEN: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    /// `channels` above 1 expects interleaved samples
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+        let byte_rate = sample_rate * block_align as u32;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&byte_rate.to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { file, data_len: 0 })
+    }
+
+    /// Appends already-encoded little-endian i16 samples
+    pub fn write_pcm(&mut self, pcm: &[u8]) -> io::Result<()> {
+        self.file.write_all(pcm)?;
+        self.data_len = self.data_len.saturating_add(pcm.len() as u32);
+        Ok(())
+    }
+
+    /// Patches the RIFF and data chunk sizes and flushes the file
+    pub fn finalize(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+/// Decoded contents of a WAV file, samples interleaved
+pub struct WavData {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads a 16-bit PCM or 32-bit float WAV file, skipping chunks other than
+/// fmt and data
+pub fn read_wav(path: impl AsRef<Path>) -> io::Result<WavData> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    parse_wav(&bytes)
+}
+
+fn parse_wav(bytes: &[u8]) -> io::Result<WavData> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let len = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let body = offset + 8;
+        // Writers that never patched the size leave it at 0 or past the end
+        let end = if len == 0 {
+            bytes.len()
+        } else {
+            (body + len).min(bytes.len())
+        };
+        match id {
+            b"fmt " if end - body >= 16 => {
+                format = Some((
+                    u16_at(body),
+                    u16_at(body + 2),
+                    u32::from_le_bytes(bytes[body + 4..body + 8].try_into().unwrap()),
+                    u16_at(body + 14),
+                ));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, bits) =
+                    format.ok_or_else(|| invalid("data chunk before fmt chunk"))?;
+                let data = &bytes[body..end];
+                let samples = match (tag, bits) {
+                    (1, 16) => data
+                        .chunks_exact(2)
+                        .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+                        .collect(),
+                    (3, 32) => data
+                        .chunks_exact(4)
+                        .map(|s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                        .collect(),
+                    _ => {
+                        return Err(invalid(
+                            "only 16-bit PCM and 32-bit float WAV are supported",
+                        ))
+                    }
+                };
+                if channels == 0 || sample_rate == 0 {
+                    return Err(invalid("bad WAV format"));
+                }
+                return Ok(WavData {
+                    samples,
+                    sample_rate,
+                    channels,
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        offset = end + (end - body) % 2;
+    }
+    Err(invalid("no data chunk"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_header_is_finalized() {
+        let path =
+            std::env::temp_dir().join(format!("audio-recorder-test-{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path, 16000, 1).unwrap();
+        wav.write_pcm(&[1, 0, 2, 0, 3, 0]).unwrap();
+        wav.write_pcm(&[4, 0]).unwrap();
+        wav.finalize().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 36 + 8);
+        assert_eq!(&bytes[8..12], b"WAVE");
+        assert_eq!(u16::from_le_bytes(bytes[22..24].try_into().unwrap()), 1);
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 16000);
+        assert_eq!(u32::from_le_bytes(bytes[28..32].try_into().unwrap()), 32000);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 8);
+        assert_eq!(&bytes[44..], &[1, 0, 2, 0, 3, 0, 4, 0]);
+
+        let wav = parse_wav(&bytes).unwrap();
+        assert_eq!((wav.sample_rate, wav.channels), (16000, 1));
+        assert_eq!(
+            wav.samples,
+            [1.0 / 32768.0, 2.0 / 32768.0, 3.0 / 32768.0, 4.0 / 32768.0]
+        );
+        assert!(parse_wav(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+}
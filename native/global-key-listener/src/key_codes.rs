@@ -91,10 +91,141 @@ pub fn key_to_code(key: &Key) -> Option<u32> {
         Key::Dot => Some(190),
         Key::Slash => Some(191),
         Key::Function => Some(179),
+        Key::Insert => Some(45),
+        // Numeric keypad. Windows reports keypad Enter as Return, so KpReturn
+        // shares its code
+        Key::KpReturn => Some(13),
+        Key::Kp0 => Some(96),
+        Key::Kp1 => Some(97),
+        Key::Kp2 => Some(98),
+        Key::Kp3 => Some(99),
+        Key::Kp4 => Some(100),
+        Key::Kp5 => Some(101),
+        Key::Kp6 => Some(102),
+        Key::Kp7 => Some(103),
+        Key::Kp8 => Some(104),
+        Key::Kp9 => Some(105),
+        Key::KpMultiply => Some(106),
+        Key::KpPlus => Some(107),
+        Key::KpMinus => Some(109),
+        Key::KpDelete => Some(110),
+        Key::KpDivide => Some(111),
+        // rdev has no media key variants; those arrive as Key::Unknown
         _ => None, // For keys that don't have a standard code
     }
 }
 
+/// Maps a key code back to a Key enum variant, the inverse of `key_to_code`.
+/// Codes shared by several keys resolve to one canonical variant: the left
+/// modifier (ControlLeft, ShiftLeft) and Return rather than KpReturn
+pub fn code_to_key(code: u32) -> Option<Key> {
+    match code {
+        18 => Some(Key::Alt),
+        225 => Some(Key::AltGr),
+        8 => Some(Key::Backspace),
+        20 => Some(Key::CapsLock),
+        17 => Some(Key::ControlLeft),
+        46 => Some(Key::Delete),
+        40 => Some(Key::DownArrow),
+        35 => Some(Key::End),
+        27 => Some(Key::Escape),
+        112 => Some(Key::F1),
+        113 => Some(Key::F2),
+        114 => Some(Key::F3),
+        115 => Some(Key::F4),
+        116 => Some(Key::F5),
+        117 => Some(Key::F6),
+        118 => Some(Key::F7),
+        119 => Some(Key::F8),
+        120 => Some(Key::F9),
+        121 => Some(Key::F10),
+        122 => Some(Key::F11),
+        123 => Some(Key::F12),
+        36 => Some(Key::Home),
+        37 => Some(Key::LeftArrow),
+        91 => Some(Key::MetaLeft),
+        92 => Some(Key::MetaRight),
+        34 => Some(Key::PageDown),
+        33 => Some(Key::PageUp),
+        13 => Some(Key::Return),
+        39 => Some(Key::RightArrow),
+        16 => Some(Key::ShiftLeft),
+        32 => Some(Key::Space),
+        9 => Some(Key::Tab),
+        38 => Some(Key::UpArrow),
+        44 => Some(Key::PrintScreen),
+        145 => Some(Key::ScrollLock),
+        19 => Some(Key::Pause),
+        144 => Some(Key::NumLock),
+        192 => Some(Key::BackQuote),
+        49 => Some(Key::Num1),
+        50 => Some(Key::Num2),
+        51 => Some(Key::Num3),
+        52 => Some(Key::Num4),
+        53 => Some(Key::Num5),
+        54 => Some(Key::Num6),
+        55 => Some(Key::Num7),
+        56 => Some(Key::Num8),
+        57 => Some(Key::Num9),
+        48 => Some(Key::Num0),
+        189 => Some(Key::Minus),
+        187 => Some(Key::Equal),
+        81 => Some(Key::KeyQ),
+        87 => Some(Key::KeyW),
+        69 => Some(Key::KeyE),
+        82 => Some(Key::KeyR),
+        84 => Some(Key::KeyT),
+        89 => Some(Key::KeyY),
+        85 => Some(Key::KeyU),
+        73 => Some(Key::KeyI),
+        79 => Some(Key::KeyO),
+        80 => Some(Key::KeyP),
+        219 => Some(Key::LeftBracket),
+        221 => Some(Key::RightBracket),
+        65 => Some(Key::KeyA),
+        83 => Some(Key::KeyS),
+        68 => Some(Key::KeyD),
+        70 => Some(Key::KeyF),
+        71 => Some(Key::KeyG),
+        72 => Some(Key::KeyH),
+        74 => Some(Key::KeyJ),
+        75 => Some(Key::KeyK),
+        76 => Some(Key::KeyL),
+        186 => Some(Key::SemiColon),
+        222 => Some(Key::Quote),
+        220 => Some(Key::BackSlash),
+        226 => Some(Key::IntlBackslash),
+        90 => Some(Key::KeyZ),
+        88 => Some(Key::KeyX),
+        67 => Some(Key::KeyC),
+        86 => Some(Key::KeyV),
+        66 => Some(Key::KeyB),
+        78 => Some(Key::KeyN),
+        77 => Some(Key::KeyM),
+        188 => Some(Key::Comma),
+        190 => Some(Key::Dot),
+        191 => Some(Key::Slash),
+        179 => Some(Key::Function),
+        45 => Some(Key::Insert),
+        96 => Some(Key::Kp0),
+        97 => Some(Key::Kp1),
+        98 => Some(Key::Kp2),
+        99 => Some(Key::Kp3),
+        100 => Some(Key::Kp4),
+        101 => Some(Key::Kp5),
+        102 => Some(Key::Kp6),
+        103 => Some(Key::Kp7),
+        104 => Some(Key::Kp8),
+        105 => Some(Key::Kp9),
+        106 => Some(Key::KpMultiply),
+        107 => Some(Key::KpPlus),
+        109 => Some(Key::KpMinus),
+        110 => Some(Key::KpDelete),
+        111 => Some(Key::KpDivide),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,4 +282,51 @@ mod tests {
         assert_eq!(key_to_code(&Key::LeftArrow), Some(37));
         assert_eq!(key_to_code(&Key::RightArrow), Some(39));
     }
+
+    #[test]
+    fn test_key_to_code_keypad() {
+        // Test numeric keypad keys
+        assert_eq!(key_to_code(&Key::Kp0), Some(96));
+        assert_eq!(key_to_code(&Key::Kp5), Some(101));
+        assert_eq!(key_to_code(&Key::Kp9), Some(105));
+        assert_eq!(key_to_code(&Key::KpMultiply), Some(106));
+        assert_eq!(key_to_code(&Key::KpPlus), Some(107));
+        assert_eq!(key_to_code(&Key::KpMinus), Some(109));
+        assert_eq!(key_to_code(&Key::KpDelete), Some(110));
+        assert_eq!(key_to_code(&Key::KpDivide), Some(111));
+        assert_eq!(key_to_code(&Key::KpReturn), Some(13));
+        assert_eq!(key_to_code(&Key::Insert), Some(45));
+    }
+
+    #[test]
+    fn test_code_to_key_round_trip() {
+        let keys = [
+            Key::KeyA,
+            Key::KeyZ,
+            Key::Num0,
+            Key::F12,
+            Key::Escape,
+            Key::Space,
+            Key::MetaLeft,
+            Key::MetaRight,
+            Key::Alt,
+            Key::UpArrow,
+            Key::Slash,
+            Key::Function,
+            Key::Kp5,
+            Key::KpDivide,
+        ];
+        for key in keys {
+            assert_eq!(code_to_key(key_to_code(&key).unwrap()), Some(key));
+        }
+    }
+
+    #[test]
+    fn test_code_to_key_shared_codes() {
+        // Keys sharing a code resolve to the canonical variant
+        assert_eq!(code_to_key(17), Some(Key::ControlLeft));
+        assert_eq!(code_to_key(16), Some(Key::ShiftLeft));
+        assert_eq!(code_to_key(13), Some(Key::Return));
+        assert_eq!(code_to_key(0), None);
+    }
 }
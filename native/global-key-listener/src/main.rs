@@ -1,13 +1,19 @@
 use chrono::Utc;
+use ito_ipc::{print_json_line, read_stdin_commands};
 #[cfg(target_os = "windows")]
-use rdev::{grab, simulate, Event, EventType, Key};
+use rdev::{grab, simulate, Button, Event, EventType, GrabError, Key};
 #[cfg(not(target_os = "windows"))]
-use rdev::{grab, Event, EventType, Key};
+use rdev::{grab, Button, Event, EventType, GrabError, Key};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::io::{self, BufRead, Write};
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 mod key_codes;
 
@@ -21,28 +27,736 @@ use objc::{msg_send, sel, sel_impl};
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct HotkeyCombo {
     keys: Vec<String>,
+    // Echoed back in hotkey events so the host can map straight to an action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    // When set, holding the combo this long emits hold-start, and release
+    // emits hold-end or tap depending on whether the threshold was crossed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hold_ms: Option<u64>,
+    // When set, hotkey events also carry the resulting `state`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<HotkeyMode>,
+    // When false the combo is only observed: hotkey events are still sent,
+    // but its keys also reach the focused app
+    #[serde(default = "default_block")]
+    block: bool,
+    // When true, a modifier matches either side of the keyboard, e.g.
+    // ControlLeft in the combo is satisfied by ControlRight too
+    #[serde(default)]
+    side_agnostic: bool,
+    // When set, releasing the combo only ends it once its keys have stayed
+    // up this long, so a keyboard or KVM bounce (a spurious keyup/keydown
+    // pair) doesn't split one press in two
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    release_debounce_ms: Option<u64>,
+}
+
+fn default_block() -> bool {
+    true
+}
+
+/// Keys pressed one after another, e.g. G then D, rather than held together
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SequenceHotkey {
+    sequence: Vec<String>,
+    // Longest gap allowed between consecutive keys before starting over
+    #[serde(default = "default_sequence_interval_ms")]
+    interval_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+}
+
+fn default_sequence_interval_ms() -> u64 {
+    600
+}
+
+/// One entry of register_hotkeys, told apart by `keys` vs `sequence`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum HotkeyEntry {
+    Combo(HotkeyCombo),
+    Sequence(SequenceHotkey),
+}
+
+/// Progress through a registered sequence
+#[derive(Debug)]
+struct SequenceState {
+    hotkey: SequenceHotkey,
+    // Keys of the sequence matched so far
+    step: usize,
+    last_at: Option<Instant>,
+}
+
+impl SequenceState {
+    fn new(hotkey: SequenceHotkey) -> Self {
+        SequenceState {
+            hotkey,
+            step: 0,
+            last_at: None,
+        }
+    }
+
+    /// Moves on for a fresh keydown, returning true when it completes the
+    /// sequence
+    fn advance(&mut self, name: &str, now: Instant) -> bool {
+        let keys = &self.hotkey.sequence;
+        let interval = Duration::from_millis(self.hotkey.interval_ms);
+        if self
+            .last_at
+            .is_some_and(|at| now.saturating_duration_since(at) > interval)
+        {
+            self.step = 0;
+        }
+        self.step = if keys.get(self.step).is_some_and(|key| key == name) {
+            self.step + 1
+        } else if keys.first().is_some_and(|key| key == name) {
+            // A wrong key that starts the sequence over, e.g. G G D
+            1
+        } else {
+            0
+        };
+        self.last_at = Some(now);
+        if !keys.is_empty() && self.step == keys.len() {
+            self.step = 0;
+            return true;
+        }
+        false
+    }
+}
+
+// The left-hand name of a modifier, for side-agnostic matching
+fn modifier_family(name: &str) -> &str {
+    match name {
+        "ControlRight" => "ControlLeft",
+        "ShiftRight" => "ShiftLeft",
+        "MetaRight" => "MetaLeft",
+        "AltGr" => "Alt",
+        _ => name,
+    }
+}
+
+impl HotkeyCombo {
+    fn same_key(&self, a: &str, b: &str) -> bool {
+        if self.side_agnostic {
+            modifier_family(a) == modifier_family(b)
+        } else {
+            a == b
+        }
+    }
+
+    fn has_key(&self, name: &str) -> bool {
+        self.keys.iter().any(|key| self.same_key(key, name))
+    }
+
+    // Matches when ALL its keys are pressed and nothing else is
+    fn matches(&self, pressed: &[String]) -> bool {
+        let all_pressed = self
+            .keys
+            .iter()
+            .all(|key| pressed.iter().any(|p| self.same_key(key, p)));
+        all_pressed && !self.keys.is_empty() && self.keys.len() == pressed.len()
+    }
+}
+
+/// How a hotkey's active/inactive state follows its presses
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HotkeyMode {
+    /// Active exactly while the combo is held
+    Hold,
+    /// Each complete press flips the state
+    Toggle,
+}
+
+// X11 auto-repeat arrives as release/press pairs with no gap, which a person
+// can't produce, so a re-press this soon after release is the same press
+const TOGGLE_REPEAT_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Current state of a toggle-mode hotkey
+#[derive(Debug)]
+struct ToggleState {
+    keys: Vec<String>,
+    on: bool,
+    released_at: Option<Instant>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "command")]
 enum Command {
     #[serde(rename = "register_hotkeys")]
-    RegisterHotkeys { hotkeys: Vec<HotkeyCombo> },
+    RegisterHotkeys { hotkeys: Vec<HotkeyEntry> },
+    #[serde(rename = "clear_hotkeys")]
+    ClearHotkeys,
+    #[serde(rename = "unregister_hotkey")]
+    UnregisterHotkey { keys: Vec<String> },
+    #[serde(rename = "configure_heartbeat")]
+    ConfigureHeartbeat {
+        interval_secs: Option<u64>,
+        enabled: bool,
+    },
+    // Reports the listener's view of which keys are held, for diagnosing a
+    // missed keyup leaving a key stuck
+    #[serde(rename = "get_pressed_keys")]
+    GetPressedKeys { request_id: Option<String> },
+    // Forgets every held key, to recover from a keyup the grab never saw
+    // (focus stolen mid-press, a synthetic paste) without a restart
+    #[serde(rename = "reset_key_state")]
+    ResetKeyState,
+    // Adds dt_ms and proc_us to key events, for checking the grab callback
+    // isn't adding latency on the input path
+    #[serde(rename = "enable_metrics")]
+    EnableMetrics { enabled: bool },
+    // Apps, by name or executable, in which every event passes through
+    // untouched, e.g. games and remote desktop clients. Replaces the list
+    #[serde(rename = "set_suppression_apps")]
+    SetSuppressionApps { apps: Vec<String> },
+    #[serde(rename = "shutdown")]
+    Shutdown,
+    #[serde(rename = "version")]
+    Version,
+}
+
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// How often the focused app is checked against the suppression list. Looking
+// it up is far too slow to do in the grab callback
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runtime heartbeat settings sent from the command thread
+#[derive(Debug, Clone, Copy)]
+struct HeartbeatConfig {
+    // None keeps the current interval
+    interval_secs: Option<u64>,
+    enabled: bool,
+}
+
+/// What the grab callback should do with an event after state is updated
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyAction {
+    /// Let the event through without reporting it (copy shortcuts, to avoid
+    /// feedback loops with selected-text-reader, and key repeat)
+    Ignore,
+    /// Keep the event from reaching the OS without reporting it (key repeat
+    /// while a hotkey is held)
+    Suppress,
+    /// Report the event and let it through
+    Pass,
+    /// Report the event and keep it from reaching the OS
+    Block,
+}
+
+/// Listener state shared between the stdin command thread and the grab
+/// callback
+struct ListenerState {
+    registered_hotkeys: Vec<HotkeyCombo>,
+    registered_sequences: Vec<SequenceState>,
+    // Sequences the current update completed, reported once the lock is
+    // released
+    completed_sequences: Vec<SequenceHotkey>,
+    currently_pressed: Vec<String>,
+    // Combo that last matched, held until one of its keys is released
+    active_hotkey: Option<HotkeyCombo>,
+    // Bumped whenever a combo becomes active so stale hold timers can be
+    // told apart from the current press
+    activation: u64,
+    hold_started: bool,
+    // Hold timer fired while a release was pending. A bounce back turns the
+    // press into a hold then, otherwise it stays a tap
+    hold_due: bool,
+    // Debounced release of the active combo waiting out its timer, told
+    // apart from earlier ones by a count like `activation`
+    pending_release: Option<u64>,
+    releases: u64,
+    // Release timer (id, debounce_ms) the current update started
+    started_release: Option<(u64, u64)>,
+    // Set when the current update's bounce back made the press a hold
+    resumed_hold: bool,
+    // Modifier tracking to detect Cmd+C/Ctrl+C combinations
+    cmd_pressed: bool,
+    ctrl_pressed: bool,
+    copy_in_progress: bool,
+    // Toggle-mode hotkeys, kept across re-registration of the same keys
+    toggles: Vec<ToggleState>,
+    // State a toggle-mode hotkey flipped to during the current update
+    toggled: Option<bool>,
+}
+
+impl ListenerState {
+    const fn new() -> Self {
+        ListenerState {
+            registered_hotkeys: Vec::new(),
+            registered_sequences: Vec::new(),
+            completed_sequences: Vec::new(),
+            currently_pressed: Vec::new(),
+            active_hotkey: None,
+            activation: 0,
+            hold_started: false,
+            hold_due: false,
+            pending_release: None,
+            releases: 0,
+            started_release: None,
+            resumed_hold: false,
+            cmd_pressed: false,
+            ctrl_pressed: false,
+            copy_in_progress: false,
+            toggles: Vec::new(),
+            toggled: None,
+        }
+    }
+
+    // Find the registered hotkey matching the currently pressed keys
+    fn matching_hotkey(&self) -> Option<&HotkeyCombo> {
+        self.registered_hotkeys
+            .iter()
+            .find(|hotkey| hotkey.matches(&self.currently_pressed))
+    }
+
+    // Check if current pressed keys match a registered hotkey that blocks
+    fn should_block(&self) -> bool {
+        self.matching_hotkey().is_some_and(|hotkey| hotkey.block)
+    }
+
+    fn hotkey_count(&self) -> usize {
+        self.registered_hotkeys.len() + self.registered_sequences.len()
+    }
+
+    /// Removes every registered combo made of exactly `keys`, in any order,
+    /// and every sequence of exactly `keys` in that order. Returns how many
+    /// were removed
+    fn unregister(&mut self, keys: &[String]) -> usize {
+        let before = self.hotkey_count();
+        self.registered_hotkeys.retain(|hotkey| {
+            hotkey.keys.len() != keys.len() || !keys.iter().all(|k| hotkey.keys.contains(k))
+        });
+        self.registered_sequences
+            .retain(|sequence| sequence.hotkey.sequence != keys);
+        before - self.hotkey_count()
+    }
+
+    fn set_sequences(&mut self, sequences: Vec<SequenceHotkey>) {
+        self.registered_sequences = sequences.into_iter().map(SequenceState::new).collect();
+    }
+
+    // Every fresh keydown moves each sequence on or starts it over
+    fn advance_sequences(&mut self, name: &str, now: Instant) {
+        for sequence in &mut self.registered_sequences {
+            if sequence.advance(name, now) {
+                self.completed_sequences.push(sequence.hotkey.clone());
+            }
+        }
+    }
+
+    /// Drops toggle states whose hotkey is no longer registered
+    fn prune_toggles(&mut self) {
+        let registered = &self.registered_hotkeys;
+        self.toggles
+            .retain(|toggle| registered.iter().any(|hotkey| hotkey.keys == toggle.keys));
+    }
+
+    /// Flips the toggle for `keys`, or returns None when the press is really
+    /// key repeat following the previous release
+    fn flip_toggle(&mut self, keys: &[String]) -> Option<bool> {
+        let index = match self.toggles.iter().position(|toggle| toggle.keys == keys) {
+            Some(index) => index,
+            None => {
+                self.toggles.push(ToggleState {
+                    keys: keys.to_vec(),
+                    on: false,
+                    released_at: None,
+                });
+                self.toggles.len() - 1
+            }
+        };
+        let toggle = &mut self.toggles[index];
+        if toggle
+            .released_at
+            .is_some_and(|released| released.elapsed() < TOGGLE_REPEAT_DEBOUNCE)
+        {
+            return None;
+        }
+        toggle.on = !toggle.on;
+        Some(toggle.on)
+    }
+
+    fn pressed_keys_message(&self, request_id: Option<String>) -> serde_json::Value {
+        let mut message = json!({
+            "type": "pressed-keys",
+            "keys": self.currently_pressed,
+            "cmd": self.cmd_pressed,
+            "ctrl": self.ctrl_pressed,
+        });
+        if let Some(request_id) = request_id {
+            message["request_id"] = json!(request_id);
+        }
+        message
+    }
+
+    /// Treats every held key as released, ending the active hotkey if any
+    fn reset_keys(&mut self) {
+        for name in std::mem::take(&mut self.currently_pressed) {
+            self.release(&name);
+        }
+        // Nothing can bounce back after a reset
+        self.end_active();
+        self.cmd_pressed = false;
+        self.ctrl_pressed = false;
+        self.copy_in_progress = false;
+    }
+
+    fn uses_function_key(&self) -> bool {
+        self.registered_hotkeys
+            .iter()
+            .any(|hotkey| hotkey.block && hotkey.keys.contains(&"Function".to_string()))
+    }
+
+    fn meta_held(&self) -> bool {
+        self.cmd_pressed
+            || self
+                .currently_pressed
+                .iter()
+                .any(|k| k == "MetaLeft" || k == "MetaRight")
+    }
+
+    // Add a key or button to the pressed set and track which hotkey it completes
+    fn press(&mut self, name: String) {
+        if !self.currently_pressed.contains(&name) {
+            self.currently_pressed.push(name);
+        }
+
+        if let Some(hotkey) = self.matching_hotkey().cloned() {
+            // Back within the debounce, or on to another combo: either way
+            // the pending release is done with
+            self.pending_release = None;
+            if std::mem::take(&mut self.hold_due) && self.active_hotkey.as_ref() == Some(&hotkey) {
+                self.hold_started = true;
+                self.resumed_hold = true;
+            }
+            if self.active_hotkey.as_ref() != Some(&hotkey) {
+                if hotkey.mode == Some(HotkeyMode::Toggle) {
+                    self.toggled = self.flip_toggle(&hotkey.keys);
+                }
+                self.active_hotkey = Some(hotkey);
+                self.activation += 1;
+                self.hold_started = false;
+            }
+        }
+    }
+
+    fn release(&mut self, name: &str) {
+        self.currently_pressed.retain(|k| k != name);
+        let Some(hotkey) = self
+            .active_hotkey
+            .as_ref()
+            .filter(|hotkey| hotkey.has_key(name))
+        else {
+            return;
+        };
+        match hotkey.release_debounce_ms {
+            Some(debounce_ms) if debounce_ms > 0 => {
+                if self.pending_release.is_none() {
+                    self.releases += 1;
+                    self.pending_release = Some(self.releases);
+                    self.started_release = Some((self.releases, debounce_ms));
+                }
+            }
+            _ => self.end_active(),
+        }
+    }
+
+    fn end_active(&mut self) {
+        self.pending_release = None;
+        self.hold_due = false;
+        if let Some(hotkey) = self.active_hotkey.take() {
+            if let Some(toggle) = self.toggles.iter_mut().find(|t| t.keys == hotkey.keys) {
+                toggle.released_at = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Called once a release timer fires. Ends the active combo if that
+    /// release is still pending, i.e. its keys weren't pressed again
+    fn release_elapsed(&mut self, release: u64) {
+        if self.pending_release == Some(release) {
+            self.end_active();
+        }
+    }
+
+    fn key_press(&mut self, key: &Key) -> KeyAction {
+        let key_name = format!("{:?}", key);
+
+        // Check for copy combinations before updating modifier states
+        // Ignore Cmd+C/Cmd+X (macOS) and Ctrl+C/Ctrl+X (Windows/Linux)
+        // combinations to prevent feedback loops with selected-text-reader. The
+        // ito_ipc::synthetic::MARKER on injected macOS events can't replace
+        // this: rdev's Event doesn't carry the CGEvent it was read from
+        if matches!(key, Key::KeyC | Key::KeyX) && (self.cmd_pressed || self.ctrl_pressed) {
+            self.copy_in_progress = true;
+            return KeyAction::Ignore;
+        }
+
+        let name = normalize_key_name(&key_name);
+        // OS auto-repeat of a key that is already down isn't a new press
+        let repeat = self.currently_pressed.contains(&name);
+        if !repeat {
+            self.advance_sequences(&name, Instant::now());
+        }
+
+        // Update pressed keys BEFORE checking if we should block
+        self.press(name);
+
+        // Track modifier key states
+        if matches!(key, Key::MetaLeft | Key::MetaRight) {
+            self.cmd_pressed = true;
+        }
+        if matches!(key, Key::ControlLeft | Key::ControlRight) {
+            self.ctrl_pressed = true;
+        }
+
+        // Block based on exact match of a blocking hotkey, or Unknown(179) if
+        // any blocking hotkey uses Function
+        let block = self.should_block() || (key_name == "Unknown(179)" && self.uses_function_key());
+        match (repeat, block) {
+            (false, false) => KeyAction::Pass,
+            (false, true) => KeyAction::Block,
+            (true, false) => KeyAction::Ignore,
+            (true, true) => KeyAction::Suppress,
+        }
+    }
+
+    /// Called once a hold timer fires. Returns the combo if the press that
+    /// started the timer is still held and hold-start hasn't been reported yet
+    fn hold_elapsed(&mut self, activation: u64) -> Option<HotkeyCombo> {
+        if self.activation != activation || self.hold_started {
+            return None;
+        }
+        let hotkey = self.active_hotkey.clone()?;
+        // The keys went up before the threshold, so this is a tap unless
+        // they bounce back
+        if self.pending_release.is_some() {
+            self.hold_due = true;
+            return None;
+        }
+        self.hold_started = true;
+        Some(hotkey)
+    }
+
+    fn key_release(&mut self, key: &Key) -> KeyAction {
+        let key_name = format!("{:?}", key);
+
+        // Update pressed keys
+        self.release(&normalize_key_name(&key_name));
+
+        // Check for C/X key release while copy is in progress or modifiers are still held
+        if matches!(key, Key::KeyC | Key::KeyX)
+            && (self.copy_in_progress || self.cmd_pressed || self.ctrl_pressed)
+        {
+            self.copy_in_progress = false;
+            return KeyAction::Ignore;
+        }
+
+        // Track modifier key states
+        if matches!(key, Key::MetaLeft | Key::MetaRight) {
+            self.cmd_pressed = false;
+        }
+        if matches!(key, Key::ControlLeft | Key::ControlRight) {
+            self.ctrl_pressed = false;
+        }
+
+        // Always allow key release events through
+        KeyAction::Pass
+    }
+
+    // Mouse buttons take part in hotkeys just like keys, so they can be
+    // combined with modifiers
+    fn button_press(&mut self, name: String) -> KeyAction {
+        // A button no combo uses isn't tracked or reported, or every hotkey
+        // would stop matching exactly while it's held, e.g. mid-drag, here
+        // and in the host's own key set
+        if !self
+            .registered_hotkeys
+            .iter()
+            .any(|hotkey| hotkey.has_key(&name))
+        {
+            return KeyAction::Ignore;
+        }
+        self.press(name);
+        if self.should_block() {
+            KeyAction::Block
+        } else {
+            KeyAction::Pass
+        }
+    }
+
+    fn button_release(&mut self, name: &str) -> KeyAction {
+        // Reported only if its press was, even if the hotkeys changed since
+        let tracked = self.currently_pressed.iter().any(|k| k == name);
+        self.release(name);
+        if tracked {
+            KeyAction::Pass
+        } else {
+            KeyAction::Ignore
+        }
+    }
+}
+
+/// Matched-hotkey state captured around a state update so transitions can be
+/// reported once the lock is released
+struct HotkeyTransition {
+    previous: Option<HotkeyCombo>,
+    hold_started: bool,
+    current: Option<HotkeyCombo>,
+    activation: u64,
+    // New state of a toggle-mode combo that was just pressed
+    toggled: Option<bool>,
+    completed_sequences: Vec<SequenceHotkey>,
+    // Debounced release to end once its timer fires, as (id, debounce_ms)
+    started_release: Option<(u64, u64)>,
+    // Hold-start to report for a press that bounced back past its threshold
+    resumed_hold: bool,
+}
+
+impl HotkeyTransition {
+    /// One event per combo that changed: released for the one that ended and
+    /// pressed for the one that started, each carrying its phase and state
+    /// where it has them
+    fn hotkey_events(&self) -> Vec<(&HotkeyCombo, Vec<(&'static str, &'static str)>)> {
+        let mut events = Vec::new();
+        if self.resumed_hold {
+            if let Some(hotkey) = &self.current {
+                events.push((hotkey, vec![("phase", "hold-start")]));
+            }
+        }
+        if self.previous == self.current {
+            return events;
+        }
+        if let Some(hotkey) = &self.previous {
+            let mut fields = vec![("action", "released")];
+            if hotkey.hold_ms.is_some() {
+                let phase = if self.hold_started { "hold-end" } else { "tap" };
+                fields.push(("phase", phase));
+            }
+            if hotkey.mode == Some(HotkeyMode::Hold) {
+                fields.push(("state", "inactive"));
+            }
+            events.push((hotkey, fields));
+        }
+        if let Some(hotkey) = &self.current {
+            let mut fields = vec![("action", "pressed")];
+            let active = match hotkey.mode {
+                Some(HotkeyMode::Hold) => Some(true),
+                Some(HotkeyMode::Toggle) => self.toggled,
+                None => None,
+            };
+            if let Some(active) = active {
+                fields.push(("state", if active { "active" } else { "inactive" }));
+            }
+            events.push((hotkey, fields));
+        }
+        events
+    }
+}
+
+fn update_state<T>(f: impl FnOnce(&mut ListenerState) -> T) -> (T, HotkeyTransition) {
+    let mut state = state();
+    let previous = state.active_hotkey.clone();
+    let hold_started = state.hold_started;
+    state.toggled = None;
+    let result = f(&mut state);
+    let transition = HotkeyTransition {
+        previous,
+        hold_started,
+        current: state.active_hotkey.clone(),
+        activation: state.activation,
+        toggled: state.toggled.take(),
+        completed_sequences: std::mem::take(&mut state.completed_sequences),
+        started_release: state.started_release.take(),
+        resumed_hold: std::mem::take(&mut state.resumed_hold),
+    };
+    (result, transition)
+}
+
+static STATE: Mutex<ListenerState> = Mutex::new(ListenerState::new());
+// Set once shutdown starts so the grab stops blocking anything while the
+// process exits
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static METRICS_ENABLED: AtomicBool = AtomicBool::new(false);
+// Set while the focused app is on the suppression list
+static FOCUS_SUPPRESSED: AtomicBool = AtomicBool::new(false);
+// When the grab last saw a key or button event, only tracked with metrics on
+static LAST_EVENT_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Timing of one grab callback, reported with its key event
+#[derive(Debug, Clone, Copy)]
+struct EventTiming {
+    started: Instant,
+    // None for the first event after metrics were enabled
+    since_previous: Option<Duration>,
 }
 
-// Global state for registered hotkeys and currently pressed keys
-#[allow(static_mut_refs)]
-static mut REGISTERED_HOTKEYS: Vec<HotkeyCombo> = Vec::new();
-#[allow(static_mut_refs)]
-static mut CURRENTLY_PRESSED: Vec<String> = Vec::new();
+impl EventTiming {
+    fn start() -> Option<Self> {
+        if !METRICS_ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+        let started = Instant::now();
+        let previous = LAST_EVENT_AT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(started);
+        Some(EventTiming {
+            started,
+            since_previous: previous.map(|at| started - at),
+        })
+    }
+}
 
-// Global state for tracking modifier keys to detect Cmd+C/Ctrl+C combinations
-#[allow(static_mut_refs)]
-static mut CMD_PRESSED: bool = false;
-#[allow(static_mut_refs)]
-static mut CTRL_PRESSED: bool = false;
-#[allow(static_mut_refs)]
-static mut COPY_IN_PROGRESS: bool = false;
+fn state() -> MutexGuard<'static, ListenerState> {
+    // A panic while holding the lock shouldn't take the listener down with it
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Name mouse buttons so they can't collide with key names. Side buttons arrive
+// as Button::Unknown with platform-specific numbering, normalized here so
+// Button4/Button5 are back/forward everywhere
+fn button_name(button: &Button) -> String {
+    match button {
+        Button::Left => "ButtonLeft".to_string(),
+        Button::Right => "ButtonRight".to_string(),
+        Button::Middle => "ButtonMiddle".to_string(),
+        Button::Unknown(code) => format!("Button{}", side_button_number(*code)),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn side_button_number(code: u8) -> u32 {
+    // XBUTTON1 = 1, XBUTTON2 = 2
+    code as u32 + 3
+}
+
+#[cfg(target_os = "macos")]
+fn side_button_number(code: u8) -> u32 {
+    // CGMouseButton numbering starts at 0 for the left button
+    code as u32 + 1
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn side_button_number(code: u8) -> u32 {
+    // X11 buttons 4-7 are scroll wheel directions, side buttons are 8 and 9
+    if code >= 8 {
+        code as u32 - 4
+    } else {
+        code as u32
+    }
+}
+
+// Normalize Unknown(179) to Function for detection purposes
+fn normalize_key_name(key_name: &str) -> String {
+    if key_name == "Unknown(179)" {
+        "Function".to_string()
+    } else {
+        key_name.to_string()
+    }
+}
 
 /// Prevents macOS App Nap from suspending this process.
 /// Returns an activity token that must be retained for the entire process
@@ -61,7 +775,7 @@ fn prevent_app_nap() -> id {
 
         let activity: id = msg_send![process_info, beginActivityWithOptions:options reason:reason];
 
-        eprintln!("macOS App Nap prevention enabled for keyboard listener process");
+        log::info!("macOS App Nap prevention enabled for keyboard listener process");
         activity
     }
 }
@@ -72,212 +786,1130 @@ fn prevent_app_nap() {
 }
 
 fn main() {
+    ito_ipc::logging::init("global-key-listener");
+
     // Prevent macOS App Nap from suspending this process
     // Must retain this for the entire process lifetime
     #[allow(clippy::let_unit_value)]
     let _activity = prevent_app_nap();
+    // Held for the lifetime of the process so the heartbeat thread never sees
+    // a disconnect, even after stdin closes
+    let (heartbeat_tx, heartbeat_rx) = mpsc::channel();
+    let (suppression_tx, suppression_rx) = mpsc::channel();
+
     // Spawn a thread to read commands from stdin
-    thread::spawn(|| {
-        let stdin = io::stdin();
-        for line in stdin.lock().lines().map_while(Result::ok) {
-            match serde_json::from_str::<Command>(&line) {
-                Ok(command) => handle_command(command),
-                Err(e) => eprintln!("Error parsing command: {}", e),
-            }
-        }
+    let command_heartbeat_tx = heartbeat_tx.clone();
+    let command_suppression_tx = suppression_tx.clone();
+    thread::spawn(move || {
+        read_stdin_commands(|command: Command| {
+            handle_command(command, &command_heartbeat_tx, &command_suppression_tx);
+            ControlFlow::Continue(())
+        });
     });
 
     // Spawn heartbeat thread
-    thread::spawn(|| {
-        let mut heartbeat_id = 0u64;
-        loop {
-            thread::sleep(Duration::from_secs(10)); // Send heartbeat every 10 seconds
-
-            heartbeat_id += 1;
-            let heartbeat_json = json!({
-                "type": "heartbeat_ping",
-                "id": heartbeat_id.to_string(),
-                "timestamp": Utc::now().to_rfc3339()
-            });
+    thread::spawn(move || heartbeat_loop(heartbeat_rx));
+    thread::spawn(move || suppression_loop(suppression_rx));
 
-            println!("{}", heartbeat_json);
-            io::stdout().flush().unwrap();
-        }
-    });
+    // Commands are handled on the stdin thread, so they work from here on
+    // even though grabbing starts after this
+    print_json_line(&ito_ipc::ready!());
 
     // Start grabbing events
     if let Err(error) = grab(callback) {
-        eprintln!("Error: {:?}", error);
+        log::error!("{:?}", error);
+        // Lets the host tell missing permissions apart from a crash
+        print_json_line(&json!({
+            "type": "fatal",
+            "reason": "grab_failed",
+            "detail": format!("{:?}", error),
+            "likely_permission": likely_permission_error(&error),
+        }));
+        std::process::exit(1);
     }
 }
 
-fn handle_command(command: Command) {
-    match command {
-        Command::RegisterHotkeys { hotkeys } => unsafe {
-            REGISTERED_HOTKEYS = hotkeys.clone();
-            eprintln!("Registered {} hotkeys", REGISTERED_HOTKEYS.len());
-        },
+// The event tap can't be created without Accessibility (or Input Monitoring)
+// access, and rdev doesn't say why it failed, so ask the OS directly
+#[cfg(target_os = "macos")]
+fn likely_permission_error(error: &GrabError) -> bool {
+    #[link(name = "ApplicationServices", kind = "framework")]
+    extern "C" {
+        fn AXIsProcessTrusted() -> bool;
     }
-    io::stdout().flush().unwrap();
+
+    matches!(error, GrabError::EventTapError) || !unsafe { AXIsProcessTrusted() }
 }
 
-// Check if current pressed keys match any registered hotkey
-fn should_block() -> bool {
-    unsafe {
-        // Check each registered hotkey
-        for hotkey in &REGISTERED_HOTKEYS {
-            // A hotkey blocks when ALL its keys are currently pressed
-            let all_pressed = hotkey
-                .keys
-                .iter()
-                .all(|key| CURRENTLY_PRESSED.contains(key));
+// Reading /dev/input needs the input group or root. KeyboardError is what
+// rdev reports when none of the devices could be opened
+#[cfg(target_os = "linux")]
+fn likely_permission_error(error: &GrabError) -> bool {
+    match error {
+        GrabError::IoError(e) => e.kind() == io::ErrorKind::PermissionDenied,
+        GrabError::KeyboardError => true,
+        _ => false,
+    }
+}
 
-            let same_length = hotkey.keys.len() == CURRENTLY_PRESSED.len();
+// Low-level hooks need no permission on Windows
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn likely_permission_error(_error: &GrabError) -> bool {
+    false
+}
 
-            if all_pressed && !hotkey.keys.is_empty() && same_length {
-                return true;
+fn heartbeat_loop(config_rx: Receiver<HeartbeatConfig>) {
+    let mut interval = DEFAULT_HEARTBEAT_INTERVAL;
+    let mut enabled = true;
+    let mut heartbeat_id = 0u64;
+    loop {
+        // Wait out the interval, waking early if the config changes
+        let update = if enabled {
+            config_rx.recv_timeout(interval)
+        } else {
+            config_rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match update {
+            Ok(config) => {
+                if let Some(secs) = config.interval_secs {
+                    interval = Duration::from_secs(secs.max(1));
+                }
+                enabled = config.enabled;
+                continue;
             }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
         }
-        false
+
+        heartbeat_id += 1;
+        let heartbeat_json = json!({
+            "type": "heartbeat_ping",
+            "id": heartbeat_id.to_string(),
+            "timestamp": Utc::now().to_rfc3339()
+        });
+
+        print_json_line(&heartbeat_json);
     }
 }
 
-fn callback(event: Event) -> Option<Event> {
-    match event.event_type {
-        EventType::KeyPress(key) => {
-            let key_name = format!("{:?}", key);
-
-            // Check for copy combinations before updating modifier states
-            // Ignore Cmd+C (macOS) and Ctrl+C (Windows/Linux) combinations to prevent
-            // feedback loops with selected-text-reader
-            if matches!(key, Key::KeyC) && unsafe { CMD_PRESSED || CTRL_PRESSED } {
-                unsafe {
-                    COPY_IN_PROGRESS = true;
-                }
-                // Still pass through the event to the system but don't output it to our
-                // listener
-                return Some(event);
-            }
+// Watch the focused app while there are apps to suppress, flipping
+// FOCUS_SUPPRESSED as focus moves in and out of them
+fn suppression_loop(apps_rx: Receiver<Vec<String>>) {
+    let mut apps: Vec<String> = Vec::new();
+    loop {
+        let update = if apps.is_empty() {
+            apps_rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            apps_rx.recv_timeout(FOCUS_POLL_INTERVAL)
+        };
+        match update {
+            Ok(new_apps) => apps = new_apps,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
 
-            // Update pressed keys BEFORE checking if we should block
-            // Normalize Unknown(179) to Function for detection purposes
-            let normalized_key = if key_name == "Unknown(179)" {
-                "Function".to_string()
-            } else {
-                key_name.clone()
-            };
+        // A focused window that can't be read is never suppressed
+        let suppressed_app = (!apps.is_empty())
+            .then(active_win_pos_rs::get_active_window)
+            .and_then(Result::ok)
+            .filter(|window| app_matches(&apps, &window.app_name, &window.process_path))
+            .map(|window| window.app_name);
+        let suppressed = suppressed_app.is_some();
+        if FOCUS_SUPPRESSED.swap(suppressed, Ordering::SeqCst) == suppressed {
+            continue;
+        }
+        if suppressed {
+            // Keys held while focus moves in never get their keyup seen
+            let (_, transition) = update_state(|state| state.reset_keys());
+            report_hotkey_transition(transition);
+        }
+        log::info!(
+            "Key handling suppressed: {} ({:?})",
+            suppressed,
+            suppressed_app
+        );
+        print_json_line(&json!({
+            "type": "suppression-changed",
+            "suppressed": suppressed,
+            "app": suppressed_app,
+        }));
+    }
+}
 
-            unsafe {
-                if !CURRENTLY_PRESSED.contains(&normalized_key) {
-                    CURRENTLY_PRESSED.push(normalized_key);
-                }
-            }
+// Whether the focused app is on the list, by its name or its executable's,
+// ignoring case and any .exe/.app extension
+fn app_matches(apps: &[String], app_name: &str, process_path: &Path) -> bool {
+    let executable = process_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned());
+    apps.iter().any(|app| {
+        let app = app
+            .strip_suffix(".exe")
+            .or_else(|| app.strip_suffix(".app"))
+            .unwrap_or(app);
+        app.eq_ignore_ascii_case(app_name)
+            || executable
+                .as_deref()
+                .is_some_and(|executable| app.eq_ignore_ascii_case(executable))
+    })
+}
 
-            // Track modifier key states
-            if matches!(key, Key::MetaLeft | Key::MetaRight) {
-                unsafe {
-                    CMD_PRESSED = true;
+fn handle_command(
+    command: Command,
+    heartbeat_tx: &Sender<HeartbeatConfig>,
+    suppression_tx: &Sender<Vec<String>>,
+) {
+    match command {
+        Command::RegisterHotkeys { hotkeys } => {
+            let mut state = state();
+            let mut combos = Vec::new();
+            let mut sequences = Vec::new();
+            for entry in hotkeys {
+                match entry {
+                    HotkeyEntry::Combo(mut hotkey) => {
+                        hotkey.keys = resolve_key_names(hotkey.keys);
+                        combos.push(hotkey);
+                    }
+                    HotkeyEntry::Sequence(mut hotkey) => {
+                        hotkey.sequence = resolve_key_names(hotkey.sequence);
+                        sequences.push(hotkey);
+                    }
                 }
             }
-            if matches!(key, Key::ControlLeft | Key::ControlRight) {
-                unsafe {
-                    CTRL_PRESSED = true;
-                }
+            state.registered_hotkeys = combos;
+            state.set_sequences(sequences);
+            state.prune_toggles();
+            log::info!("Registered {} hotkeys", state.hotkey_count());
+            output_hotkeys_updated(state.hotkey_count());
+        }
+        Command::ClearHotkeys => {
+            let mut state = state();
+            state.registered_hotkeys.clear();
+            state.registered_sequences.clear();
+            state.prune_toggles();
+            log::info!("Cleared all hotkeys");
+            output_hotkeys_updated(0);
+        }
+        Command::UnregisterHotkey { keys } => {
+            let mut state = state();
+            let keys = resolve_key_names(keys);
+            let removed = state.unregister(&keys);
+            state.prune_toggles();
+            log::info!("Unregistered {} hotkeys matching {:?}", removed, keys);
+            output_hotkeys_updated(state.hotkey_count());
+        }
+        Command::ConfigureHeartbeat {
+            interval_secs,
+            enabled,
+        } => {
+            log::info!(
+                "Heartbeat {} (interval {:?}s)",
+                if enabled { "enabled" } else { "disabled" },
+                interval_secs
+            );
+            let _ = heartbeat_tx.send(HeartbeatConfig {
+                interval_secs,
+                enabled,
+            });
+        }
+        Command::GetPressedKeys { request_id } => {
+            print_json_line(&state().pressed_keys_message(request_id));
+        }
+        Command::ResetKeyState => {
+            let (_, transition) = update_state(|state| {
+                log::info!("Resetting key state, held: {:?}", state.currently_pressed);
+                state.reset_keys()
+            });
+            report_hotkey_transition(transition);
+            print_json_line(&json!({ "type": "key-state-reset" }));
+        }
+        Command::EnableMetrics { enabled } => {
+            log::info!(
+                "Key event metrics {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            // A stale previous event would make the first dt_ms meaningless
+            *LAST_EVENT_AT.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            METRICS_ENABLED.store(enabled, Ordering::Relaxed);
+        }
+        Command::SetSuppressionApps { apps } => {
+            log::info!("Suppressing key handling in {:?}", apps);
+            let _ = suppression_tx.send(apps);
+        }
+        Command::Version => print_json_line(&ito_ipc::version_info!()),
+        Command::Shutdown => {
+            log::info!("Shutting down");
+            // rdev has no way to end a grab, so make the callback pass every
+            // event through and exit
+            SHUTTING_DOWN.store(true, Ordering::SeqCst);
+            io::stdout().flush().unwrap();
+            std::process::exit(0);
+        }
+    }
+    io::stdout().flush().unwrap();
+}
+
+// Confirm the new set so the host knows it took effect
+fn output_hotkeys_updated(count: usize) {
+    print_json_line(&json!({ "type": "hotkeys-updated", "count": count }));
+}
+
+// Hotkeys persisted as numeric key codes are accepted in place of key names
+fn resolve_key_names(keys: Vec<String>) -> Vec<String> {
+    keys.into_iter()
+        .map(
+            |key| match key.parse::<u32>().ok().and_then(key_codes::code_to_key) {
+                Some(resolved) => format!("{:?}", resolved),
+                None => key,
+            },
+        )
+        .collect()
+}
+
+fn callback(event: Event) -> Option<Event> {
+    // Pass everything through, neither blocking nor reporting, while
+    // shutting down or while a suppressed app has focus
+    if SHUTTING_DOWN.load(Ordering::SeqCst) || FOCUS_SUPPRESSED.load(Ordering::Relaxed) {
+        return Some(event);
+    }
+
+    let timing = match event.event_type {
+        EventType::KeyPress(_)
+        | EventType::KeyRelease(_)
+        | EventType::ButtonPress(_)
+        | EventType::ButtonRelease(_) => EventTiming::start(),
+        _ => None,
+    };
+
+    match event.event_type {
+        EventType::KeyPress(key) => {
+            let ((action, _meta_held), transition) =
+                update_state(|state| (state.key_press(&key), state.meta_held()));
+
+            if matches!(action, KeyAction::Pass | KeyAction::Block) {
+                output_event("keydown", &key, timing);
+                report_hotkey_transition(transition);
             }
 
-            output_event("keydown", &key);
-
-            // Check if we should block based on exact hotkey match
-            #[allow(clippy::if_same_then_else)]
-            if should_block() {
-                // Windows-specific: Prevent Start menu from opening when Windows key is used in
-                // hotkeys Windows shows the Start menu if it sees "Win down →
-                // Win up" with no other keys in between. By injecting a
-                // harmless key (VK 0xFF), we "poison" the sequence so Windows thinks
-                // it was a combo, not a standalone Windows key press
-                #[cfg(target_os = "windows")]
-                unsafe {
-                    if CMD_PRESSED
-                        || CURRENTLY_PRESSED
-                            .iter()
-                            .any(|k| k == "MetaLeft" || k == "MetaRight")
-                    {
+            match action {
+                // Still pass through the event to the system but don't output it to our
+                // listener
+                KeyAction::Ignore => Some(event),
+                KeyAction::Pass => Some(event), // Let it through
+                KeyAction::Suppress => None,
+                // Only blocking hotkeys get here; a pass-through combo
+                // reaches Windows whole, so it never looks like a lone Win
+                KeyAction::Block => {
+                    // Windows-specific: Prevent Start menu from opening when Windows key is used in
+                    // hotkeys Windows shows the Start menu if it sees "Win down →
+                    // Win up" with no other keys in between. By injecting a
+                    // harmless key (VK 0xFF), we "poison" the sequence so Windows thinks
+                    // it was a combo, not a standalone Windows key press
+                    #[cfg(target_os = "windows")]
+                    if _meta_held {
                         // VK 0xFF is documented as "no mapping" - a valid key code with no function
                         let _ = simulate(&EventType::KeyPress(Key::Unknown(0xFF)));
                         let _ = simulate(&EventType::KeyRelease(Key::Unknown(0xFF)));
                     }
+                    None // Block the event from reaching the OS
                 }
-                None // Block the event from reaching the OS
-            } else if key_name == "Unknown(179)"
-                && unsafe {
-                    REGISTERED_HOTKEYS
-                        .iter()
-                        .any(|hotkey| hotkey.keys.contains(&"Function".to_string()))
-                }
-            {
-                None // Block Unknown(179) if any hotkey uses Function
-            } else {
-                Some(event) // Let it through
             }
         }
         EventType::KeyRelease(key) => {
-            let key_name = format!("{:?}", key);
-
-            // Normalize Unknown(179) to Function for detection purposes
-            let normalized_key = if key_name == "Unknown(179)" {
-                "Function".to_string()
-            } else {
-                key_name.clone()
-            };
-
-            // Update pressed keys
-            unsafe {
-                CURRENTLY_PRESSED.retain(|k| k != &normalized_key);
+            let (action, transition) = update_state(|state| state.key_release(&key));
+            if action == KeyAction::Pass {
+                output_event("keyup", &key, timing);
             }
-
-            // Check for C key release while copy is in progress or modifiers are still held
-            if matches!(key, Key::KeyC)
-                && unsafe { COPY_IN_PROGRESS || CMD_PRESSED || CTRL_PRESSED }
-            {
-                unsafe {
-                    COPY_IN_PROGRESS = false;
-                }
-                // Don't output this C key release event
-                return Some(event);
+            report_hotkey_transition(transition);
+            // Always allow key release events through
+            Some(event)
+        }
+        EventType::ButtonPress(button) => {
+            let name = button_name(&button);
+            let (action, transition) = update_state(|state| state.button_press(name.clone()));
+            if action != KeyAction::Ignore {
+                output_named_event("keydown", &name, None, timing);
             }
-
-            // Track modifier key states
-            if matches!(key, Key::MetaLeft | Key::MetaRight) {
-                unsafe {
-                    CMD_PRESSED = false;
-                }
+            report_hotkey_transition(transition);
+            if action == KeyAction::Block {
+                None
+            } else {
+                Some(event)
             }
-            if matches!(key, Key::ControlLeft | Key::ControlRight) {
-                unsafe {
-                    CTRL_PRESSED = false;
-                }
+        }
+        EventType::ButtonRelease(button) => {
+            let name = button_name(&button);
+            let (action, transition) = update_state(|state| state.button_release(&name));
+            if action != KeyAction::Ignore {
+                output_named_event("keyup", &name, None, timing);
             }
-
-            output_event("keyup", &key);
-
-            // Always allow key release events through
+            report_hotkey_transition(transition);
             Some(event)
         }
         _ => Some(event), // Allow all other events
     }
 }
 
-fn output_event(event_type: &str, key: &Key) {
+fn output_event(event_type: &str, key: &Key, timing: Option<EventTiming>) {
+    output_named_event(
+        event_type,
+        &format!("{:?}", key),
+        key_codes::key_to_code(key),
+        timing,
+    );
+}
+
+fn output_named_event(
+    event_type: &str,
+    key_name: &str,
+    raw_code: Option<u32>,
+    timing: Option<EventTiming>,
+) {
     let timestamp = Utc::now().to_rfc3339();
-    let key_name = format!("{:?}", key);
 
-    let event_json = json!({
+    let mut event_json = json!({
         "type": event_type,
         "key": key_name,
         "timestamp": timestamp,
-        "raw_code": key_codes::key_to_code(key)
+        "raw_code": raw_code
     });
+    if let Some(timing) = timing {
+        // Processing covers state updates and hotkey matching, up to here
+        event_json["proc_us"] = json!(timing.started.elapsed().as_micros() as u64);
+        event_json["dt_ms"] = json!(timing.since_previous.map(|dt| dt.as_secs_f64() * 1000.0));
+    }
 
-    println!("{}", event_json);
-    io::stdout().flush().unwrap();
+    print_json_line(&event_json);
+}
+
+// Report a change in the matched hotkey, starting the hold timer for a newly
+// pressed combo that has one, and any completed sequences
+fn report_hotkey_transition(transition: HotkeyTransition) {
+    for sequence in &transition.completed_sequences {
+        output_sequence_event(sequence);
+    }
+    if let Some((release, debounce_ms)) = transition.started_release {
+        spawn_release_timer(release, debounce_ms);
+    }
+    for (hotkey, fields) in transition.hotkey_events() {
+        output_hotkey_event(hotkey, &fields);
+    }
+    if transition.previous != transition.current {
+        if let Some(hold_ms) = transition.current.as_ref().and_then(|h| h.hold_ms) {
+            spawn_hold_timer(transition.activation, hold_ms);
+        }
+    }
+}
+
+// Emit hold-start if the combo is still held once the threshold passes
+fn spawn_hold_timer(activation: u64, hold_ms: u64) {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(hold_ms));
+        // Output while holding the lock so a concurrent release can't report
+        // hold-end before hold-start
+        let mut state = state();
+        if let Some(hotkey) = state.hold_elapsed(activation) {
+            output_hotkey_event(&hotkey, &[("phase", "hold-start")]);
+        }
+    });
+}
+
+// End the combo if its keys are still up once the debounce passes
+fn spawn_release_timer(release: u64, debounce_ms: u64) {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(debounce_ms));
+        let (_, transition) = update_state(|state| state.release_elapsed(release));
+        report_hotkey_transition(transition);
+    });
+}
+
+fn output_hotkey_event(hotkey: &HotkeyCombo, fields: &[(&str, &str)]) {
+    let mut event_json = json!({
+        "type": "hotkey",
+        "keys": hotkey.keys,
+        "timestamp": Utc::now().to_rfc3339()
+    });
+    for (field, value) in fields {
+        event_json[*field] = json!(value);
+    }
+    if let Some(id) = &hotkey.id {
+        event_json["id"] = json!(id);
+    }
+
+    // Only hotkey transitions are logged, never individual keys, so the log
+    // can't capture what the user types
+    log::debug!("Hotkey {:?} {:?}", hotkey.keys, fields);
+    print_json_line(&event_json);
+}
+
+fn output_sequence_event(hotkey: &SequenceHotkey) {
+    let mut event_json = json!({
+        "type": "hotkey",
+        "sequence": hotkey.sequence,
+        "timestamp": Utc::now().to_rfc3339()
+    });
+    if let Some(id) = &hotkey.id {
+        event_json["id"] = json!(id);
+    }
+
+    log::debug!("Hotkey sequence {:?} completed", hotkey.sequence);
+    print_json_line(&event_json);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn combo(keys: &[&str]) -> HotkeyCombo {
+        HotkeyCombo {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            id: None,
+            hold_ms: None,
+            mode: None,
+            block: true,
+            side_agnostic: false,
+            release_debounce_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_exact_hotkey_match_blocks() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["ControlLeft", "Space"])];
+
+        assert_eq!(state.key_press(&Key::ControlLeft), KeyAction::Pass);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        // An extra key breaks the exact match
+        assert_eq!(state.key_press(&Key::KeyA), KeyAction::Pass);
+        assert_eq!(state.key_release(&Key::KeyA), KeyAction::Pass);
+        assert!(state.should_block());
+    }
+
+    #[test]
+    fn test_active_hotkey_tracks_press_and_release() {
+        let mut state = ListenerState::new();
+        let mut toggle = combo(&["MetaLeft", "KeyD"]);
+        toggle.id = Some("toggle".to_string());
+        state.registered_hotkeys = vec![combo(&["ControlLeft", "Space"]), toggle.clone()];
+
+        state.key_press(&Key::MetaLeft);
+        assert_eq!(state.active_hotkey, None);
+        state.key_press(&Key::KeyD);
+        assert_eq!(state.active_hotkey, Some(toggle.clone()));
+
+        // Key repeat and extra keys keep the combo active until one of its keys goes up
+        state.key_press(&Key::KeyD);
+        state.key_press(&Key::ShiftLeft);
+        state.key_release(&Key::ShiftLeft);
+        assert_eq!(state.active_hotkey, Some(toggle));
+        state.key_release(&Key::MetaLeft);
+        assert_eq!(state.active_hotkey, None);
+    }
+
+    #[test]
+    fn test_key_repeat_is_reported_once() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["ControlLeft", "Space"])];
+
+        assert_eq!(state.key_press(&Key::KeyA), KeyAction::Pass);
+        assert_eq!(state.key_press(&Key::KeyA), KeyAction::Ignore);
+        assert_eq!(state.key_press(&Key::KeyA), KeyAction::Ignore);
+        state.key_release(&Key::KeyA);
+        assert_eq!(state.key_press(&Key::KeyA), KeyAction::Pass);
+        state.key_release(&Key::KeyA);
+
+        // Repeats of a blocked combo's key stay blocked, but aren't reported
+        state.key_press(&Key::ControlLeft);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        let activation = state.activation;
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Suppress);
+        assert_eq!(state.activation, activation);
+        state.key_release(&Key::Space);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+    }
+
+    #[test]
+    fn test_hold_timer_only_fires_for_current_press() {
+        let mut state = ListenerState::new();
+        let mut talk = combo(&["Function"]);
+        talk.hold_ms = Some(300);
+        state.registered_hotkeys = vec![talk.clone()];
+
+        // Released before the timer fired: tap
+        state.key_press(&Key::Unknown(179));
+        let first = state.activation;
+        state.key_release(&Key::Unknown(179));
+        assert_eq!(state.hold_elapsed(first), None);
+        assert!(!state.hold_started);
+
+        // Held past the threshold: hold-start once, then hold-end on release
+        state.key_press(&Key::Unknown(179));
+        let second = state.activation;
+        assert_ne!(first, second);
+        assert_eq!(state.hold_elapsed(first), None);
+        assert_eq!(state.hold_elapsed(second), Some(talk));
+        assert_eq!(state.hold_elapsed(second), None);
+        assert!(state.hold_started);
+
+        // Blocking is unaffected by hold_ms
+        assert!(state.should_block());
+    }
+
+    #[test]
+    fn test_release_debounce_spans_a_bounce() {
+        let mut state = ListenerState::new();
+        let mut talk = combo(&["ControlLeft", "Space"]);
+        talk.mode = Some(HotkeyMode::Hold);
+        talk.release_debounce_ms = Some(30);
+        state.registered_hotkeys = vec![talk.clone()];
+
+        state.key_press(&Key::ControlLeft);
+        state.key_press(&Key::Space);
+        let activation = state.activation;
+
+        // A keyup/keydown bounce inside the window: still the same press
+        state.key_release(&Key::Space);
+        assert_eq!(state.active_hotkey, Some(talk.clone()));
+        let (bounce, debounce_ms) = state.started_release.take().unwrap();
+        assert_eq!(debounce_ms, 30);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        state.release_elapsed(bounce);
+        assert_eq!(state.active_hotkey, Some(talk));
+        assert_eq!(state.activation, activation);
+
+        // A real release ends it once the timer fires
+        state.key_release(&Key::Space);
+        let (release, _) = state.started_release.take().unwrap();
+        assert_ne!(bounce, release);
+        state.release_elapsed(release);
+        assert_eq!(state.active_hotkey, None);
+    }
+
+    #[test]
+    fn test_release_debounce_decides_tap_at_first_release() {
+        let mut state = ListenerState::new();
+        let mut talk = combo(&["Function"]);
+        talk.hold_ms = Some(300);
+        talk.release_debounce_ms = Some(30);
+        state.registered_hotkeys = vec![talk.clone()];
+
+        // Released before the threshold, with the hold timer firing inside
+        // the debounce: still a tap
+        state.key_press(&Key::Unknown(179));
+        let tap = state.activation;
+        state.key_release(&Key::Unknown(179));
+        let (release, _) = state.started_release.take().unwrap();
+        assert_eq!(state.hold_elapsed(tap), None);
+        state.release_elapsed(release);
+        assert_eq!(state.active_hotkey, None);
+        assert!(!state.hold_started);
+
+        // A bounce that spans the threshold comes back as a hold
+        state.key_press(&Key::Unknown(179));
+        let held = state.activation;
+        state.key_release(&Key::Unknown(179));
+        state.started_release = None;
+        assert_eq!(state.hold_elapsed(held), None);
+        state.key_press(&Key::Unknown(179));
+        assert!(state.hold_started);
+        assert!(std::mem::take(&mut state.resumed_hold));
+        assert_eq!(state.hold_elapsed(held), None);
+    }
+
+    #[test]
+    fn test_hotkey_transition_is_one_event_per_combo() {
+        let mut talk = combo(&["Function"]);
+        talk.mode = Some(HotkeyMode::Hold);
+        talk.hold_ms = Some(300);
+        let transition = |previous, hold_started, current| HotkeyTransition {
+            previous,
+            hold_started,
+            current,
+            activation: 0,
+            toggled: None,
+            completed_sequences: Vec::new(),
+            started_release: None,
+            resumed_hold: false,
+        };
+
+        let pressed = transition(None, false, Some(talk.clone()));
+        assert_eq!(
+            pressed.hotkey_events(),
+            vec![(&talk, vec![("action", "pressed"), ("state", "active")])]
+        );
+        let released = transition(Some(talk.clone()), true, None);
+        assert_eq!(
+            released.hotkey_events(),
+            vec![(
+                &talk,
+                vec![
+                    ("action", "released"),
+                    ("phase", "hold-end"),
+                    ("state", "inactive")
+                ]
+            )]
+        );
+
+        // Moving straight to another combo ends one and starts the other
+        let other = combo(&["ControlLeft", "Space"]);
+        let switched = transition(Some(talk.clone()), false, Some(other.clone()));
+        assert_eq!(
+            switched.hotkey_events(),
+            vec![
+                (
+                    &talk,
+                    vec![
+                        ("action", "released"),
+                        ("phase", "tap"),
+                        ("state", "inactive")
+                    ]
+                ),
+                (&other, vec![("action", "pressed")]),
+            ]
+        );
+        assert!(transition(None, false, None).hotkey_events().is_empty());
+    }
+
+    #[test]
+    fn test_toggle_mode_flips_once_per_press() {
+        let mut state = ListenerState::new();
+        let mut toggle = combo(&["MetaLeft", "KeyD"]);
+        toggle.mode = Some(HotkeyMode::Toggle);
+        state.registered_hotkeys = vec![toggle];
+
+        let press = |state: &mut ListenerState| {
+            state.toggled = None;
+            state.key_press(&Key::MetaLeft);
+            state.key_press(&Key::KeyD);
+            state.toggled.take()
+        };
+
+        assert_eq!(press(&mut state), Some(true));
+        // Held key repeat doesn't re-trigger the combo
+        state.toggled = None;
+        state.key_press(&Key::KeyD);
+        assert_eq!(state.toggled, None);
+        // Nor does an X11-style release/press repeat pair
+        state.key_release(&Key::KeyD);
+        state.key_press(&Key::KeyD);
+        assert_eq!(state.toggled, None);
+        state.key_release(&Key::KeyD);
+        state.key_release(&Key::MetaLeft);
+
+        thread::sleep(TOGGLE_REPEAT_DEBOUNCE * 2);
+        assert_eq!(press(&mut state), Some(false));
+        state.key_release(&Key::KeyD);
+        state.key_release(&Key::MetaLeft);
+
+        // State survives re-registering the same keys, not removal
+        state.prune_toggles();
+        assert_eq!(state.toggles.len(), 1);
+        state.registered_hotkeys.clear();
+        state.prune_toggles();
+        assert!(state.toggles.is_empty());
+
+        let hotkey: HotkeyCombo =
+            serde_json::from_str(r#"{"keys":["Function"],"mode":"hold"}"#).unwrap();
+        assert_eq!(hotkey.mode, Some(HotkeyMode::Hold));
+    }
+
+    #[test]
+    fn test_pass_through_hotkey_is_reported_but_not_blocked() {
+        let mut state = ListenerState::new();
+        let mut refresh = combo(&["F5"]);
+        refresh.block = false;
+        let mut talk = combo(&["Function"]);
+        talk.block = false;
+        state.registered_hotkeys = vec![refresh.clone(), talk];
+
+        assert_eq!(state.key_press(&Key::F5), KeyAction::Pass);
+        assert_eq!(state.active_hotkey, Some(refresh));
+        // Repeats reach the app too
+        assert_eq!(state.key_press(&Key::F5), KeyAction::Ignore);
+        state.key_release(&Key::F5);
+        assert_eq!(state.active_hotkey, None);
+
+        // The raw fn code is only swallowed for blocking Function hotkeys
+        assert_eq!(state.key_press(&Key::Unknown(179)), KeyAction::Pass);
+
+        let hotkey: HotkeyCombo = serde_json::from_str(r#"{"keys":["F5"]}"#).unwrap();
+        assert!(hotkey.block);
+        let hotkey: HotkeyCombo = serde_json::from_str(r#"{"keys":["F5"],"block":false}"#).unwrap();
+        assert!(!hotkey.block);
+    }
+
+    #[test]
+    fn test_side_agnostic_modifiers() {
+        let mut state = ListenerState::new();
+        let mut dictate = combo(&["ControlLeft", "Space"]);
+        dictate.side_agnostic = true;
+        let mut strict = combo(&["ShiftLeft", "KeyA"]);
+        strict.id = Some("strict".to_string());
+        state.registered_hotkeys = vec![dictate.clone(), strict];
+
+        // The other side's Control completes the combo and releasing it ends it
+        state.key_press(&Key::ControlRight);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        assert_eq!(state.active_hotkey, Some(dictate.clone()));
+        state.key_release(&Key::ControlRight);
+        assert_eq!(state.active_hotkey, None);
+        state.key_release(&Key::Space);
+
+        // The registered side still works
+        state.key_press(&Key::ControlLeft);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        state.key_release(&Key::Space);
+        state.key_release(&Key::ControlLeft);
+
+        // Both Controls at once is an extra key, not a match
+        state.key_press(&Key::ControlLeft);
+        state.key_press(&Key::ControlRight);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Pass);
+        state.reset_keys();
+
+        // Combos without the flag keep matching exactly
+        state.key_press(&Key::ShiftRight);
+        assert_eq!(state.key_press(&Key::KeyA), KeyAction::Pass);
+        state.reset_keys();
+
+        let hotkey: HotkeyCombo =
+            serde_json::from_str(r#"{"keys":["AltGr","KeyD"],"side_agnostic":true}"#).unwrap();
+        assert!(hotkey.matches(&["Alt".to_string(), "KeyD".to_string()]));
+    }
+
+    #[test]
+    fn test_mouse_button_combo_with_modifier() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["ControlLeft", "Button4"])];
+
+        assert_eq!(state.button_press("Button4".to_string()), KeyAction::Pass);
+        state.button_release("Button4");
+
+        state.key_press(&Key::ControlLeft);
+        assert_eq!(state.button_press("Button4".to_string()), KeyAction::Block);
+        assert_eq!(
+            state.active_hotkey,
+            Some(combo(&["ControlLeft", "Button4"]))
+        );
+        assert_eq!(state.button_release("Button4"), KeyAction::Pass);
+        assert_eq!(state.active_hotkey, None);
+        assert_eq!(state.currently_pressed, vec!["ControlLeft".to_string()]);
+    }
+
+    #[test]
+    fn test_keyboard_combo_while_button_held() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["ControlLeft", "Space"])];
+
+        assert_eq!(
+            state.button_press("ButtonLeft".to_string()),
+            KeyAction::Ignore
+        );
+        state.key_press(&Key::ControlLeft);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        assert_eq!(state.active_hotkey, Some(combo(&["ControlLeft", "Space"])));
+        assert_eq!(state.button_release("ButtonLeft"), KeyAction::Ignore);
+        assert_eq!(state.active_hotkey, Some(combo(&["ControlLeft", "Space"])));
+    }
+
+    #[test]
+    fn test_button_names() {
+        assert_eq!(button_name(&Button::Left), "ButtonLeft");
+        assert_eq!(button_name(&Button::Middle), "ButtonMiddle");
+        #[cfg(target_os = "linux")]
+        assert_eq!(button_name(&Button::Unknown(8)), "Button4");
+        #[cfg(target_os = "windows")]
+        assert_eq!(button_name(&Button::Unknown(2)), "Button5");
+    }
+
+    #[test]
+    fn test_hotkey_id_is_optional() {
+        let hotkey: HotkeyCombo = serde_json::from_str(r#"{"keys":["Function"]}"#).unwrap();
+        assert_eq!(hotkey.id, None);
+        let hotkey: HotkeyCombo =
+            serde_json::from_str(r#"{"keys":["Function"],"id":"push-to-talk"}"#).unwrap();
+        assert_eq!(hotkey.id.as_deref(), Some("push-to-talk"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_grab_permission_errors() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(likely_permission_error(&GrabError::IoError(denied)));
+        assert!(!likely_permission_error(&GrabError::MissingDisplayError));
+    }
+
+    #[test]
+    fn test_copy_shortcut_is_ignored() {
+        let mut state = ListenerState::new();
+        state.key_press(&Key::MetaLeft);
+        assert_eq!(state.key_press(&Key::KeyC), KeyAction::Ignore);
+        assert_eq!(state.key_release(&Key::KeyC), KeyAction::Ignore);
+        assert_eq!(state.key_press(&Key::KeyX), KeyAction::Ignore);
+        assert_eq!(state.key_release(&Key::KeyX), KeyAction::Ignore);
+        assert_eq!(state.key_release(&Key::MetaLeft), KeyAction::Pass);
+        assert!(state.currently_pressed.is_empty());
+    }
+
+    #[test]
+    fn test_pressed_keys_message() {
+        let mut state = ListenerState::new();
+        state.key_press(&Key::MetaLeft);
+        state.key_press(&Key::KeyA);
+        assert_eq!(
+            state.pressed_keys_message(Some("q1".to_string())),
+            json!({
+                "type": "pressed-keys",
+                "keys": ["MetaLeft", "KeyA"],
+                "cmd": true,
+                "ctrl": false,
+                "request_id": "q1",
+            })
+        );
+        state.key_release(&Key::KeyA);
+        state.key_release(&Key::MetaLeft);
+        assert_eq!(state.pressed_keys_message(None)["keys"], json!([]));
+    }
+
+    #[test]
+    fn test_reset_clears_stuck_keys() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["MetaLeft", "KeyK"])];
+        state.key_press(&Key::MetaLeft);
+        state.key_press(&Key::KeyK);
+        state.key_press(&Key::KeyC);
+        assert!(state.active_hotkey.is_some());
+
+        // The keyups never arrive
+        state.reset_keys();
+        assert!(state.currently_pressed.is_empty());
+        assert!(state.active_hotkey.is_none());
+        assert!(!state.cmd_pressed && !state.copy_in_progress);
+
+        // The hotkey works again from a clean slate
+        state.key_press(&Key::MetaLeft);
+        assert_eq!(state.key_press(&Key::KeyK), KeyAction::Block);
+    }
+
+    #[test]
+    fn test_function_key_is_normalized() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["Function"])];
+        assert_eq!(state.key_press(&Key::Unknown(179)), KeyAction::Block);
+        assert_eq!(state.currently_pressed, vec!["Function".to_string()]);
+        state.key_release(&Key::Unknown(179));
+        assert!(state.currently_pressed.is_empty());
+    }
+
+    #[test]
+    fn test_clear_and_unregister_hotkeys() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![
+            combo(&["ControlLeft", "Space"]),
+            combo(&["MetaLeft", "KeyD"]),
+            combo(&["Function"]),
+        ];
+
+        let keys = vec!["Space".to_string(), "ControlLeft".to_string()];
+        assert_eq!(state.unregister(&keys), 1);
+        assert_eq!(state.unregister(&keys), 0);
+        assert_eq!(state.registered_hotkeys.len(), 2);
+
+        state.key_press(&Key::ControlLeft);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Pass);
+        state.key_release(&Key::Space);
+        state.key_release(&Key::ControlLeft);
+
+        state.registered_hotkeys.clear();
+        assert_eq!(state.key_press(&Key::Unknown(179)), KeyAction::Pass);
+        assert!(!state.should_block());
+    }
+
+    fn sequence(keys: &[&str]) -> SequenceHotkey {
+        SequenceHotkey {
+            sequence: keys.iter().map(|k| k.to_string()).collect(),
+            interval_ms: 600,
+            id: None,
+        }
+    }
+
+    fn tap(state: &mut ListenerState, key: Key) -> KeyAction {
+        let action = state.key_press(&key);
+        state.key_release(&key);
+        action
+    }
+
+    #[test]
+    fn test_sequence_resets_after_interval() {
+        let mut gd = SequenceState::new(sequence(&["KeyG", "KeyD"]));
+        let start = Instant::now();
+
+        assert!(!gd.advance("KeyG", start));
+        assert!(!gd.advance("KeyD", start + Duration::from_millis(601)));
+        // The late D didn't restart anything, so G is needed again
+        assert!(!gd.advance("KeyD", start + Duration::from_millis(700)));
+        assert!(!gd.advance("KeyG", start + Duration::from_millis(800)));
+        assert!(gd.advance("KeyD", start + Duration::from_millis(1400)));
+        assert_eq!(gd.step, 0);
+    }
+
+    #[test]
+    fn test_sequences_interleave_with_combos() {
+        let mut state = ListenerState::new();
+        state.registered_hotkeys = vec![combo(&["ControlLeft", "Space"])];
+        state.set_sequences(vec![
+            sequence(&["KeyG", "KeyD"]),
+            sequence(&["KeyG", "KeyG"]),
+        ]);
+
+        // Sequence keys pass through, and G G completes only its own sequence
+        assert_eq!(tap(&mut state, Key::KeyG), KeyAction::Pass);
+        tap(&mut state, Key::KeyG);
+        assert_eq!(
+            std::mem::take(&mut state.completed_sequences),
+            vec![sequence(&["KeyG", "KeyG"])]
+        );
+        // A repeat isn't a second press, and a G restarts G D
+        state.key_press(&Key::KeyG);
+        state.key_press(&Key::KeyG);
+        state.key_release(&Key::KeyG);
+        assert_eq!(tap(&mut state, Key::KeyD), KeyAction::Pass);
+        assert_eq!(
+            std::mem::take(&mut state.completed_sequences),
+            vec![sequence(&["KeyG", "KeyD"])]
+        );
+
+        // Any other key in between starts over
+        tap(&mut state, Key::KeyG);
+        tap(&mut state, Key::KeyX);
+        tap(&mut state, Key::KeyD);
+        assert!(state.completed_sequences.is_empty());
+
+        // A combo still matches mid-sequence, and ends it
+        tap(&mut state, Key::KeyG);
+        state.key_press(&Key::ControlLeft);
+        assert_eq!(state.key_press(&Key::Space), KeyAction::Block);
+        assert_eq!(state.active_hotkey, Some(combo(&["ControlLeft", "Space"])));
+        state.key_release(&Key::Space);
+        state.key_release(&Key::ControlLeft);
+        tap(&mut state, Key::KeyD);
+        assert!(state.completed_sequences.is_empty());
+
+        let keys = vec!["KeyG".to_string(), "KeyD".to_string()];
+        assert_eq!(state.unregister(&keys), 1);
+        assert_eq!(state.hotkey_count(), 2);
+    }
+
+    #[test]
+    fn test_resolve_key_names_accepts_codes() {
+        let keys = vec!["91".to_string(), "KeyD".to_string(), "179".to_string()];
+        assert_eq!(
+            resolve_key_names(keys),
+            vec!["MetaLeft", "KeyD", "Function"]
+        );
+    }
+
+    #[test]
+    fn test_app_matches_by_name_or_executable() {
+        let apps = vec!["mstsc.exe".to_string(), "steam".to_string()];
+        assert!(app_matches(&apps, "Steam", Path::new("/usr/bin/steam")));
+        assert!(app_matches(
+            &apps,
+            "Remote Desktop Connection",
+            Path::new("mstsc.exe")
+        ));
+        assert!(!app_matches(&apps, "Slack", Path::new("/usr/bin/slack")));
+        assert!(!app_matches(&[], "Steam", Path::new("")));
+    }
+
+    #[test]
+    fn test_commands_parse() {
+        let command: Command = serde_json::from_str(r#"{"command":"clear_hotkeys"}"#).unwrap();
+        assert!(matches!(command, Command::ClearHotkeys));
+        let command: Command =
+            serde_json::from_str(r#"{"command":"unregister_hotkey","keys":["MetaLeft","KeyD"]}"#)
+                .unwrap();
+        assert!(matches!(command, Command::UnregisterHotkey { keys } if keys.len() == 2));
+        let command: Command =
+            serde_json::from_str(r#"{"command":"get_pressed_keys","request_id":"q1"}"#).unwrap();
+        assert!(matches!(
+            command,
+            Command::GetPressedKeys { request_id: Some(id) } if id == "q1"
+        ));
+        let command: Command = serde_json::from_str(r#"{"command":"reset_key_state"}"#).unwrap();
+        assert!(matches!(command, Command::ResetKeyState));
+        let command: Command =
+            serde_json::from_str(r#"{"command":"enable_metrics","enabled":true}"#).unwrap();
+        assert!(matches!(command, Command::EnableMetrics { enabled: true }));
+        let command: Command = serde_json::from_str(
+            r#"{"command":"register_hotkeys","hotkeys":[{"keys":["MetaLeft","KeyD"]},{"sequence":["KeyG","KeyD"],"id":"go"}]}"#,
+        )
+        .unwrap();
+        let Command::RegisterHotkeys { hotkeys } = command else {
+            panic!("expected register_hotkeys");
+        };
+        assert!(matches!(&hotkeys[0], HotkeyEntry::Combo(hotkey) if hotkey.block));
+        assert!(matches!(
+            &hotkeys[1],
+            HotkeyEntry::Sequence(hotkey) if hotkey.interval_ms == 600 && hotkey.id.as_deref() == Some("go")
+        ));
+        let command: Command =
+            serde_json::from_str(r#"{"command":"set_suppression_apps","apps":["Steam"]}"#).unwrap();
+        assert!(matches!(command, Command::SetSuppressionApps { apps } if apps == ["Steam"]));
+        let command: Command = serde_json::from_str(r#"{"command":"shutdown"}"#).unwrap();
+        assert!(matches!(command, Command::Shutdown));
+        let command: Command =
+            serde_json::from_str(r#"{"command":"configure_heartbeat","enabled":false}"#).unwrap();
+        assert!(matches!(
+            command,
+            Command::ConfigureHeartbeat {
+                interval_secs: None,
+                enabled: false
+            }
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_register_and_key_events() {
+        let store = Arc::new(Mutex::new(ListenerState::new()));
+
+        let registrar = {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    let hotkeys = if i % 2 == 0 {
+                        vec![combo(&["ControlLeft", "Space"])]
+                    } else {
+                        vec![combo(&["Function"]), combo(&["MetaLeft", "KeyD"])]
+                    };
+                    store.lock().unwrap().registered_hotkeys = hotkeys;
+                }
+            })
+        };
+        let typist = {
+            let store = Arc::clone(&store);
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    for key in [Key::ControlLeft, Key::Space, Key::KeyA] {
+                        store.lock().unwrap().key_press(&key);
+                    }
+                    for key in [Key::KeyA, Key::Space, Key::ControlLeft] {
+                        store.lock().unwrap().key_release(&key);
+                    }
+                }
+            })
+        };
+        registrar.join().unwrap();
+        typist.join().unwrap();
+
+        let mut state = store.lock().unwrap();
+        assert!(state.currently_pressed.is_empty());
+        assert!(!state.ctrl_pressed);
+        assert_eq!(state.registered_hotkeys.len(), 2);
+        assert!(!state.should_block());
+
+        state.key_press(&Key::MetaLeft);
+        assert_eq!(state.key_press(&Key::KeyD), KeyAction::Block);
+    }
 }
@@ -0,0 +1,30 @@
+//! Build script helper for embedding the git commit in the native binaries.
+
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+/// Expose the short commit SHA to the crate being built as the `ITO_GIT_SHA`
+/// env var, or "unknown" when building outside a git checkout. Call from
+/// build.rs
+pub fn emit_git_sha() {
+    let sha = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ITO_GIT_SHA={}", sha);
+
+    // Re-run when HEAD moves to another branch or the branch gets a new commit
+    if let Some(head) = git(&["rev-parse", "--git-path", "HEAD"]) {
+        println!("cargo:rerun-if-changed={}", head);
+    }
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        if let Some(ref_path) = git(&["rev-parse", "--git-path", &head_ref]) {
+            println!("cargo:rerun-if-changed={}", ref_path);
+        }
+    }
+}
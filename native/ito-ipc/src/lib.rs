@@ -0,0 +1,279 @@
+//! Stdin/stdout protocol shared by the native helper binaries.
+//!
+//! Commands arrive as newline-delimited JSON on stdin. Responses go out either
+//! as newline-delimited JSON, or, for binaries that also stream binary data
+//! (audio-recorder), as framed messages: [type: u8][len: u32 LE][payload].
+//!
+//! Newline framing is safe for any payload: JSON is written compact and
+//! serde_json escapes control characters inside strings, so a value holding
+//! multi-line text still takes exactly one line. Lines are read as bytes and
+//! validated as UTF-8 one at a time, so a malformed line is skipped without
+//! losing the ones after it.
+
+pub mod build_info;
+pub mod logging;
+pub mod synthetic;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, BufRead, Write};
+use std::ops::ControlFlow;
+
+/// Framed payload holding a UTF-8 JSON object with a "type" field
+pub const MSG_TYPE_JSON: u8 = 1;
+/// Framed payload holding raw audio samples
+pub const MSG_TYPE_AUDIO: u8 = 2;
+/// Framed payload holding one Opus packet of mono audio
+pub const MSG_TYPE_OPUS: u8 = 3;
+
+/// Response to the `version` command, identifying the exact build running
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct VersionInfo {
+    #[serde(rename = "type")]
+    pub response_type: &'static str,
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git: &'static str,
+}
+
+impl VersionInfo {
+    pub fn new(name: &'static str, version: &'static str, git: &'static str) -> Self {
+        VersionInfo {
+            response_type: "version",
+            name,
+            version,
+            git,
+        }
+    }
+}
+
+/// Printed once a long-running binary is reading commands and its main loop
+/// is up, so the host can hold off sending commands until then
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Ready {
+    #[serde(rename = "type")]
+    pub response_type: &'static str,
+    pub name: &'static str,
+}
+
+impl Ready {
+    pub fn new(name: &'static str) -> Self {
+        Ready {
+            response_type: "ready",
+            name,
+        }
+    }
+}
+
+/// `Ready` for the calling crate
+#[macro_export]
+macro_rules! ready {
+    () => {
+        $crate::Ready::new(env!("CARGO_PKG_NAME"))
+    };
+}
+
+/// `VersionInfo` for the calling crate. The crate's build.rs must call
+/// `build_info::emit_git_sha`
+#[macro_export]
+macro_rules! version_info {
+    () => {
+        $crate::VersionInfo::new(
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            env!("ITO_GIT_SHA"),
+        )
+    };
+}
+
+/// Version string for clap's `--version`, e.g. "0.1.0 (1a2b3c4)". The crate's
+/// build.rs must call `build_info::emit_git_sha`
+#[macro_export]
+macro_rules! version_string {
+    () => {
+        concat!(env!("CARGO_PKG_VERSION"), " (", env!("ITO_GIT_SHA"), ")")
+    };
+}
+
+pub fn write_framed_message(writer: &mut impl Write, msg_type: u8, data: &[u8]) -> io::Result<()> {
+    let len = data.len() as u32;
+    writer.write_all(&[msg_type])?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+pub fn write_framed_json(writer: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    let json = serde_json::to_string(value)?;
+    write_framed_message(writer, MSG_TYPE_JSON, json.as_bytes())
+}
+
+pub fn write_json_line(writer: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    let json = serde_json::to_string(value)?;
+    writeln!(writer, "{}", json)?;
+    writer.flush()
+}
+
+/// Write one JSON line to stdout. Failures are logged since there is nowhere
+/// else to send them
+pub fn print_json_line(value: &impl Serialize) {
+    if let Err(e) = write_json_line(&mut io::stdout().lock(), value) {
+        log::error!("Error writing JSON to stdout: {}", e);
+    }
+}
+
+/// Parse one JSON command per line from `reader` until EOF or until `handle`
+/// breaks. Blank lines are skipped, and unparseable or non-UTF-8 ones logged
+pub fn read_commands<C: DeserializeOwned>(
+    mut reader: impl BufRead,
+    mut handle: impl FnMut(C) -> ControlFlow<()>,
+) {
+    let mut bytes = Vec::new();
+    loop {
+        bytes.clear();
+        match reader.read_until(b'\n', &mut bytes) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                log::error!("Error reading commands: {}", e);
+                break;
+            }
+        }
+        // `lines()` would end the whole stream at the first invalid line
+        let line = match std::str::from_utf8(&bytes) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Skipping command that isn't valid UTF-8: {}", e);
+                continue;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(value) => value,
+            Err(e) => {
+                log::warn!("Error parsing command: {}", e);
+                continue;
+            }
+        };
+        // Only the command name is logged, arguments can carry user text
+        log::debug!(
+            "Received command {}",
+            value
+                .get("command")
+                .and_then(|c| c.as_str())
+                .unwrap_or("<none>")
+        );
+        match serde_json::from_value::<C>(value) {
+            Ok(command) => {
+                if handle(command).is_break() {
+                    break;
+                }
+            }
+            Err(e) => log::warn!("Error parsing command: {}", e),
+        }
+    }
+}
+
+/// `read_commands` over this process's stdin
+pub fn read_stdin_commands<C: DeserializeOwned>(handle: impl FnMut(C) -> ControlFlow<()>) {
+    read_commands(io::stdin().lock(), handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(tag = "command")]
+    enum TestCommand {
+        #[serde(rename = "ping")]
+        Ping { id: u32 },
+    }
+
+    #[test]
+    fn test_write_framed_message_layout() {
+        let mut buf = Vec::new();
+        write_framed_message(&mut buf, MSG_TYPE_AUDIO, &[1, 2, 3]).unwrap();
+        assert_eq!(buf, vec![MSG_TYPE_AUDIO, 3, 0, 0, 0, 1, 2, 3]);
+
+        let mut buf = Vec::new();
+        write_framed_json(&mut buf, &serde_json::json!({ "type": "x" })).unwrap();
+        assert_eq!(buf[0], MSG_TYPE_JSON);
+        assert_eq!(u32::from_le_bytes(buf[1..5].try_into().unwrap()), 12);
+        assert_eq!(&buf[5..], br#"{"type":"x"}"#);
+    }
+
+    #[test]
+    fn test_version_info_serializes_as_version_message() {
+        let info = VersionInfo::new("audio-recorder", "0.1.0", "1a2b3c4");
+        assert_eq!(
+            serde_json::to_value(&info).unwrap(),
+            serde_json::json!({
+                "type": "version",
+                "name": "audio-recorder",
+                "version": "0.1.0",
+                "git": "1a2b3c4",
+            })
+        );
+    }
+
+    #[test]
+    fn test_ready_serializes() {
+        assert_eq!(
+            serde_json::to_value(crate::ready!()).unwrap(),
+            serde_json::json!({ "type": "ready", "name": "ito-ipc" })
+        );
+    }
+
+    #[test]
+    fn test_write_json_line() {
+        let mut buf = Vec::new();
+        write_json_line(&mut buf, &serde_json::json!({ "type": "x" })).unwrap();
+        assert_eq!(buf, b"{\"type\":\"x\"}\n");
+    }
+
+    #[test]
+    fn test_multiline_text_round_trips_as_one_line() {
+        let text = "first line\nsecond\r\n\tthird\u{2028}";
+        let mut buf = Vec::new();
+        write_json_line(
+            &mut buf,
+            &serde_json::json!({ "command": "echo", "text": text }),
+        )
+        .unwrap();
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 1);
+
+        // A line of invalid UTF-8 is skipped without losing what follows
+        let mut input = b"{\"command\":\"echo\",\"text\":\"\xff\"}\n".to_vec();
+        input.extend_from_slice(&buf);
+        let mut seen = Vec::new();
+        read_commands(input.as_slice(), |command: serde_json::Value| {
+            seen.push(command);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0]["text"], text);
+    }
+
+    #[test]
+    fn test_read_commands_skips_bad_lines_and_stops_on_break() {
+        let input = "\n{\"command\":\"ping\",\"id\":1}\nnot json\n{\"command\":\"ping\",\"id\":2}\n{\"command\":\"ping\",\"id\":3}\n";
+        let mut seen = Vec::new();
+        read_commands(input.as_bytes(), |command: TestCommand| {
+            seen.push(command);
+            if seen.len() == 2 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(
+            seen,
+            vec![TestCommand::Ping { id: 1 }, TestCommand::Ping { id: 2 }]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Diagnostics for the native helpers, routed through the `log` crate.
+//!
+//! By default messages go to stderr as "[name] message", as they always have.
+//! When `ITO_LOG_DIR` is set, each process instead writes to its own
+//! `<name>-<unix seconds>-<pid>.log` file in that directory, one JSON object
+//! per line, and also records debug-level detail such as every command
+//! received. Stderr is not reliably captured by the host, so the file is the
+//! thing to ask users for when reproducing a bug.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory to write log files to. Unset means log to stderr
+pub const LOG_DIR_ENV: &str = "ITO_LOG_DIR";
+
+enum Sink {
+    Stderr,
+    File(Mutex<File>),
+}
+
+struct Logger {
+    name: &'static str,
+    sink: Sink,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match &self.sink {
+            Sink::Stderr => eprintln!("[{}] {}", self.name, record.args()),
+            Sink::File(file) => {
+                let line = format_record(self.name, unix_millis(), record);
+                if let Ok(mut file) = file.lock() {
+                    let _ = writeln!(file, "{}", line);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &self.sink {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+/// Install the logger for the binary `name`. Call once at the top of `main`,
+/// before anything logs
+pub fn init(name: &'static str) {
+    let (sink, level) = match std::env::var_os(LOG_DIR_ENV) {
+        Some(dir) => match open_log_file(name, Path::new(&dir)) {
+            Ok(file) => (Sink::File(Mutex::new(file)), LevelFilter::Debug),
+            Err(e) => {
+                eprintln!(
+                    "[{}] Failed to open log file in {:?}, logging to stderr: {}",
+                    name, dir, e
+                );
+                (Sink::Stderr, LevelFilter::Info)
+            }
+        },
+        None => (Sink::Stderr, LevelFilter::Info),
+    };
+
+    let logger = Box::leak(Box::new(Logger { name, sink }));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+fn open_log_file(name: &str, dir: &Path) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    File::create(log_file_path(
+        name,
+        dir,
+        unix_millis() / 1000,
+        std::process::id(),
+    ))
+}
+
+fn log_file_path(name: &str, dir: &Path, unix_secs: u128, pid: u32) -> PathBuf {
+    dir.join(format!("{}-{}-{}.log", name, unix_secs, pid))
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+fn format_record(name: &str, timestamp_ms: u128, record: &Record) -> serde_json::Value {
+    serde_json::json!({
+        "ts": timestamp_ms as u64,
+        "bin": name,
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "msg": record.args().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file_name_and_record_format() {
+        assert_eq!(
+            log_file_path("text-writer", Path::new("logs"), 1700000000, 42),
+            Path::new("logs").join("text-writer-1700000000-42.log")
+        );
+
+        assert_eq!(
+            format_record(
+                "text-writer",
+                1700000000123,
+                &Record::builder()
+                    .level(log::Level::Warn)
+                    .target("text_writer")
+                    .args(format_args!("restore failed: {}", "busy"))
+                    .build()
+            ),
+            serde_json::json!({
+                "ts": 1700000000123u64,
+                "bin": "text-writer",
+                "level": "WARN",
+                "target": "text_writer",
+                "msg": "restore failed: busy",
+            })
+        );
+    }
+}
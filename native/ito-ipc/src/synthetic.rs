@@ -0,0 +1,13 @@
+//! Marker carried by every keyboard event the helpers post on macOS.
+//!
+//! selected-text-reader and text-writer inject Shift+Arrow, Cmd+C, Cmd+V and
+//! typed characters. Tagging all of them the same way lets anything watching
+//! the event stream tell them apart from the user's own keystrokes.
+//! global-key-listener can't read it yet, since rdev doesn't expose the
+//! CGEvent behind an event, and still ignores copy shortcuts instead.
+
+/// CGEvent integer field the marker is stored in
+pub const MARKER_FIELD: u32 = 121;
+
+/// "ITO" in ASCII
+pub const MARKER: i64 = 0x49544F;
@@ -0,0 +1,177 @@
+//! Cancellation and deadlines of in-flight requests.
+//!
+//! Commands are processed one at a time, so a `cancel` is handled straight on
+//! the stdin thread and only flags the request. The selection loops check the
+//! flag between synthetic keystrokes, so a user who releases push-to-talk or
+//! switches apps doesn't get stray Shift+Arrow presses in the new window.
+//!
+//! Every request also gets a deadline. Past it, `check` fails the same way,
+//! so the request unwinds and restores the clipboard. A request stuck in a
+//! call that never returns can't check, so once it's overdue the watchdog
+//! answers for it and its own late response is dropped.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time a request has to finish before it's failed with "timeout"
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Further time a request that stopped checking gets before the watchdog
+/// answers for it
+pub const WATCHDOG_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct CancelState {
+    // Request currently being processed
+    active: Option<String>,
+    // Latest request a cancel arrived for, which may still be queued
+    cancelled: Option<String>,
+    deadline: Option<Instant>,
+    // Set once the watchdog has answered for the active request
+    answered: bool,
+}
+
+impl CancelState {
+    fn begin(&mut self, request_id: &str, timeout: Duration) {
+        self.active = Some(request_id.to_string());
+        self.deadline = Some(Instant::now() + timeout);
+        self.answered = false;
+    }
+
+    fn check(&self) -> Result<(), Cancelled> {
+        if self.active.is_some() && self.active == self.cancelled {
+            Err(Cancelled::Host)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Err(Cancelled::Timeout)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn take_overdue(&mut self, grace: Duration) -> Option<String> {
+        let overdue = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline + grace);
+        if !overdue || self.answered {
+            return None;
+        }
+        self.answered = true;
+        self.active.clone()
+    }
+}
+
+static STATE: Mutex<CancelState> = Mutex::new(CancelState {
+    active: None,
+    cancelled: None,
+    deadline: None,
+    answered: false,
+});
+
+/// Error returned from a cancelled or overdue request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cancelled {
+    /// The host sent `cancel` for it
+    Host,
+    /// It ran past its deadline
+    Timeout,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cancelled::Host => write!(f, "cancelled"),
+            Cancelled::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Clears the active request, and any cancel aimed at it, when dropped
+pub struct ActiveRequest;
+
+impl ActiveRequest {
+    /// Ends the request, returning false when the watchdog already answered
+    /// for it and the response should be dropped
+    pub fn finish(self) -> bool {
+        !STATE.lock().unwrap().answered
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap();
+        if state.cancelled == state.active {
+            state.cancelled = None;
+        }
+        state.active = None;
+        state.deadline = None;
+        state.answered = false;
+    }
+}
+
+/// Mark `request_id` as the request being processed
+pub fn begin(request_id: &str) -> ActiveRequest {
+    STATE.lock().unwrap().begin(request_id, REQUEST_TIMEOUT);
+    ActiveRequest
+}
+
+/// Flag `request_id` as cancelled, whether it's running now or still queued
+pub fn cancel(request_id: &str) {
+    STATE.lock().unwrap().cancelled = Some(request_id.to_string());
+}
+
+/// Fails with `Cancelled` once the active request has been cancelled or is
+/// past its deadline
+pub fn check() -> Result<(), Cancelled> {
+    STATE.lock().unwrap().check()
+}
+
+/// The active request's id once it's been overdue for `grace`, at most once
+/// per request. The caller answers for it
+pub fn take_overdue(grace: Duration) -> Option<String> {
+    STATE.lock().unwrap().take_overdue(grace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_only_stops_matching_request() {
+        let active = begin("a");
+        cancel("b");
+        assert!(check().is_ok());
+        cancel("a");
+        assert!(check().is_err());
+        drop(active);
+        assert!(check().is_ok());
+
+        // A cancel that arrives while the request is still queued
+        cancel("c");
+        let active = begin("c");
+        assert!(check().is_err());
+        drop(active);
+        let _active = begin("c");
+        assert!(check().is_ok());
+    }
+
+    // On its own state, so an overdue request can't fail other tests' checks
+    #[test]
+    fn test_deadline_times_out_and_watchdog_answers_once() {
+        let mut state = CancelState::default();
+        state.begin("slow", Duration::ZERO);
+        assert_eq!(state.check().unwrap_err(), Cancelled::Timeout);
+        assert_eq!(state.check().unwrap_err().to_string(), "timeout");
+        assert_eq!(state.take_overdue(Duration::ZERO).as_deref(), Some("slow"));
+        assert_eq!(state.take_overdue(Duration::ZERO), None);
+        assert!(state.answered);
+
+        state.begin("fast", REQUEST_TIMEOUT);
+        assert!(state.check().is_ok());
+        assert_eq!(state.take_overdue(Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,156 @@
+use arboard::Clipboard;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Give a copy we triggered time to land before restoring, otherwise it can
+// overwrite the restored contents
+const RESTORE_SETTLE_MS: u64 = 25;
+const RESTORE_ATTEMPTS: u32 = 5;
+
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 10;
+pub const DEFAULT_POLL_TIMEOUT_MS: u64 = 200;
+pub const DEFAULT_KEYSTROKE_DELAY_MS: u64 = 1;
+pub const DEFAULT_COPY_SETTLE_MS: u64 = 25;
+
+/// Pacing of the keystrokes sent to read text and of the clipboard reads that
+/// follow. Slow or remote apps (RDP, some Electron editors) need a longer
+/// timeout, and over RDP keystrokes sent back to back get dropped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PollTiming {
+    /// How often and for how long to poll for a triggered copy to land
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Pause between the Shift+Arrow presses that extend or undo a selection
+    pub keystroke_delay: Duration,
+    /// Wait after a copy or cut where the clipboard is read once rather than
+    /// polled
+    pub copy_settle: Duration,
+}
+
+impl PollTiming {
+    pub fn from_millis(interval_ms: Option<u64>, timeout_ms: Option<u64>) -> Self {
+        PollTiming {
+            // A zero interval would spin on the clipboard
+            interval: Duration::from_millis(interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS).max(1)),
+            timeout: Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_POLL_TIMEOUT_MS)),
+            keystroke_delay: Duration::from_millis(DEFAULT_KEYSTROKE_DELAY_MS),
+            copy_settle: Duration::from_millis(DEFAULT_COPY_SETTLE_MS),
+        }
+    }
+}
+
+/// Timing a request can override, in milliseconds. Anything left out comes
+/// from the app's strategy
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TimingOverrides {
+    #[serde(rename = "pollIntervalMs", alias = "poll_interval_ms")]
+    pub poll_interval_ms: Option<u64>,
+    #[serde(rename = "pollTimeoutMs", alias = "poll_timeout_ms")]
+    pub poll_timeout_ms: Option<u64>,
+    #[serde(rename = "keystrokeDelayMs", alias = "keystroke_delay_ms")]
+    pub keystroke_delay_ms: Option<u64>,
+    #[serde(rename = "copySettleMs", alias = "copy_settle_ms")]
+    pub copy_settle_ms: Option<u64>,
+}
+
+impl Default for PollTiming {
+    fn default() -> Self {
+        PollTiming::from_millis(None, None)
+    }
+}
+
+/// Poll until the clipboard text differs from `baseline`, the state read just
+/// before the copy was triggered. Any change counts, so a selection that is
+/// only whitespace is returned as soon as it lands. Returns empty text if
+/// nothing arrives before the timeout
+pub fn wait_for_clipboard_change(
+    clipboard: &mut Clipboard,
+    baseline: Option<&str>,
+    timing: PollTiming,
+) -> String {
+    let start = Instant::now();
+    loop {
+        thread::sleep(timing.interval);
+
+        if let Ok(text) = clipboard.get_text() {
+            if baseline != Some(text.as_str()) {
+                return text;
+            }
+        }
+
+        if start.elapsed() >= timing.timeout {
+            return String::new();
+        }
+    }
+}
+
+/// Clipboard handle that snapshots the user's text on creation and puts it
+/// back when dropped, so the restore also runs on early-return error paths
+pub struct ClipboardGuard {
+    clipboard: Clipboard,
+    // None when the clipboard held no text at all, as opposed to empty text
+    original: Option<String>,
+}
+
+impl ClipboardGuard {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let mut clipboard =
+            Clipboard::new().map_err(|e| format!("Clipboard init failed: {}", e))?;
+        let original = clipboard.get_text().ok();
+        Ok(ClipboardGuard {
+            clipboard,
+            original,
+        })
+    }
+
+    fn restore(&mut self) -> Result<(), String> {
+        thread::sleep(Duration::from_millis(RESTORE_SETTLE_MS));
+
+        for attempt in 1..=RESTORE_ATTEMPTS {
+            let result = match &self.original {
+                Some(text) => self.clipboard.set_text(text.as_str()),
+                None => self.clipboard.clear(),
+            };
+
+            // Verify the restore by reading it back
+            if result.is_ok() {
+                let current = self.clipboard.get_text().unwrap_or_default();
+                if current == self.original.as_deref().unwrap_or_default() {
+                    return Ok(());
+                }
+            }
+
+            thread::sleep(Duration::from_millis(10 * attempt as u64));
+        }
+
+        Err(format!(
+            "clipboard did not match original after {} attempts",
+            RESTORE_ATTEMPTS
+        ))
+    }
+}
+
+impl Deref for ClipboardGuard {
+    type Target = Clipboard;
+
+    fn deref(&self) -> &Clipboard {
+        &self.clipboard
+    }
+}
+
+impl DerefMut for ClipboardGuard {
+    fn deref_mut(&mut self) -> &mut Clipboard {
+        &mut self.clipboard
+    }
+}
+
+impl Drop for ClipboardGuard {
+    fn drop(&mut self) {
+        match self.restore() {
+            Ok(()) => log::debug!("Restored clipboard"),
+            Err(e) => log::warn!("Failed to restore clipboard: {}", e),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::clipboard::{wait_for_clipboard_change, ClipboardGuard, PollTiming};
+use crate::{ContextSide, SelectionMethod};
+use arboard::{Clipboard, GetExtLinux, LinuxClipboardKind};
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use std::thread;
+use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Count characters as the editor sees them. The cursor moves by grapheme
+// cluster, so a flag or ZWJ emoji is a single arrow key press
+pub fn count_editor_chars(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+pub fn get_selected_text(
+    method: SelectionMethod,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // The PRIMARY selection holds the currently highlighted text on both X11
+    // and Wayland, so it can be read without sending any keystrokes
+    if method != SelectionMethod::Clipboard {
+        let mut clipboard =
+            Clipboard::new().map_err(|e| format!("Clipboard init failed: {}", e))?;
+        if let Ok(text) = clipboard
+            .get()
+            .clipboard(LinuxClipboardKind::Primary)
+            .text()
+        {
+            if !text.is_empty() {
+                return Ok(text);
+            }
+        }
+        if method == SelectionMethod::Accessibility {
+            return Ok(String::new());
+        }
+    }
+
+    // Some apps don't publish PRIMARY, fall back to Ctrl+C. The guard restores
+    // the original clipboard contents when it goes out of scope
+    let mut clipboard = ClipboardGuard::new()?;
+    clipboard
+        .clear()
+        .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+
+    copy_selected_text()?;
+
+    // Small delay for copy operation to complete
+    thread::sleep(timing.copy_settle);
+
+    Ok(clipboard.get_text().unwrap_or_default())
+}
+
+// arboard has no RTF flavor on Linux, so rich text requests fall back to
+// plain text
+pub fn read_clipboard_rtf() -> Option<String> {
+    None
+}
+
+// Enigo can't connect without an X server (or XWayland) to post through
+pub fn can_send_synthetic_input() -> bool {
+    match Enigo::new(&Settings::default()) {
+        Ok(_) => true,
+        Err(e) => {
+            log::warn!("Synthetic input unavailable: {}", e);
+            false
+        }
+    }
+}
+
+pub fn copy_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Unicode('c'), Direction::Click)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
+pub fn cut_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Unicode('x'), Direction::Click)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
+// Extend the selection to the start (Shift+Ctrl+Home) or end (Shift+Ctrl+End)
+// of the document
+pub fn select_to_document_edge(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    let edge = match side {
+        ContextSide::Before => Key::Home,
+        ContextSide::After => Key::End,
+    };
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Shift, Direction::Press)?;
+    enigo.key(edge, Direction::Click)?;
+    enigo.key(Key::Shift, Direction::Release)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
+// A bare arrow press collapses the selection to that end, which is where the
+// cursor was before select_to_document_edge
+pub fn collapse_selection(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    let arrow = match side {
+        ContextSide::Before => Key::RightArrow,
+        ContextSide::After => Key::LeftArrow,
+    };
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(arrow, Direction::Click)?;
+
+    Ok(())
+}
+
+pub fn paste_clipboard() -> Result<(), Box<dyn std::error::Error>> {
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Unicode('v'), Direction::Click)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
+// Simple function to select previous N characters and copy them
+pub fn select_previous_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    select_chars_and_copy(Key::LeftArrow, char_count, clipboard, timing)
+}
+
+// Same as select_previous_chars_and_copy, but selecting forward with
+// Shift+Right
+pub fn select_next_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    select_chars_and_copy(Key::RightArrow, char_count, clipboard, timing)
+}
+
+fn select_chars_and_copy(
+    arrow: Key,
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut enigo = Enigo::new(&Settings::default())?;
+
+    // Send Shift+Arrow N times to select context text
+    for _ in 0..char_count {
+        crate::cancel::check()?;
+        enigo.key(Key::Shift, Direction::Press)?;
+        enigo.key(arrow, Direction::Click)?;
+        enigo.key(Key::Shift, Direction::Release)?;
+
+        // Brief pause between selections
+        thread::sleep(timing.keystroke_delay);
+    }
+
+    // Allow selection to complete
+    thread::sleep(Duration::from_millis(10));
+
+    // Anything other than this counts as the copy having landed
+    let baseline = clipboard.get_text().ok();
+
+    copy_selected_text()?;
+
+    // Adaptively wait for and get text from clipboard
+    Ok(wait_for_clipboard_change(
+        clipboard,
+        baseline.as_deref(),
+        timing,
+    ))
+}
+
+// Shift cursor right while deselecting text
+pub fn shift_cursor_right_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    shift_cursor_with_deselect(Key::RightArrow, char_count, keystroke_delay)
+}
+
+// Shift cursor left while deselecting text selected with Shift+Right
+pub fn shift_cursor_left_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    shift_cursor_with_deselect(Key::LeftArrow, char_count, keystroke_delay)
+}
+
+fn shift_cursor_with_deselect(
+    arrow: Key,
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if char_count == 0 {
+        return Ok(());
+    }
+
+    let mut enigo = Enigo::new(&Settings::default())?;
+    for _ in 0..char_count {
+        enigo.key(Key::Shift, Direction::Press)?;
+        enigo.key(arrow, Direction::Click)?;
+        enigo.key(Key::Shift, Direction::Release)?;
+
+        // Brief pause between movements
+        if char_count > 1 {
+            thread::sleep(keystroke_delay);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_editor_chars() {
+        assert_eq!(count_editor_chars("hello"), 5);
+        // CRLF is a single grapheme cluster
+        assert_eq!(count_editor_chars("line1\r\nline2"), 11);
+        assert_eq!(count_editor_chars("Hi 👋"), 4);
+        assert_eq!(count_editor_chars("👨‍👩‍👧 🇯🇵"), 3);
+        assert_eq!(count_editor_chars("cafe\u{301}"), 4);
+        assert_eq!(count_editor_chars(""), 0);
+    }
+}
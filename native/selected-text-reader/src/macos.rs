@@ -1,13 +1,25 @@
+use crate::clipboard::{wait_for_clipboard_change, ClipboardGuard, PollTiming};
+use crate::{ContextSide, SelectionMethod};
+use accessibility_sys_ng::{
+    kAXErrorSuccess, kAXFocusedUIElementAttribute, kAXSelectedTextAttribute,
+    kAXSelectedTextRangeAttribute, AXIsProcessTrusted, AXUIElementCopyAttributeValue,
+    AXUIElementCreateSystemWide, AXUIElementRef,
+};
 use arboard::Clipboard;
+use core_foundation::base::{CFGetTypeID, CFTypeRef, TCFType};
+use core_foundation::data::{CFData, CFDataRef};
+use core_foundation::string::{CFString, CFStringGetTypeID, CFStringRef};
+use ito_ipc::synthetic;
 use libc::c_void;
 use std::ptr;
 use std::thread;
 use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
 
-// Count characters as the editor sees them (on macOS, just use normal char
-// count)
+// Count characters as the editor sees them. The cursor moves by grapheme
+// cluster, so a flag or ZWJ emoji is a single arrow key press
 pub fn count_editor_chars(text: &str) -> usize {
-    text.chars().count()
+    text.graphemes(true).count()
 }
 
 // Raw Quartz C API bindings for CGEventCreateKeyboardEvent
@@ -17,12 +29,23 @@ type CGEventRef = *mut __CGEvent;
 
 type CGKeyCode = u16;
 type CGEventFlags = u64;
+const LEFT_ARROW_KEY_CODE: CGKeyCode = 123;
+const RIGHT_ARROW_KEY_CODE: CGKeyCode = 124;
+const DOWN_ARROW_KEY_CODE: CGKeyCode = 125;
+const UP_ARROW_KEY_CODE: CGKeyCode = 126;
 const CG_EVENT_FLAG_MASK_COMMAND: CGEventFlags = 0x100000;
 const CG_EVENT_FLAG_MASK_SHIFT: CGEventFlags = 0x020000;
 
 type CGEventTapLocation = u32;
 const CG_SESSION_EVENT_TAP: CGEventTapLocation = 1;
 
+// Pasteboard Manager C API, used to read rich flavors arboard doesn't expose
+type PasteboardRef = *mut c_void;
+type PasteboardItemID = *mut c_void;
+type OSStatus = i32;
+const NO_ERR: OSStatus = 0;
+const PASTEBOARD_CLIPBOARD: &str = "com.apple.pasteboard.clipboard";
+
 extern "C" {
     fn CGEventCreateKeyboardEvent(
         source: *mut c_void,
@@ -33,14 +56,165 @@ extern "C" {
     fn CGEventPost(tap: CGEventTapLocation, event: CGEventRef);
     fn CGEventSetIntegerValueField(event: CGEventRef, field: u32, value: i64);
     fn CFRelease(cf: *const c_void);
+
+    fn PasteboardCreate(name: CFStringRef, pasteboard: *mut PasteboardRef) -> OSStatus;
+    fn PasteboardSynchronize(pasteboard: PasteboardRef) -> u32;
+    fn PasteboardGetItemCount(pasteboard: PasteboardRef, count: *mut usize) -> OSStatus;
+    fn PasteboardGetItemIdentifier(
+        pasteboard: PasteboardRef,
+        index: isize,
+        item: *mut PasteboardItemID,
+    ) -> OSStatus;
+    fn PasteboardCopyItemFlavorData(
+        pasteboard: PasteboardRef,
+        item: PasteboardItemID,
+        flavor: CFStringRef,
+        data: *mut CFDataRef,
+    ) -> OSStatus;
+}
+
+pub fn get_selected_text(
+    method: SelectionMethod,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match method {
+        SelectionMethod::Accessibility => Ok(get_selected_text_ax().unwrap_or_default()),
+        SelectionMethod::Clipboard => get_selected_text_cmd_c(timing),
+        // Only fall back to Cmd+C when the focused app doesn't expose its
+        // selection through the Accessibility API
+        SelectionMethod::Auto => match get_selected_text_ax() {
+            Some(text) => Ok(text),
+            None => get_selected_text_cmd_c(timing),
+        },
+    }
 }
 
-pub fn get_selected_text() -> Result<String, Box<dyn std::error::Error>> {
-    // Simple approach: use Cmd+C (copy) to get any selected text
-    let mut clipboard = Clipboard::new().map_err(|e| format!("Clipboard init failed: {}", e))?;
+// Read kAXSelectedTextAttribute from the focused element. Doesn't touch the
+// clipboard, but returns None in apps that don't implement it
+fn get_selected_text_ax() -> Option<String> {
+    unsafe {
+        let system_wide = AXUIElementCreateSystemWide();
+        if system_wide.is_null() {
+            return None;
+        }
+        let focused = copy_ax_attribute(system_wide, kAXFocusedUIElementAttribute);
+        CFRelease(system_wide as *const c_void);
+        let focused = focused?;
+
+        let selected = copy_ax_attribute(focused as AXUIElementRef, kAXSelectedTextAttribute);
+        CFRelease(focused as *const c_void);
+        let selected = selected?;
 
-    // Store original clipboard contents
-    let original_clipboard = clipboard.get_text().unwrap_or_default();
+        if CFGetTypeID(selected) != CFStringGetTypeID() {
+            CFRelease(selected as *const c_void);
+            return None;
+        }
+        let text = CFString::wrap_under_create_rule(selected as CFStringRef).to_string();
+        if text.is_empty() {
+            None
+        } else {
+            Some(text)
+        }
+    }
+}
+
+// Returns an owned (+1) reference the caller must release
+unsafe fn copy_ax_attribute(element: AXUIElementRef, attribute: &str) -> Option<CFTypeRef> {
+    let attribute = CFString::new(attribute);
+    let mut value: CFTypeRef = ptr::null();
+    let error = AXUIElementCopyAttributeValue(element, attribute.as_concrete_TypeRef(), &mut value);
+    if error == kAXErrorSuccess && !value.is_null() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+// AXValue wrapping a CFRange, from AXValue.h
+const AX_VALUE_TYPE_CF_RANGE: u32 = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct TextRange {
+    location: isize,
+    length: isize,
+}
+
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXValueCreate(value_type: u32, value: *const c_void) -> CFTypeRef;
+    fn AXValueGetValue(value: CFTypeRef, value_type: u32, value_out: *mut c_void) -> u8;
+    fn AXUIElementSetAttributeValue(
+        element: AXUIElementRef,
+        attribute: CFStringRef,
+        value: CFTypeRef,
+    ) -> i32;
+}
+
+/// The focused element's selected range, to be set back exactly after the
+/// Shift+Arrow presses of get-cursor-context
+pub struct SavedSelection {
+    // Owned, released on drop
+    element: AXUIElementRef,
+    range: TextRange,
+}
+
+impl Drop for SavedSelection {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.element as *const c_void) };
+    }
+}
+
+// None in apps that don't expose kAXSelectedTextRangeAttribute, which is
+// most Electron and web content
+pub fn save_selection() -> Option<SavedSelection> {
+    unsafe {
+        let system_wide = AXUIElementCreateSystemWide();
+        if system_wide.is_null() {
+            return None;
+        }
+        let focused = copy_ax_attribute(system_wide, kAXFocusedUIElementAttribute);
+        CFRelease(system_wide as *const c_void);
+        let mut saved = SavedSelection {
+            element: focused? as AXUIElementRef,
+            range: TextRange::default(),
+        };
+
+        let value = copy_ax_attribute(saved.element, kAXSelectedTextRangeAttribute)?;
+        let ok = AXValueGetValue(
+            value,
+            AX_VALUE_TYPE_CF_RANGE,
+            &mut saved.range as *mut TextRange as *mut c_void,
+        ) != 0;
+        CFRelease(value as *const c_void);
+        ok.then_some(saved)
+    }
+}
+
+// Setting the range leaves the selection anchored at its start, whichever
+// way the user made it
+pub fn restore_selection(saved: &SavedSelection) -> bool {
+    unsafe {
+        let value = AXValueCreate(
+            AX_VALUE_TYPE_CF_RANGE,
+            &saved.range as *const TextRange as *const c_void,
+        );
+        if value.is_null() {
+            return false;
+        }
+        let attribute = CFString::new(kAXSelectedTextRangeAttribute);
+        let error =
+            AXUIElementSetAttributeValue(saved.element, attribute.as_concrete_TypeRef(), value);
+        CFRelease(value as *const c_void);
+        error == kAXErrorSuccess
+    }
+}
+
+fn get_selected_text_cmd_c(timing: PollTiming) -> Result<String, Box<dyn std::error::Error>> {
+    // Simple approach: use Cmd+C (copy) to get any selected text. The guard
+    // restores the original clipboard contents when it goes out of scope -
+    // ITO is copying on behalf of user for context
+    let mut clipboard = ClipboardGuard::new()?;
 
     clipboard
         .clear()
@@ -50,43 +224,107 @@ pub fn get_selected_text() -> Result<String, Box<dyn std::error::Error>> {
     native_cmd_c()?;
 
     // Small delay for copy operation to complete
-    thread::sleep(Duration::from_millis(25));
+    thread::sleep(timing.copy_settle);
 
     // Get the copied text from clipboard (this is what was selected)
     let selected_text = clipboard.get_text().unwrap_or_default();
 
-    // Always restore original clipboard contents - ITO is cutting on behalf of user
-    // for context
-    let _ = clipboard.set_text(original_clipboard);
-
     Ok(selected_text)
 }
 
+// Read the public.rtf flavor of the first clipboard item, if the source app
+// provided one
+pub fn read_clipboard_rtf() -> Option<String> {
+    unsafe {
+        let name = CFString::new(PASTEBOARD_CLIPBOARD);
+        let mut pasteboard: PasteboardRef = ptr::null_mut();
+        if PasteboardCreate(name.as_concrete_TypeRef(), &mut pasteboard) != NO_ERR
+            || pasteboard.is_null()
+        {
+            return None;
+        }
+        PasteboardSynchronize(pasteboard);
+
+        let mut data: CFDataRef = ptr::null();
+        let mut count = 0usize;
+        let mut item: PasteboardItemID = ptr::null_mut();
+        let flavor = CFString::new("public.rtf");
+        // Item indices are 1-based
+        let found = PasteboardGetItemCount(pasteboard, &mut count) == NO_ERR
+            && count > 0
+            && PasteboardGetItemIdentifier(pasteboard, 1, &mut item) == NO_ERR
+            && PasteboardCopyItemFlavorData(
+                pasteboard,
+                item,
+                flavor.as_concrete_TypeRef(),
+                &mut data,
+            ) == NO_ERR
+            && !data.is_null();
+        CFRelease(pasteboard as *const c_void);
+
+        if !found {
+            return None;
+        }
+        let data = CFData::wrap_under_create_rule(data);
+        Some(String::from_utf8_lossy(data.bytes()).into_owned())
+    }
+}
+
+// Tag an event we post so it can be told apart from the user's keystrokes
+unsafe fn mark_synthetic(event: CGEventRef) {
+    CGEventSetIntegerValueField(event, synthetic::MARKER_FIELD, synthetic::MARKER);
+}
+
+// CGEventPost silently drops events from a process that hasn't been granted
+// Accessibility access, so that grant is what decides whether Cmd+C works
+pub fn can_send_synthetic_input() -> bool {
+    unsafe { AXIsProcessTrusted() }
+}
+
 // Native macOS Cmd+C implementation using raw Quartz C API - matching Python
 // exactly
 pub fn native_cmd_c() -> Result<(), Box<dyn std::error::Error>> {
-    unsafe {
-        // Key code for 'C' is 8 on macOS
-        let c_key_code: CGKeyCode = 8;
+    // Key code for 'C' is 8 on macOS
+    post_cmd_shortcut(8)
+}
+
+// Cmd+X, cutting the selection to the clipboard
+pub fn native_cmd_x() -> Result<(), Box<dyn std::error::Error>> {
+    // Key code for 'X' is 7 on macOS
+    post_cmd_shortcut(7)
+}
+
+// Cmd+V, pasting the clipboard over the current selection
+pub fn native_cmd_v() -> Result<(), Box<dyn std::error::Error>> {
+    // Key code for 'V' is 9 on macOS
+    post_cmd_shortcut(9)
+}
 
-        // Create key down event for Cmd+C - using None as source like Python
-        let key_down_event = CGEventCreateKeyboardEvent(ptr::null_mut(), c_key_code, true);
+fn post_cmd_shortcut(key_code: CGKeyCode) -> Result<(), Box<dyn std::error::Error>> {
+    post_key(key_code, CG_EVENT_FLAG_MASK_COMMAND)
+}
+
+fn post_key(key_code: CGKeyCode, flags: CGEventFlags) -> Result<(), Box<dyn std::error::Error>> {
+    unsafe {
+        // Create key down event for the shortcut - using None as source like Python
+        let key_down_event = CGEventCreateKeyboardEvent(ptr::null_mut(), key_code, true);
         if key_down_event.is_null() {
             return Err("Failed to create key down event".into());
         }
 
-        // Set Command flag
-        CGEventSetFlags(key_down_event, CG_EVENT_FLAG_MASK_COMMAND);
+        CGEventSetFlags(key_down_event, flags);
 
-        // Create key up event for Cmd+C - using None as source like Python
-        let key_up_event = CGEventCreateKeyboardEvent(ptr::null_mut(), c_key_code, false);
+        // Create key up event for the shortcut - using None as source like Python
+        let key_up_event = CGEventCreateKeyboardEvent(ptr::null_mut(), key_code, false);
         if key_up_event.is_null() {
             CFRelease(key_down_event as *const c_void);
             return Err("Failed to create key up event".into());
         }
 
-        // Set Command flag
-        CGEventSetFlags(key_up_event, CG_EVENT_FLAG_MASK_COMMAND);
+        CGEventSetFlags(key_up_event, flags);
+
+        mark_synthetic(key_down_event);
+        mark_synthetic(key_up_event);
 
         // Post the events with timing like Python
         CGEventPost(CG_SESSION_EVENT_TAP, key_down_event);
@@ -104,17 +342,61 @@ pub fn native_cmd_c() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Extend the selection to the start (Shift+Cmd+Up) or end (Shift+Cmd+Down)
+// of the document
+pub fn select_to_document_edge(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    let key_code = match side {
+        ContextSide::Before => UP_ARROW_KEY_CODE,
+        ContextSide::After => DOWN_ARROW_KEY_CODE,
+    };
+    post_key(
+        key_code,
+        CG_EVENT_FLAG_MASK_SHIFT | CG_EVENT_FLAG_MASK_COMMAND,
+    )
+}
+
+// A bare arrow press collapses the selection to that end, which is where the
+// cursor was before select_to_document_edge
+pub fn collapse_selection(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    let key_code = match side {
+        ContextSide::Before => RIGHT_ARROW_KEY_CODE,
+        ContextSide::After => LEFT_ARROW_KEY_CODE,
+    };
+    post_key(key_code, 0)
+}
+
 // Simple function to select previous N characters and copy them
 pub fn select_previous_chars_and_copy(
     char_count: usize,
     clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    select_chars_and_copy(LEFT_ARROW_KEY_CODE, char_count, clipboard, timing)
+}
+
+// Same as select_previous_chars_and_copy, but selecting forward with
+// Shift+Right
+pub fn select_next_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Send Shift+Left N times to select precursor text (copied from working
+    select_chars_and_copy(RIGHT_ARROW_KEY_CODE, char_count, clipboard, timing)
+}
+
+fn select_chars_and_copy(
+    arrow_key_code: CGKeyCode,
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // Send Shift+Arrow N times to select context text (copied from working
     // get_context)
     for _i in 0..char_count {
+        crate::cancel::check()?;
         unsafe {
-            let key_down_event = CGEventCreateKeyboardEvent(ptr::null_mut(), 123, true); // Left arrow
-            let key_up_event = CGEventCreateKeyboardEvent(ptr::null_mut(), 123, false);
+            let key_down_event = CGEventCreateKeyboardEvent(ptr::null_mut(), arrow_key_code, true);
+            let key_up_event = CGEventCreateKeyboardEvent(ptr::null_mut(), arrow_key_code, false);
 
             if key_down_event.is_null() || key_up_event.is_null() {
                 if !key_down_event.is_null() {
@@ -123,16 +405,15 @@ pub fn select_previous_chars_and_copy(
                 if !key_up_event.is_null() {
                     CFRelease(key_up_event as *const c_void);
                 }
-                return Err("Failed to create shift+left event".into());
+                return Err("Failed to create shift+arrow event".into());
             }
 
             // Set Shift flag for selection
             CGEventSetFlags(key_down_event, CG_EVENT_FLAG_MASK_SHIFT);
             CGEventSetFlags(key_up_event, CG_EVENT_FLAG_MASK_SHIFT);
 
-            // Mark as synthetic events
-            CGEventSetIntegerValueField(key_down_event, 121, 0x49544F);
-            CGEventSetIntegerValueField(key_up_event, 121, 0x49544F);
+            mark_synthetic(key_down_event);
+            mark_synthetic(key_up_event);
 
             // Post events using session event tap to avoid interference
             CGEventPost(CG_SESSION_EVENT_TAP, key_down_event);
@@ -144,35 +425,45 @@ pub fn select_previous_chars_and_copy(
         }
 
         // Brief pause between selections
-        thread::sleep(Duration::from_millis(1));
+        thread::sleep(timing.keystroke_delay);
     }
 
     // Allow selection to complete (match working get_context timing)
     thread::sleep(Duration::from_millis(10));
 
+    // Anything other than this counts as the copy having landed
+    let baseline = clipboard.get_text().ok();
+
     native_cmd_c()?;
 
     // Adaptively wait for and get text from clipboard
-    let mut context_text = String::new();
-    let max_retries = 20; // Poll for a maximum of 20 * 10ms = 200ms
-    for _ in 0..max_retries {
-        // Give a tiny bit of time for the clipboard to update
-        thread::sleep(Duration::from_millis(10));
-
-        if let Ok(text) = clipboard.get_text() {
-            if !text.is_empty() {
-                context_text = text;
-                break; // Success! We got the text.
-            }
-        }
-    }
-
-    Ok(context_text)
+    Ok(wait_for_clipboard_change(
+        clipboard,
+        baseline.as_deref(),
+        timing,
+    ))
 }
 
 // Shift cursor right while deselecting text
 pub fn shift_cursor_right_with_deselect(
     char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    shift_cursor_with_deselect(RIGHT_ARROW_KEY_CODE, char_count, keystroke_delay)
+}
+
+// Shift cursor left while deselecting text selected with Shift+Right
+pub fn shift_cursor_left_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    shift_cursor_with_deselect(LEFT_ARROW_KEY_CODE, char_count, keystroke_delay)
+}
+
+fn shift_cursor_with_deselect(
+    arrow_key_code: CGKeyCode,
+    char_count: usize,
+    keystroke_delay: Duration,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if char_count == 0 {
         return Ok(());
@@ -180,18 +471,16 @@ pub fn shift_cursor_right_with_deselect(
 
     for _i in 0..char_count {
         unsafe {
-            let right_arrow_key_code: CGKeyCode = 124; // Right Arrow key code
-            let key_down = CGEventCreateKeyboardEvent(ptr::null_mut(), right_arrow_key_code, true);
-            let key_up = CGEventCreateKeyboardEvent(ptr::null_mut(), right_arrow_key_code, false);
+            let key_down = CGEventCreateKeyboardEvent(ptr::null_mut(), arrow_key_code, true);
+            let key_up = CGEventCreateKeyboardEvent(ptr::null_mut(), arrow_key_code, false);
 
             if !key_down.is_null() && !key_up.is_null() {
-                // Set Shift flag to unselect the text as we move right
+                // Set Shift flag to unselect the text as we move back
                 CGEventSetFlags(key_down, CG_EVENT_FLAG_MASK_SHIFT);
                 CGEventSetFlags(key_up, CG_EVENT_FLAG_MASK_SHIFT);
 
-                // Mark as synthetic events
-                CGEventSetIntegerValueField(key_down, 121, 0x49544F);
-                CGEventSetIntegerValueField(key_up, 121, 0x49544F);
+                mark_synthetic(key_down);
+                mark_synthetic(key_up);
 
                 CGEventPost(CG_SESSION_EVENT_TAP, key_down);
                 CGEventPost(CG_SESSION_EVENT_TAP, key_up);
@@ -203,7 +492,7 @@ pub fn shift_cursor_right_with_deselect(
 
         // Brief pause between movements
         if char_count > 1 {
-            thread::sleep(Duration::from_millis(1));
+            thread::sleep(keystroke_delay);
         }
     }
 
@@ -219,6 +508,7 @@ mod tests {
         assert_eq!(count_editor_chars("hello"), 5);
         assert_eq!(count_editor_chars("Hello 世界"), 8);
         assert_eq!(count_editor_chars("Hi 👋"), 4);
+        assert_eq!(count_editor_chars("👨‍👩‍👧 🇯🇵"), 3);
         assert_eq!(count_editor_chars(""), 0);
     }
 }
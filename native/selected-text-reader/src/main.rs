@@ -1,10 +1,21 @@
 use arboard::Clipboard;
+use clipboard::{wait_for_clipboard_change, ClipboardGuard, PollTiming, TimingOverrides};
+use ito_ipc::{print_json_line, read_stdin_commands};
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, Write};
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
 use std::thread;
 use std::time::Duration;
+use strategy::{AppStrategy, StrategyOverride, StrategyTable};
+use unicode_segmentation::UnicodeSegmentation;
+
+mod cancel;
+mod clipboard;
+mod strategy;
 
 // Platform-specific modules
+#[cfg(target_os = "linux")]
+mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
 #[cfg(target_os = "windows")]
@@ -16,8 +27,27 @@ enum Command {
     #[serde(rename = "get-text")]
     GetText {
         format: Option<String>,
+        method: Option<String>,
+        // In chars (Unicode scalar values), like the reported length
         #[serde(rename = "maxLength")]
         max_length: Option<usize>,
+        // Cut the selection instead of copying it, removing it from the
+        // document
+        #[serde(rename = "cutCurrentSelection")]
+        cut_current_selection: Option<bool>,
+        // Bundle id or app name to pick the app strategy by, instead of the
+        // frontmost app's name
+        #[serde(rename = "appHint", alias = "app_hint")]
+        app_hint: Option<String>,
+        // Keystroke pacing and copy settle time, over the app's strategy
+        #[serde(flatten)]
+        timing: TimingOverrides,
+        #[serde(rename = "requestId")]
+        request_id: String,
+    },
+    #[serde(rename = "replace-selection")]
+    ReplaceSelection {
+        text: String,
         #[serde(rename = "requestId")]
         request_id: String,
     },
@@ -25,11 +55,154 @@ enum Command {
     GetCursorContext {
         #[serde(rename = "contextLength")]
         context_length: Option<usize>,
+        // Cut the selection before reading the context, returning it as
+        // cutText
         #[serde(rename = "cutCurrentSelection")]
         cut_current_selection: Option<bool>,
+        direction: Option<String>,
+        // How often and how long to poll for each copy to land (defaulting to
+        // 10ms and 200ms), the pause between Shift+Arrow presses (1ms) and
+        // the wait after a one-off copy (25ms)
+        #[serde(flatten)]
+        timing: TimingOverrides,
+        #[serde(rename = "appHint", alias = "app_hint")]
+        app_hint: Option<String>,
+        // Report the context read so far every few chars as context-partial
+        // messages before the final response
+        stream: Option<bool>,
+        #[serde(rename = "requestId")]
+        request_id: String,
+    },
+    // The selection if there is one, otherwise the context before the cursor,
+    // with the clipboard saved and restored once for both
+    #[serde(rename = "get-text-or-context")]
+    GetTextOrContext {
+        #[serde(rename = "contextLength")]
+        context_length: Option<usize>,
+        // In chars (Unicode scalar values), applied to the selection
+        #[serde(rename = "maxLength")]
+        max_length: Option<usize>,
+        #[serde(rename = "requestId")]
+        request_id: String,
+    },
+    // Whole text of the focused field, read without changing it and with the
+    // cursor put back where it was
+    #[serde(rename = "get-document-text")]
+    GetDocumentText {
+        // In chars (Unicode scalar values), like the reported length
+        #[serde(rename = "maxLength")]
+        max_length: Option<usize>,
+        #[serde(rename = "requestId")]
+        request_id: String,
+    },
+    // Stops the GetCursorContext or GetDocumentText with this request id, if
+    // it's running or queued
+    #[serde(rename = "cancel")]
+    Cancel {
+        #[serde(rename = "requestId")]
+        request_id: String,
+    },
+    // Reports whether the clipboard and synthetic keystrokes this binary
+    // relies on work in the current environment
+    #[serde(rename = "self-test")]
+    SelfTest {
         #[serde(rename = "requestId")]
         request_id: String,
     },
+    // Adds per-app strategies or overrides some of their fields, keyed by
+    // bundle id or app name
+    #[serde(rename = "configure-strategies")]
+    ConfigureStrategies {
+        strategies: BTreeMap<String, StrategyOverride>,
+        #[serde(rename = "requestId")]
+        request_id: String,
+    },
+    #[serde(rename = "version")]
+    Version,
+}
+
+/// Which side of the cursor GetCursorContext reads
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ContextDirection {
+    #[default]
+    Before,
+    After,
+    Both,
+}
+
+impl ContextDirection {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "before" => Some(ContextDirection::Before),
+            "after" => Some(ContextDirection::After),
+            "both" => Some(ContextDirection::Both),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ContextSide {
+    Before,
+    After,
+}
+
+impl ContextSide {
+    fn opposite(self) -> Self {
+        match self {
+            ContextSide::Before => ContextSide::After,
+            ContextSide::After => ContextSide::Before,
+        }
+    }
+}
+
+/// How GetText reads the selection
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum SelectionMethod {
+    /// Read without touching the clipboard where possible, falling back to a
+    /// copy keystroke
+    #[default]
+    Auto,
+    /// Accessibility API (macOS) or PRIMARY selection (Linux) only
+    Accessibility,
+    /// Copy keystroke and read the clipboard only
+    Clipboard,
+}
+
+impl SelectionMethod {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(SelectionMethod::Auto),
+            "accessibility" => Some(SelectionMethod::Accessibility),
+            "clipboard" => Some(SelectionMethod::Clipboard),
+            _ => None,
+        }
+    }
+}
+
+/// Clipboard flavor GetText returns the selection in
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum TextFormat {
+    #[default]
+    Plain,
+    Html,
+    Rtf,
+    /// Converted from the HTML flavor
+    Markdown,
+}
+
+impl TextFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(TextFormat::Plain),
+            "html" => Some(TextFormat::Html),
+            "rtf" => Some(TextFormat::Rtf),
+            "markdown" => Some(TextFormat::Markdown),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -38,8 +211,26 @@ struct SelectedTextResponse {
     request_id: String,
     success: bool,
     text: Option<String>,
+    // The format actually returned, which falls back to plain when the source
+    // app doesn't provide the requested flavor
+    format: Option<TextFormat>,
     error: Option<String>,
+    // Number of chars (Unicode scalar values) in text
     length: usize,
+    // Frontmost app when the selection was read, None where it can't be
+    // determined
+    #[serde(rename = "appName")]
+    app_name: Option<String>,
+    #[serde(rename = "windowTitle")]
+    window_title: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReplaceSelectionResponse {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    success: bool,
+    error: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -49,225 +240,970 @@ struct CursorContextResponse {
     success: bool,
     #[serde(rename = "contextText")]
     context_text: Option<String>,
+    // Both sides of the cursor, only set for direction "both"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<CursorContext>,
+    error: Option<String>,
+    // Number of chars (Unicode scalar values) in context_text, or in both
+    // sides of context
+    length: usize,
+    // Selection removed by cutCurrentSelection, also returned when reading
+    // the context afterwards fails, since it's no longer in the document
+    #[serde(rename = "cutText", skip_serializing_if = "Option::is_none")]
+    cut_text: Option<String>,
+}
+
+/// Where GetTextOrContext's text came from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum TextSource {
+    Selection,
+    Context,
+}
+
+#[derive(Serialize)]
+struct TextOrContextResponse {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    success: bool,
+    // None when the read failed
+    source: Option<TextSource>,
+    text: Option<String>,
+    error: Option<String>,
+    // Number of chars (Unicode scalar values) in text
+    length: usize,
+}
+
+#[derive(Serialize)]
+struct SelfTestResponse {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    capabilities: Capabilities,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Capabilities {
+    // Text written to the clipboard reads back unchanged
+    clipboard_rw: bool,
+    // Keystrokes can be posted to other apps. Checked without sending any,
+    // so a focused window never receives a stray Ctrl+C
+    synthetic_input: bool,
+}
+
+#[derive(Serialize)]
+struct DocumentTextResponse {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    success: bool,
+    text: Option<String>,
     error: Option<String>,
+    // Number of chars (Unicode scalar values) in text
     length: usize,
 }
 
+// Sent by the watchdog in place of the response of a request that hung
+#[derive(Serialize)]
+struct TimeoutResponse {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    success: bool,
+    error: &'static str,
+}
+
+// The whole table after configure-strategies, built-in entries included
+#[derive(Serialize)]
+struct StrategiesResponse<'a> {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    strategies: &'a BTreeMap<String, AppStrategy>,
+}
+
+// Interim context while a streaming GetCursorContext is still selecting
+#[derive(Serialize)]
+struct ContextPartial<'a> {
+    #[serde(rename = "type")]
+    response_type: &'static str,
+    #[serde(rename = "requestId")]
+    request_id: &'a str,
+    side: ContextSide,
+    text: &'a str,
+    // Number of editor chars (grapheme clusters) in text
+    chars: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct CursorContext {
+    before: String,
+    after: String,
+}
+
 fn main() {
+    ito_ipc::logging::init("selected-text-reader");
+
     let (cmd_tx, cmd_rx) = crossbeam_channel::unbounded::<Command>();
 
     let mut command_processor = CommandProcessor::new(cmd_rx);
 
     // Spawn thread to read commands from stdin
     thread::spawn(move || {
-        let stdin = io::stdin();
-        for l in stdin.lock().lines().map_while(Result::ok) {
-            if l.trim().is_empty() {
-                continue;
+        read_stdin_commands(|command: Command| {
+            // The processor is busy with the request being cancelled, so this
+            // can't wait in the queue behind it
+            if let Command::Cancel { request_id } = &command {
+                cancel::cancel(request_id);
+                return ControlFlow::Continue(());
             }
-            if let Ok(command) = serde_json::from_str::<Command>(&l) {
-                if let Err(e) = cmd_tx.send(command) {
-                    eprintln!(
-                        "[selected-text-reader] Failed to send command to processor: {}",
-                        e
-                    );
-                    break;
-                }
+            if let Err(e) = cmd_tx.send(command) {
+                log::error!("Failed to send command to processor: {}", e);
+                return ControlFlow::Break(());
             }
+            ControlFlow::Continue(())
+        });
+    });
+
+    // Answers for a request stuck past its deadline in a call that never
+    // returns, so the host isn't left waiting
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_millis(100));
+        if let Some(request_id) = cancel::take_overdue(cancel::WATCHDOG_GRACE) {
+            log::warn!(
+                "Request {} is overdue, answering with a timeout",
+                request_id
+            );
+            print_json_line(&TimeoutResponse {
+                request_id,
+                success: false,
+                error: "timeout",
+            });
         }
     });
 
+    // Commands sent from here on queue on stdin until the loop picks them up
+    print_json_line(&ito_ipc::ready!());
     command_processor.run();
 }
 
 struct CommandProcessor {
     cmd_rx: crossbeam_channel::Receiver<Command>,
+    strategies: StrategyTable,
 }
 
 impl CommandProcessor {
     fn new(cmd_rx: crossbeam_channel::Receiver<Command>) -> Self {
-        CommandProcessor { cmd_rx }
+        CommandProcessor {
+            cmd_rx,
+            strategies: StrategyTable::builtin(),
+        }
+    }
+
+    // Strategy for the hinted app, or for the frontmost one
+    fn strategy_for(&self, app_hint: Option<String>) -> AppStrategy {
+        let app = app_hint.or_else(|| {
+            active_win_pos_rs::get_active_window()
+                .ok()
+                .map(|window| window.app_name)
+        });
+        let strategy = self.strategies.lookup(app.as_deref());
+        log::debug!("Using {:?} for {:?}", strategy, app);
+        strategy
     }
 
     fn run(&mut self) {
         while let Ok(command) = self.cmd_rx.recv() {
             match command {
                 Command::GetText {
-                    format: _,
+                    format,
+                    method,
                     max_length,
+                    cut_current_selection,
+                    app_hint,
+                    timing,
                     request_id,
-                } => self.handle_get_text(max_length, request_id),
-                Command::GetCursorContext {
+                } => {
+                    let strategy = self.strategy_for(app_hint);
+                    let method = match method.as_deref().map(SelectionMethod::from_name) {
+                        Some(Some(method)) => method,
+                        Some(None) => {
+                            log::warn!("Unknown method {:?}, using auto", method);
+                            SelectionMethod::default()
+                        }
+                        None => strategy.method(),
+                    };
+                    let format = match format.as_deref().map(TextFormat::from_name) {
+                        Some(Some(format)) => format,
+                        Some(None) => {
+                            log::warn!("Unknown format {:?}, using plain", format);
+                            TextFormat::default()
+                        }
+                        None => TextFormat::default(),
+                    };
+                    self.handle_get_text(
+                        method,
+                        format,
+                        max_length,
+                        cut_current_selection.unwrap_or(strategy.cut),
+                        strategy.timing(&timing),
+                        request_id,
+                    )
+                }
+                Command::ReplaceSelection { text, request_id } => {
+                    self.handle_replace_selection(text, request_id)
+                }
+                Command::GetTextOrContext {
                     context_length,
-                    cut_current_selection,
+                    max_length,
+                    request_id,
+                } => {
+                    let timing = self.strategy_for(None).timing(&TimingOverrides::default());
+                    self.handle_get_text_or_context(context_length, max_length, timing, request_id)
+                }
+                Command::GetDocumentText {
+                    max_length,
                     request_id,
-                } => self.handle_get_cursor_context(
+                } => self.handle_get_document_text(max_length, request_id),
+                Command::SelfTest { request_id } => self.handle_self_test(request_id),
+                Command::ConfigureStrategies {
+                    strategies,
+                    request_id,
+                } => {
+                    log::info!("Configuring strategies for {:?}", strategies.keys());
+                    self.strategies.configure(strategies);
+                    print_json_line(&StrategiesResponse {
+                        request_id,
+                        strategies: self.strategies.entries(),
+                    });
+                }
+                Command::Version => print_json_line(&ito_ipc::version_info!()),
+                // Handled on the stdin thread
+                Command::Cancel { .. } => {}
+                Command::GetCursorContext {
                     context_length,
                     cut_current_selection,
+                    direction,
+                    timing,
+                    app_hint,
+                    stream,
                     request_id,
-                ),
+                } => {
+                    let strategy = self.strategy_for(app_hint);
+                    let direction = match direction.as_deref().map(ContextDirection::from_name) {
+                        Some(Some(direction)) => direction,
+                        Some(None) => {
+                            log::warn!("Unknown direction {:?}, using before", direction);
+                            ContextDirection::default()
+                        }
+                        None => ContextDirection::default(),
+                    };
+                    self.handle_get_cursor_context(
+                        context_length,
+                        cut_current_selection.unwrap_or(strategy.cut),
+                        direction,
+                        strategy.timing(&timing),
+                        stream.unwrap_or(false),
+                        request_id,
+                    )
+                }
             }
         }
     }
 
-    fn handle_get_text(&mut self, max_length: Option<usize>, request_id: String) {
+    fn handle_get_text(
+        &mut self,
+        method: SelectionMethod,
+        format: TextFormat,
+        max_length: Option<usize>,
+        cut: bool,
+        timing: PollTiming,
+        request_id: String,
+    ) {
         let max_len = max_length.unwrap_or(10000);
 
-        let response = match get_selected_text() {
-            Ok(selected_text) => {
-                let text = if selected_text.is_empty() {
-                    None
-                } else if selected_text.len() > max_len {
-                    Some(selected_text.chars().take(max_len).collect())
-                } else {
-                    Some(selected_text)
-                };
+        let active = cancel::begin(&request_id);
+        // Capture the app right before copying so a focus change can't
+        // attribute the selection to the wrong window
+        let active_window = active_win_pos_rs::get_active_window().ok();
+        let (app_name, window_title) = match active_window {
+            Some(window) => (Some(window.app_name), Some(window.title)),
+            None => (None, None),
+        };
+
+        // Cutting always goes through the clipboard, whatever the method
+        let result = if format != TextFormat::Plain {
+            get_selected_rich_text(format, cut, timing)
+        } else if cut {
+            cut_selection(timing).map(|text| (text, TextFormat::Plain))
+        } else {
+            get_selected_text(method, timing).map(|text| (text, TextFormat::Plain))
+        };
+
+        let response = match result {
+            Ok((selected_text, returned_format)) => {
+                let (text, length) = truncate_chars(selected_text, max_len);
 
                 SelectedTextResponse {
                     request_id,
                     success: true,
-                    text: text.clone(),
+                    text,
+                    format: Some(returned_format),
                     error: None,
-                    length: text.as_ref().map(|t| t.len()).unwrap_or(0),
+                    length,
+                    app_name,
+                    window_title,
                 }
             }
             Err(e) => SelectedTextResponse {
                 request_id,
                 success: false,
                 text: None,
+                format: None,
                 error: Some(format!("Failed to get selected text: {}", e)),
                 length: 0,
+                app_name,
+                window_title,
+            },
+        };
+
+        if active.finish() {
+            print_json_line(&response);
+        }
+    }
+
+    fn handle_replace_selection(&mut self, text: String, request_id: String) {
+        let active = cancel::begin(&request_id);
+        let response = match replace_selection(&text) {
+            Ok(()) => ReplaceSelectionResponse {
+                request_id,
+                success: true,
+                error: None,
+            },
+            Err(e) => ReplaceSelectionResponse {
+                request_id,
+                success: false,
+                error: Some(format!("Failed to replace selection: {}", e)),
             },
         };
 
-        // Always respond with JSON
-        match serde_json::to_string(&response) {
-            Ok(json) => {
-                println!("{}", json);
-                if let Err(e) = io::stdout().flush() {
-                    eprintln!("[selected-text-reader] Error flushing stdout: {}", e);
+        if active.finish() {
+            print_json_line(&response);
+        }
+    }
+
+    fn handle_get_text_or_context(
+        &mut self,
+        context_length: Option<usize>,
+        max_length: Option<usize>,
+        timing: PollTiming,
+        request_id: String,
+    ) {
+        let active = cancel::begin(&request_id);
+        let result = get_text_or_context(context_length.unwrap_or(10), timing);
+        let respond = active.finish();
+
+        let response = match result {
+            Ok((source, text)) => {
+                let max_len = match source {
+                    TextSource::Selection => max_length.unwrap_or(10000),
+                    TextSource::Context => usize::MAX,
+                };
+                let (text, length) = truncate_chars(text, max_len);
+                TextOrContextResponse {
+                    request_id,
+                    success: true,
+                    source: Some(source),
+                    text,
+                    error: None,
+                    length,
                 }
             }
-            Err(e) => {
-                eprintln!(
-                    "[selected-text-reader] Error serializing response to JSON: {}",
-                    e
-                );
+            Err(e) if e.is::<cancel::Cancelled>() => TextOrContextResponse {
+                request_id,
+                success: false,
+                source: None,
+                text: None,
+                error: Some(e.to_string()),
+                length: 0,
+            },
+            Err(e) => TextOrContextResponse {
+                request_id,
+                success: false,
+                source: None,
+                text: None,
+                error: Some(format!("Failed to get text or context: {}", e)),
+                length: 0,
+            },
+        };
+
+        if respond {
+            print_json_line(&response);
+        }
+    }
+
+    fn handle_get_document_text(&mut self, max_length: Option<usize>, request_id: String) {
+        let max_len = max_length.unwrap_or(DEFAULT_DOCUMENT_MAX_LENGTH);
+
+        let active = cancel::begin(&request_id);
+        let result = get_document_text(PollTiming::default());
+        let respond = active.finish();
+
+        let response = match result {
+            Ok(document_text) => {
+                let (text, length) = truncate_chars(document_text, max_len);
+                DocumentTextResponse {
+                    request_id,
+                    success: true,
+                    text,
+                    error: None,
+                    length,
+                }
             }
+            Err(e) if e.is::<cancel::Cancelled>() => DocumentTextResponse {
+                request_id,
+                success: false,
+                text: None,
+                error: Some(e.to_string()),
+                length: 0,
+            },
+            Err(e) => DocumentTextResponse {
+                request_id,
+                success: false,
+                text: None,
+                error: Some(format!("Failed to get document text: {}", e)),
+                length: 0,
+            },
+        };
+
+        if respond {
+            print_json_line(&response);
+        }
+    }
+
+    fn handle_self_test(&mut self, request_id: String) {
+        let active = cancel::begin(&request_id);
+        let capabilities = Capabilities {
+            clipboard_rw: check_clipboard_rw(),
+            synthetic_input: can_send_synthetic_input(),
+        };
+        log::info!("Self-test: {:?}", capabilities);
+        if active.finish() {
+            print_json_line(&SelfTestResponse {
+                request_id,
+                capabilities,
+            });
         }
     }
 
     fn handle_get_cursor_context(
         &mut self,
         context_length: Option<usize>,
-        _cut_current_selection: Option<bool>,
+        cut: bool,
+        direction: ContextDirection,
+        timing: PollTiming,
+        stream: bool,
         request_id: String,
     ) {
         let context_len = context_length.unwrap_or(10);
 
-        let response = match get_cursor_context(context_len) {
-            Ok(context_text) => {
-                let text = if context_text.is_empty() {
-                    None
+        let active = cancel::begin(&request_id);
+        // Once cut there's no selection left, so the context is read from
+        // where it was
+        let cut_text = if cut {
+            match cut_selection(timing) {
+                Ok(text) => Some(text),
+                Err(e) => {
+                    if active.finish() {
+                        print_json_line(&CursorContextResponse {
+                            request_id,
+                            success: false,
+                            context_text: None,
+                            context: None,
+                            error: Some(format!("Failed to cut selection: {}", e)),
+                            length: 0,
+                            cut_text: None,
+                        });
+                    }
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let result = get_cursor_context(
+            context_len,
+            direction,
+            timing,
+            stream.then_some(request_id.as_str()),
+        );
+        let respond = active.finish();
+
+        let response = match result {
+            Ok(context) if direction == ContextDirection::Both => CursorContextResponse {
+                request_id,
+                success: true,
+                context_text: None,
+                length: context.before.chars().count() + context.after.chars().count(),
+                context: Some(context),
+                error: None,
+                cut_text,
+            },
+            Ok(context) => {
+                let context_text = if direction == ContextDirection::After {
+                    context.after
                 } else {
-                    Some(context_text.clone())
+                    context.before
                 };
+                let length = context_text.chars().count();
 
                 CursorContextResponse {
                     request_id,
                     success: true,
-                    context_text: text.clone(),
+                    context_text: (length > 0).then_some(context_text),
+                    context: None,
                     error: None,
-                    length: text.as_ref().map(|t| t.len()).unwrap_or(0),
+                    length,
+                    cut_text,
                 }
             }
+            Err(e) if e.is::<cancel::Cancelled>() => CursorContextResponse {
+                request_id,
+                success: false,
+                context_text: None,
+                context: None,
+                error: Some(e.to_string()),
+                length: 0,
+                cut_text,
+            },
             Err(e) => CursorContextResponse {
                 request_id,
                 success: false,
                 context_text: None,
+                context: None,
                 error: Some(format!("Failed to get cursor context: {}", e)),
                 length: 0,
+                cut_text,
             },
         };
 
-        // Always respond with JSON
-        match serde_json::to_string(&response) {
-            Ok(json) => {
-                println!("{}", json);
-                if let Err(e) = io::stdout().flush() {
-                    eprintln!("[selected-text-reader] Error flushing stdout: {}", e);
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "[selected-text-reader] Error serializing response to JSON: {}",
-                    e
-                );
-            }
+        if respond {
+            print_json_line(&response);
+        }
+    }
+}
+
+// Cap text at max_len chars, returning None for empty text along with the
+// resulting char count
+fn truncate_chars(text: String, max_len: usize) -> (Option<String>, usize) {
+    match text.char_indices().nth(max_len) {
+        Some((end, _)) => {
+            let mut text = text;
+            text.truncate(end);
+            ((max_len > 0).then_some(text), max_len)
+        }
+        None => {
+            let length = text.chars().count();
+            ((length > 0).then_some(text), length)
         }
     }
 }
 
 // Platform-specific implementations
 #[cfg(target_os = "macos")]
-fn get_selected_text() -> Result<String, Box<dyn std::error::Error>> {
-    macos::get_selected_text()
+fn get_selected_text(
+    method: SelectionMethod,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    macos::get_selected_text(method, timing)
 }
 
 #[cfg(target_os = "windows")]
-fn get_selected_text() -> Result<String, Box<dyn std::error::Error>> {
-    windows::get_selected_text()
+fn get_selected_text(
+    method: SelectionMethod,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    windows::get_selected_text(method, timing)
 }
 
-fn get_cursor_context(context_length: usize) -> Result<String, Box<dyn std::error::Error>> {
-    // Use keyboard commands to get cursor context
-    // This is more reliable across different applications than Accessibility API
-    let mut clipboard = Clipboard::new().map_err(|e| format!("Clipboard init failed: {}", e))?;
-
-    // Store original clipboard contents
-    let original_clipboard = clipboard.get_text().unwrap_or_default();
+#[cfg(target_os = "linux")]
+fn get_selected_text(
+    method: SelectionMethod,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    linux::get_selected_text(method, timing)
+}
 
-    // First, get any existing selected text
+// Paste text over the current selection through the clipboard, keeping the
+// user's clipboard intact
+fn replace_selection(text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // The guard restores the original clipboard contents on every return path
+    let mut clipboard = ClipboardGuard::new()?;
     clipboard
-        .clear()
-        .map_err(|e| format!("Clipboard clear failed: {}", e))?;
-    copy_selected_text()?;
-    thread::sleep(Duration::from_millis(25));
-    let selected_text = clipboard.get_text().unwrap_or_default();
-    let selected_char_count = count_editor_chars(&selected_text);
+        .set_text(text)
+        .map_err(|e| format!("Clipboard set failed: {}", e))?;
 
-    let context_text = if selected_char_count == 0 {
-        // Case 1: No selected text - proceed normally with cursor context
-        clipboard
-            .clear()
-            .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+    // Verify clipboard was actually set by reading it back
+    let mut attempts = 0;
+    while clipboard.get_text().ok().as_deref() != Some(text) {
+        attempts += 1;
+        if attempts > 50 {
+            return Err("Failed to verify clipboard content was set".into());
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
 
-        let result = select_previous_chars_and_copy(context_length, &mut clipboard);
-        match result {
-            Ok(precursor_text) => {
-                let precursor_char_count = count_editor_chars(&precursor_text);
-                // Shift right by the amount we grabbed
-                if precursor_char_count > 0 {
-                    let _ = shift_cursor_right_with_deselect(precursor_char_count);
-                }
-                precursor_text
-            }
-            Err(e) => format!("[ERROR] {}", e),
+    paste_clipboard()?;
+
+    // The target app reads the clipboard asynchronously after the paste
+    // keystroke, so wait before the guard restores it
+    thread::sleep(Duration::from_millis(150));
+
+    Ok(())
+}
+
+// Write a sentinel to the clipboard and read it back, restoring the user's
+// contents afterwards
+fn check_clipboard_rw() -> bool {
+    const SENTINEL: &str = "ito-self-test-7f3a";
+
+    let mut clipboard = match ClipboardGuard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => {
+            log::warn!("Self-test: {}", e);
+            return false;
         }
-    } else {
-        // Case 2: Some text already selected - try extending by one character
+    };
+    if let Err(e) = clipboard.set_text(SENTINEL) {
+        log::warn!("Self-test: clipboard set failed: {}", e);
+        return false;
+    }
+    // Some clipboard managers take a moment to settle, as in replace_selection
+    for _ in 0..50 {
+        if clipboard.get_text().ok().as_deref() == Some(SENTINEL) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+    log::warn!("Self-test: clipboard did not read back the text written to it");
+    false
+}
+
+const DEFAULT_DOCUMENT_MAX_LENGTH: usize = 100_000;
+
+// Read the document a side at a time: select from the cursor to the document
+// edge, copy, and collapse the selection back. Unlike Select-All, this leaves
+// the cursor where it was with one arrow press per side, however long the
+// document, and never leaves the whole document selected for a later paste
+// to replace
+fn get_document_text(timing: PollTiming) -> Result<String, Box<dyn std::error::Error>> {
+    // The guard restores the original clipboard contents on every return path
+    let mut clipboard = ClipboardGuard::new()?;
+    let mut text = String::new();
+
+    for side in [ContextSide::Before, ContextSide::After] {
+        cancel::check()?;
         clipboard
             .clear()
             .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+        select_to_document_edge(side)?;
+        thread::sleep(Duration::from_millis(10));
 
-        let result = select_previous_chars_and_copy(1, &mut clipboard);
+        // Anything other than this counts as the copy having landed
+        let baseline = clipboard.get_text().ok();
+        let side_text = copy_selected_text()
+            .map(|()| wait_for_clipboard_change(&mut clipboard, baseline.as_deref(), timing));
+
+        // Collapse whatever the copy did, since an empty or failed copy can
+        // still leave the side selected for a later paste to replace
+        collapse_selection(side)?;
+        text.push_str(&side_text?);
+    }
+
+    Ok(text)
+}
+
+// Cut the selection and return it, keeping the user's clipboard intact
+fn cut_selection(timing: PollTiming) -> Result<String, Box<dyn std::error::Error>> {
+    // The guard restores the original clipboard contents on every return path
+    let mut clipboard = ClipboardGuard::new()?;
+    clipboard
+        .clear()
+        .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+    cut_selected_text()?;
+    thread::sleep(timing.copy_settle);
+    Ok(clipboard.get_text().unwrap_or_default())
+}
+
+// Rich flavors only exist on the clipboard, so this always copies the
+// selection, regardless of the selection method
+fn get_selected_rich_text(
+    format: TextFormat,
+    cut: bool,
+    timing: PollTiming,
+) -> Result<(String, TextFormat), Box<dyn std::error::Error>> {
+    // The guard restores the original clipboard contents on every return path
+    let mut clipboard = ClipboardGuard::new()?;
+    clipboard
+        .clear()
+        .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+    if cut {
+        cut_selected_text()?;
+    } else {
+        copy_selected_text()?;
+    }
+    thread::sleep(timing.copy_settle);
+
+    let rich = match format {
+        TextFormat::Html | TextFormat::Markdown => clipboard.get().html().ok(),
+        TextFormat::Rtf => read_clipboard_rtf(),
+        TextFormat::Plain => None,
+    };
+
+    Ok(match rich.filter(|text| !text.trim().is_empty()) {
+        Some(html) if format == TextFormat::Markdown => {
+            (html2md::parse_html(&html), TextFormat::Markdown)
+        }
+        Some(text) => (text, format),
+        None => (clipboard.get_text().unwrap_or_default(), TextFormat::Plain),
+    })
+}
+
+// With `stream` set to the request id, context-partial messages are printed
+// as each side is read
+fn get_cursor_context(
+    context_length: usize,
+    direction: ContextDirection,
+    timing: PollTiming,
+    stream: Option<&str>,
+) -> Result<CursorContext, Box<dyn std::error::Error>> {
+    // Use keyboard commands to get cursor context
+    // This is more reliable across different applications than Accessibility API
+    // The guard restores the original clipboard contents on every return path
+    let mut clipboard = ClipboardGuard::new()?;
+
+    // First, get any existing selected text
+    let selected_text = copy_selection(&mut clipboard, timing)?;
+    let selected_char_count = count_editor_chars(&selected_text);
+
+    let mut editor = KeyboardEditor {
+        clipboard: &mut clipboard,
+        timing,
+        stream,
+        saved: None,
+    };
+    read_cursor_context(&mut editor, context_length, direction, selected_char_count)
+}
+
+/// The text field get-cursor-context works on, through Shift+Arrow presses
+/// and copies. Tests drive the same logic with a model editor
+trait Editor {
+    /// Clear the clipboard, press Shift+Arrow `count` times towards `side`
+    /// and copy the whole selection
+    fn extend_and_copy(
+        &mut self,
+        side: ContextSide,
+        count: usize,
+    ) -> Result<String, Box<dyn std::error::Error>>;
+
+    /// Same as extend_and_copy, reporting partial context along the way when
+    /// the host asked to stream
+    fn extend_and_copy_streaming(
+        &mut self,
+        side: ContextSide,
+        count: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.extend_and_copy(side, count)
+    }
+
+    /// Press Shift+Arrow `count` times away from `side`
+    fn retract(
+        &mut self,
+        side: ContextSide,
+        count: usize,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Press a bare arrow towards the cursor's starting point, collapsing a
+    /// selection that extend_and_copy made to where the cursor was
+    fn collapse(&mut self, side: ContextSide) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Remember the exact selection through accessibility, where the app
+    /// exposes it. Returns false when only the measured restore is available
+    fn save_selection(&mut self) -> bool {
+        false
+    }
+
+    /// Put back what save_selection remembered
+    fn restore_selection(&mut self) -> bool {
+        false
+    }
+}
+
+struct KeyboardEditor<'a> {
+    clipboard: &'a mut Clipboard,
+    timing: PollTiming,
+    stream: Option<&'a str>,
+    saved: Option<SavedSelection>,
+}
+
+impl Editor for KeyboardEditor<'_> {
+    fn extend_and_copy(
+        &mut self,
+        side: ContextSide,
+        count: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.clipboard
+            .clear()
+            .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+        extend_selection_and_copy(side, count, self.clipboard, self.timing)
+    }
+
+    fn extend_and_copy_streaming(
+        &mut self,
+        side: ContextSide,
+        count: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        match self.stream {
+            Some(request_id) => {
+                extend_selection_streaming(side, count, self.clipboard, self.timing, request_id)
+            }
+            None => self.extend_and_copy(side, count),
+        }
+    }
+
+    fn retract(
+        &mut self,
+        side: ContextSide,
+        count: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        undo_selection_extension(side, count, self.timing)
+    }
+
+    fn collapse(&mut self, side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+        collapse_selection(side)
+    }
+
+    fn save_selection(&mut self) -> bool {
+        self.saved = save_selection();
+        self.saved.is_some()
+    }
+
+    fn restore_selection(&mut self) -> bool {
+        self.saved.as_ref().is_some_and(restore_selection)
+    }
+}
+
+// Each side puts the selection back by undoing exactly the presses it
+// measured. Where accessibility exposes the selected range it's also saved up
+// front and set again at the end, so a copy that didn't land or a cancelled
+// read can't leave the selection moved
+fn read_cursor_context(
+    editor: &mut impl Editor,
+    context_length: usize,
+    direction: ContextDirection,
+    selected_char_count: usize,
+) -> Result<CursorContext, Box<dyn std::error::Error>> {
+    let saved = editor.save_selection();
+
+    let mut read_sides = || -> Result<CursorContext, Box<dyn std::error::Error>> {
+        let mut context = CursorContext::default();
+        if direction != ContextDirection::After {
+            context.before = get_context_side(
+                editor,
+                ContextSide::Before,
+                context_length,
+                selected_char_count,
+            )?;
+        }
+        if direction != ContextDirection::Before {
+            context.after = get_context_side(
+                editor,
+                ContextSide::After,
+                context_length,
+                selected_char_count,
+            )?;
+        }
+        Ok(context)
+    };
+    let context = read_sides();
+
+    if saved && !editor.restore_selection() {
+        log::warn!("Could not restore the selection through accessibility");
+    }
+    context
+}
+
+// Copy the selection and read it back, leaving the caller to restore the
+// clipboard
+fn copy_selection(
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    clipboard
+        .clear()
+        .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+    copy_selected_text()?;
+    thread::sleep(timing.copy_settle);
+    Ok(clipboard.get_text().unwrap_or_default())
+}
+
+// The selection if there is one, otherwise the context before the cursor.
+// Both reads share one guard, so the clipboard is restored once
+fn get_text_or_context(
+    context_length: usize,
+    timing: PollTiming,
+) -> Result<(TextSource, String), Box<dyn std::error::Error>> {
+    let mut clipboard = ClipboardGuard::new()?;
+
+    let selected_text = copy_selection(&mut clipboard, timing)?;
+    if !selected_text.is_empty() {
+        return Ok((TextSource::Selection, selected_text));
+    }
+
+    let mut editor = KeyboardEditor {
+        clipboard: &mut clipboard,
+        timing,
+        stream: None,
+        saved: None,
+    };
+    let context = get_context_side(&mut editor, ContextSide::Before, context_length, 0)?;
+    Ok((TextSource::Context, context))
+}
+
+// Extend the selection towards `side`, copy it, then undo the extension so the
+// user's cursor/selection ends up where it started
+fn get_context_side(
+    editor: &mut impl Editor,
+    side: ContextSide,
+    context_length: usize,
+    selected_char_count: usize,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let context_text = if selected_char_count == 0 {
+        // Case 1: No selected text - proceed normally with cursor context
+        let result = editor.extend_and_copy_streaming(side, context_length);
+        // Don't move the cursor back either, focus may be somewhere else now
+        cancel::check()?;
+        match result {
+            Ok(side_text) => {
+                let side_char_count = count_editor_chars(&side_text);
+                // Shift back by the amount we grabbed
+                if side_char_count > 0 {
+                    let _ = editor.retract(side, side_char_count);
+                } else if context_length > 0 {
+                    settle_empty_copy(editor, side);
+                }
+                side_text
+            }
+            Err(e) => format!("[ERROR] {}", e),
+        }
+    } else {
+        // Case 2: Some text already selected - try extending by one character.
+        // Which end moves depends on which way the user made the selection
+        let result = editor.extend_and_copy(side, 1);
+        cancel::check()?;
         match result {
             Ok(extended_text) => {
                 let extended_char_count = count_editor_chars(&extended_text);
 
                 if extended_char_count < selected_char_count {
                     // Selection shrunk - undo and return empty
-                    let _ = shift_cursor_right_with_deselect(1);
+                    let _ = editor.retract(side, 1);
                     String::new()
                 } else if extended_char_count == selected_char_count {
                     // Selection unchanged - return empty, no need to return cursor.
@@ -276,12 +1212,9 @@ fn get_cursor_context(context_length: usize) -> Result<String, Box<dyn std::erro
                 } else {
                     // Selection extended successfully - continue extending to get full
                     // context_length
-                    clipboard
-                        .clear()
-                        .map_err(|e| format!("Clipboard clear failed: {}", e))?;
-
                     let full_result =
-                        select_previous_chars_and_copy(context_length - 1, &mut clipboard);
+                        editor.extend_and_copy(side, context_length.saturating_sub(1));
+                    cancel::check()?;
                     match full_result {
                         Ok(full_context_text) => {
                             let full_context_char_count = count_editor_chars(&full_context_text);
@@ -291,19 +1224,18 @@ fn get_cursor_context(context_length: usize) -> Result<String, Box<dyn std::erro
                                 (full_context_char_count as i32 - selected_char_count as i32)
                                     .unsigned_abs() as usize;
                             if chars_to_undo > 0 {
-                                let _ = shift_cursor_right_with_deselect(chars_to_undo);
+                                let _ = editor.retract(side, chars_to_undo);
                             }
 
-                            // Return only the newly added context (first n characters where n is
-                            // the difference)
                             let new_context_char_count =
-                                full_context_char_count - selected_char_count;
-                            full_context_text
-                                .chars()
-                                .take(new_context_char_count)
-                                .collect()
+                                full_context_char_count.saturating_sub(selected_char_count);
+                            new_context(side, &full_context_text, new_context_char_count)
+                        }
+                        Err(e) => {
+                            // The first press did land, take it back
+                            let _ = editor.retract(side, 1);
+                            format!("[ERROR] {}", e)
                         }
-                        Err(e) => format!("[ERROR] {}", e),
                     }
                 }
             }
@@ -311,12 +1243,107 @@ fn get_cursor_context(context_length: usize) -> Result<String, Box<dyn std::erro
         }
     };
 
-    // Always restore original clipboard
-    let _ = clipboard.set_text(original_clipboard);
-
     Ok(context_text)
 }
 
+// An empty copy means either the cursor sits at the edge of the field, so the
+// presses selected nothing, or the copy never landed and left a selection we
+// can't measure. One Shift+Arrow back tells them apart: at the edge it selects
+// a character past where the cursor started, which copies and is undone. If
+// that copy is empty too, a bare arrow collapses whatever the presses selected
+// to where the cursor started
+fn settle_empty_copy(editor: &mut impl Editor, side: ContextSide) {
+    if editor.retract(side, 1).is_err() {
+        return;
+    }
+    match editor.extend_and_copy(side, 0) {
+        Ok(probe) if !probe.is_empty() => {
+            let _ = editor.retract(side.opposite(), 1);
+        }
+        _ => {
+            let _ = editor.collapse(side);
+        }
+    }
+}
+
+// Return only the newly added context: the first `count` cursor positions
+// before the selection, or the last `count` after it
+fn new_context(side: ContextSide, full_context_text: &str, count: usize) -> String {
+    let graphemes = full_context_text.graphemes(true);
+    match side {
+        ContextSide::Before => graphemes.take(count).collect(),
+        ContextSide::After => {
+            let total = count_editor_chars(full_context_text);
+            graphemes.skip(total.saturating_sub(count)).collect()
+        }
+    }
+}
+
+fn extend_selection_and_copy(
+    side: ContextSide,
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match side {
+        ContextSide::Before => select_previous_chars_and_copy(char_count, clipboard, timing),
+        ContextSide::After => select_next_chars_and_copy(char_count, clipboard, timing),
+    }
+}
+
+// How many chars a streaming read selects between partial reports
+const STREAM_STEP_CHARS: usize = 10;
+
+// Extend the selection a few chars at a time, copying after each step and
+// reporting the context read so far. Costs one extra copy per step, so only
+// used when the host asked to stream
+fn extend_selection_streaming(
+    side: ContextSide,
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+    request_id: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut text = String::new();
+    let mut text_chars = 0;
+    let mut selected = 0;
+    while selected < char_count {
+        let step = STREAM_STEP_CHARS.min(char_count - selected);
+        clipboard
+            .clear()
+            .map_err(|e| format!("Clipboard clear failed: {}", e))?;
+        // Each copy holds the whole selection so far
+        let step_text = extend_selection_and_copy(side, step, clipboard, timing)?;
+        selected += step;
+        let step_chars = count_editor_chars(&step_text);
+        // The selection stopped growing at the edge of the field
+        if step_chars <= text_chars {
+            break;
+        }
+        text = step_text;
+        text_chars = step_chars;
+        print_json_line(&ContextPartial {
+            response_type: "context-partial",
+            request_id,
+            side,
+            text: &text,
+            chars: text_chars,
+        });
+    }
+    Ok(text)
+}
+
+fn undo_selection_extension(
+    side: ContextSide,
+    char_count: usize,
+    timing: PollTiming,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match side {
+        ContextSide::Before => shift_cursor_right_with_deselect(char_count, timing.keystroke_delay),
+        ContextSide::After => shift_cursor_left_with_deselect(char_count, timing.keystroke_delay),
+    }
+}
+
 // Platform-specific helper functions
 #[cfg(target_os = "macos")]
 fn copy_selected_text() -> Result<(), Box<dyn std::error::Error>> {
@@ -328,30 +1355,219 @@ fn copy_selected_text() -> Result<(), Box<dyn std::error::Error>> {
     windows::copy_selected_text()
 }
 
+#[cfg(target_os = "linux")]
+fn copy_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    linux::copy_selected_text()
+}
+
+#[cfg(target_os = "macos")]
+fn cut_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    macos::native_cmd_x()
+}
+
+#[cfg(target_os = "windows")]
+fn cut_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    windows::cut_selected_text()
+}
+
+#[cfg(target_os = "linux")]
+fn cut_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    linux::cut_selected_text()
+}
+
+#[cfg(target_os = "macos")]
+fn select_to_document_edge(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    macos::select_to_document_edge(side)
+}
+
+#[cfg(target_os = "windows")]
+fn select_to_document_edge(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    windows::select_to_document_edge(side)
+}
+
+#[cfg(target_os = "linux")]
+fn select_to_document_edge(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    linux::select_to_document_edge(side)
+}
+
+#[cfg(target_os = "macos")]
+fn collapse_selection(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    macos::collapse_selection(side)
+}
+
+#[cfg(target_os = "windows")]
+fn collapse_selection(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    windows::collapse_selection(side)
+}
+
+#[cfg(target_os = "linux")]
+fn collapse_selection(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    linux::collapse_selection(side)
+}
+
+#[cfg(target_os = "macos")]
+fn paste_clipboard() -> Result<(), Box<dyn std::error::Error>> {
+    macos::native_cmd_v()
+}
+
+#[cfg(target_os = "windows")]
+fn paste_clipboard() -> Result<(), Box<dyn std::error::Error>> {
+    windows::paste_clipboard()
+}
+
+#[cfg(target_os = "linux")]
+fn paste_clipboard() -> Result<(), Box<dyn std::error::Error>> {
+    linux::paste_clipboard()
+}
+
+#[cfg(target_os = "macos")]
+fn can_send_synthetic_input() -> bool {
+    macos::can_send_synthetic_input()
+}
+
+#[cfg(target_os = "windows")]
+fn can_send_synthetic_input() -> bool {
+    windows::can_send_synthetic_input()
+}
+
+#[cfg(target_os = "linux")]
+fn can_send_synthetic_input() -> bool {
+    linux::can_send_synthetic_input()
+}
+
+#[cfg(target_os = "macos")]
+fn read_clipboard_rtf() -> Option<String> {
+    macos::read_clipboard_rtf()
+}
+
+#[cfg(target_os = "windows")]
+fn read_clipboard_rtf() -> Option<String> {
+    windows::read_clipboard_rtf()
+}
+
+#[cfg(target_os = "linux")]
+fn read_clipboard_rtf() -> Option<String> {
+    linux::read_clipboard_rtf()
+}
+
+#[cfg(target_os = "macos")]
+fn select_previous_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    macos::select_previous_chars_and_copy(char_count, clipboard, timing)
+}
+
 #[cfg(target_os = "macos")]
+fn select_next_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    macos::select_next_chars_and_copy(char_count, clipboard, timing)
+}
+
+#[cfg(target_os = "windows")]
 fn select_previous_chars_and_copy(
     char_count: usize,
     clipboard: &mut Clipboard,
+    timing: PollTiming,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    macos::select_previous_chars_and_copy(char_count, clipboard)
+    windows::select_previous_chars_and_copy(char_count, clipboard, timing)
 }
 
 #[cfg(target_os = "windows")]
+fn select_next_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    windows::select_next_chars_and_copy(char_count, clipboard, timing)
+}
+
+#[cfg(target_os = "linux")]
 fn select_previous_chars_and_copy(
     char_count: usize,
     clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    linux::select_previous_chars_and_copy(char_count, clipboard, timing)
+}
+
+#[cfg(target_os = "linux")]
+fn select_next_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    windows::select_previous_chars_and_copy(char_count, clipboard)
+    linux::select_next_chars_and_copy(char_count, clipboard, timing)
+}
+
+#[cfg(target_os = "macos")]
+fn shift_cursor_right_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    macos::shift_cursor_right_with_deselect(char_count, keystroke_delay)
 }
 
 #[cfg(target_os = "macos")]
-fn shift_cursor_right_with_deselect(char_count: usize) -> Result<(), Box<dyn std::error::Error>> {
-    macos::shift_cursor_right_with_deselect(char_count)
+fn shift_cursor_left_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    macos::shift_cursor_left_with_deselect(char_count, keystroke_delay)
+}
+
+#[cfg(target_os = "windows")]
+fn shift_cursor_right_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    windows::shift_cursor_right_with_deselect(char_count, keystroke_delay)
 }
 
 #[cfg(target_os = "windows")]
-fn shift_cursor_right_with_deselect(char_count: usize) -> Result<(), Box<dyn std::error::Error>> {
-    windows::shift_cursor_right_with_deselect(char_count)
+fn shift_cursor_left_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    windows::shift_cursor_left_with_deselect(char_count, keystroke_delay)
+}
+
+#[cfg(target_os = "linux")]
+fn shift_cursor_right_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    linux::shift_cursor_right_with_deselect(char_count, keystroke_delay)
+}
+
+#[cfg(target_os = "linux")]
+fn shift_cursor_left_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    linux::shift_cursor_left_with_deselect(char_count, keystroke_delay)
+}
+
+#[cfg(target_os = "macos")]
+use macos::{restore_selection, save_selection, SavedSelection};
+
+// Only macOS exposes the selected range through accessibility so far, the
+// other platforms rely on the measured restore
+#[cfg(not(target_os = "macos"))]
+enum SavedSelection {}
+
+#[cfg(not(target_os = "macos"))]
+fn save_selection() -> Option<SavedSelection> {
+    None
+}
+
+#[cfg(not(target_os = "macos"))]
+fn restore_selection(saved: &SavedSelection) -> bool {
+    match *saved {}
 }
 
 #[cfg(target_os = "macos")]
@@ -363,3 +1579,466 @@ fn count_editor_chars(text: &str) -> usize {
 fn count_editor_chars(text: &str) -> usize {
     windows::count_editor_chars(text)
 }
+
+#[cfg(target_os = "linux")]
+fn count_editor_chars(text: &str) -> usize {
+    linux::count_editor_chars(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_method_from_name() {
+        assert_eq!(
+            SelectionMethod::from_name("auto"),
+            Some(SelectionMethod::Auto)
+        );
+        assert_eq!(
+            SelectionMethod::from_name("accessibility"),
+            Some(SelectionMethod::Accessibility)
+        );
+        assert_eq!(
+            SelectionMethod::from_name("clipboard"),
+            Some(SelectionMethod::Clipboard)
+        );
+        assert_eq!(SelectionMethod::from_name("ocr"), None);
+    }
+
+    #[test]
+    fn test_truncate_chars_multibyte() {
+        // 8 chars but 12 bytes
+        let (text, length) = truncate_chars("Hello 世界".to_string(), 7);
+        assert_eq!(text.as_deref(), Some("Hello 世"));
+        assert_eq!(length, 7);
+        assert_eq!(text.unwrap().chars().count(), length);
+
+        let (text, length) = truncate_chars("Hello 世界".to_string(), 8);
+        assert_eq!(text.as_deref(), Some("Hello 世界"));
+        assert_eq!(length, 8);
+    }
+
+    #[test]
+    fn test_truncate_chars_emoji() {
+        let (text, length) = truncate_chars("Hi 👋👋".to_string(), 4);
+        assert_eq!(text.as_deref(), Some("Hi 👋"));
+        assert_eq!(length, 4);
+
+        let (text, length) = truncate_chars("👋".to_string(), 10);
+        assert_eq!(text.as_deref(), Some("👋"));
+        assert_eq!(length, 1);
+    }
+
+    #[test]
+    fn test_truncate_chars_empty() {
+        assert_eq!(truncate_chars(String::new(), 10), (None, 0));
+        assert_eq!(truncate_chars("abc".to_string(), 0), (None, 0));
+    }
+
+    #[test]
+    fn test_replace_selection_parses() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"replace-selection","text":"new text","requestId":"r1"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::ReplaceSelection { text, request_id } if text == "new text" && request_id == "r1"
+        ));
+    }
+
+    #[test]
+    fn test_cut_current_selection_parses() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"get-text","cutCurrentSelection":true,"requestId":"r4"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::GetText {
+                cut_current_selection: Some(true),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_configure_strategies_parses() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"configure-strategies","strategies":{"Code":{"pollTimeoutMs":800}},"requestId":"r9"}"#,
+        )
+        .unwrap();
+        let Command::ConfigureStrategies { strategies, .. } = command else {
+            panic!("expected configure-strategies");
+        };
+        assert_eq!(strategies["Code"].poll_timeout_ms, Some(800));
+        assert_eq!(strategies["Code"].ax_first, None);
+
+        let command: Command = serde_json::from_str(
+            r#"{"command":"get-cursor-context","appHint":"com.microsoft.VSCode","requestId":"r10"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::GetCursorContext { app_hint: Some(hint), .. } if hint == "com.microsoft.VSCode"
+        ));
+    }
+
+    #[test]
+    fn test_context_partial_serializes() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"get-cursor-context","stream":true,"requestId":"r11"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::GetCursorContext {
+                stream: Some(true),
+                ..
+            }
+        ));
+        let partial = ContextPartial {
+            response_type: "context-partial",
+            request_id: "r11",
+            side: ContextSide::Before,
+            text: "lo wörld",
+            chars: 8,
+        };
+        assert_eq!(
+            serde_json::to_value(&partial).unwrap(),
+            serde_json::json!({
+                "type": "context-partial",
+                "requestId": "r11",
+                "side": "before",
+                "text": "lo wörld",
+                "chars": 8,
+            })
+        );
+    }
+
+    #[test]
+    fn test_get_text_or_context_parses() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"get-text-or-context","contextLength":20,"maxLength":500,"requestId":"r13"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::GetTextOrContext {
+                context_length: Some(20),
+                max_length: Some(500),
+                request_id,
+            } if request_id == "r13"
+        ));
+        assert_eq!(
+            serde_json::to_value(TextSource::Context).unwrap(),
+            "context"
+        );
+    }
+
+    #[test]
+    fn test_get_document_text_parses() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"get-document-text","maxLength":500,"requestId":"r5"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::GetDocumentText {
+                max_length: Some(500),
+                request_id,
+            } if request_id == "r5"
+        ));
+    }
+
+    #[test]
+    fn test_cancel_parses() {
+        let command: Command =
+            serde_json::from_str(r#"{"command":"cancel","requestId":"r2"}"#).unwrap();
+        assert!(matches!(command, Command::Cancel { request_id } if request_id == "r2"));
+    }
+
+    #[test]
+    fn test_self_test_parses_and_reports_capabilities() {
+        let command: Command =
+            serde_json::from_str(r#"{"command":"self-test","requestId":"r3"}"#).unwrap();
+        assert!(matches!(command, Command::SelfTest { request_id } if request_id == "r3"));
+
+        let response = SelfTestResponse {
+            request_id: "r3".to_string(),
+            capabilities: Capabilities {
+                clipboard_rw: true,
+                synthetic_input: false,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({
+                "requestId": "r3",
+                "capabilities": {"clipboard_rw": true, "synthetic_input": false},
+            })
+        );
+    }
+
+    // Editor model for the cursor tests: the cursor sits between grapheme
+    // clusters and each Shift+Arrow press moves it by one
+    fn press_shift_arrow(text: &str, cursor: usize, presses: isize) -> (usize, String) {
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        let moved = (cursor as isize + presses).clamp(0, graphemes.len() as isize) as usize;
+        let selected = graphemes[cursor.min(moved)..cursor.max(moved)].concat();
+        (moved, selected)
+    }
+
+    #[test]
+    fn test_cursor_restored_across_zwj_and_combining_marks() {
+        let text = "see 👨‍👩‍👧 at the cafe\u{301} 🇯🇵";
+        let start = text.graphemes(true).count();
+        for context_length in 1..=start {
+            // Select backwards, then shift back right by what was copied
+            let (selected_to, copied) = press_shift_arrow(text, start, -(context_length as isize));
+            let (restored, _) =
+                press_shift_arrow(text, selected_to, count_editor_chars(&copied) as isize);
+            assert_eq!(restored, start, "context_length {}", context_length);
+
+            // And forwards from the beginning
+            let (selected_to, copied) = press_shift_arrow(text, 0, context_length as isize);
+            let (restored, _) =
+                press_shift_arrow(text, selected_to, -(count_editor_chars(&copied) as isize));
+            assert_eq!(restored, 0, "context_length {}", context_length);
+        }
+
+        // Counting chars would overshoot by the extra code points
+        let (_, copied) = press_shift_arrow(text, start, -1);
+        assert_eq!(copied, "🇯🇵");
+        assert_eq!(count_editor_chars(&copied), 1);
+        assert_eq!(copied.chars().count(), 2);
+    }
+
+    // A text field with an anchored selection: Shift+Arrow moves the focus
+    // end, and the selection is whatever lies between anchor and focus
+    struct ModelEditor {
+        graphemes: Vec<&'static str>,
+        anchor: usize,
+        focus: usize,
+        // Copies come back empty, as when the app is slow to copy
+        copy_fails: bool,
+        // Exposes the selected range like an accessibility API
+        accessible: bool,
+        saved: Option<(usize, usize)>,
+    }
+
+    impl ModelEditor {
+        fn new(text: &'static str, anchor: usize, focus: usize) -> Self {
+            ModelEditor {
+                graphemes: text.graphemes(true).collect(),
+                anchor,
+                focus,
+                copy_fails: false,
+                accessible: false,
+                saved: None,
+            }
+        }
+
+        fn selection(&self) -> String {
+            self.graphemes[self.anchor.min(self.focus)..self.anchor.max(self.focus)].concat()
+        }
+
+        fn press(&mut self, towards: ContextSide, count: usize) {
+            self.focus = match towards {
+                ContextSide::Before => self.focus.saturating_sub(count),
+                ContextSide::After => (self.focus + count).min(self.graphemes.len()),
+            };
+        }
+    }
+
+    impl Editor for ModelEditor {
+        fn extend_and_copy(
+            &mut self,
+            side: ContextSide,
+            count: usize,
+        ) -> Result<String, Box<dyn std::error::Error>> {
+            self.press(side, count);
+            Ok(if self.copy_fails {
+                String::new()
+            } else {
+                self.selection()
+            })
+        }
+
+        fn retract(
+            &mut self,
+            side: ContextSide,
+            count: usize,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            self.press(side.opposite(), count);
+            Ok(())
+        }
+
+        // Like a real bare arrow: collapses a selection to the end it
+        // points at, and moves a plain cursor one step
+        fn collapse(&mut self, side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+            let (start, end) = (self.anchor.min(self.focus), self.anchor.max(self.focus));
+            let to = match side {
+                ContextSide::Before if start == end => (end + 1).min(self.graphemes.len()),
+                ContextSide::Before => end,
+                ContextSide::After if start == end => start.saturating_sub(1),
+                ContextSide::After => start,
+            };
+            (self.anchor, self.focus) = (to, to);
+            Ok(())
+        }
+
+        fn save_selection(&mut self) -> bool {
+            self.saved = self.accessible.then_some((self.anchor, self.focus));
+            self.saved.is_some()
+        }
+
+        fn restore_selection(&mut self) -> bool {
+            match self.saved {
+                Some((anchor, focus)) => {
+                    (self.anchor, self.focus) = (anchor, focus);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_cursor_context_restores_selection() {
+        let text = "say 👋 to the cafe\u{301} 🇯🇵";
+        let len = text.graphemes(true).count();
+        // No selection, selections made either way, and ones touching the edges
+        let selections = [
+            (0, 0),
+            (6, 6),
+            (len, len),
+            (4, 9),
+            (9, 4),
+            (0, 3),
+            (3, 0),
+            (len - 2, len),
+            (len, len - 2),
+            (5, 6),
+            (6, 5),
+        ];
+        let directions = [
+            ContextDirection::Before,
+            ContextDirection::After,
+            ContextDirection::Both,
+        ];
+        for (anchor, focus) in selections {
+            for direction in directions {
+                for context_length in [1, 3, len + 5] {
+                    let mut editor = ModelEditor::new(text, anchor, focus);
+                    let before = editor.selection();
+                    let selected = count_editor_chars(&before);
+                    let context =
+                        read_cursor_context(&mut editor, context_length, direction, selected)
+                            .unwrap();
+                    let case = format!("{:?} {:?} {}", (anchor, focus), direction, context_length);
+                    assert_eq!((editor.anchor, editor.focus), (anchor, focus), "{}", case);
+                    assert_eq!(editor.selection().as_bytes(), before.as_bytes(), "{}", case);
+
+                    // Without a selection both sides are always read
+                    if anchor == focus && direction == ContextDirection::Both {
+                        let start = anchor.saturating_sub(context_length);
+                        let end = (anchor + context_length).min(len);
+                        assert_eq!(context.before, editor.graphemes[start..anchor].concat());
+                        assert_eq!(context.after, editor.graphemes[anchor..end].concat());
+                    }
+                }
+            }
+        }
+
+        // A copy that never lands can't be measured, so the presses are
+        // collapsed back to where the cursor started
+        for direction in directions {
+            let mut editor = ModelEditor::new(text, 6, 6);
+            editor.copy_fails = true;
+            read_cursor_context(&mut editor, 3, direction, 0).unwrap();
+            assert_eq!((editor.anchor, editor.focus), (6, 6), "{:?}", direction);
+        }
+        let mut editor = ModelEditor::new(text, 6, 6);
+        editor.copy_fails = true;
+        editor.accessible = true;
+        read_cursor_context(&mut editor, 3, ContextDirection::Before, 0).unwrap();
+        assert_eq!((editor.anchor, editor.focus), (6, 6));
+    }
+
+    #[test]
+    fn test_new_context_keeps_clusters_whole() {
+        // The selection is "é" written with a combining accent, extended by two
+        // clusters on either side
+        assert_eq!(new_context(ContextSide::Before, "👨‍👩‍👧 e\u{301}", 2), "👨‍👩‍👧 ");
+        assert_eq!(new_context(ContextSide::After, "e\u{301} 🇯🇵", 2), " 🇯🇵");
+        assert_eq!(new_context(ContextSide::After, "abc", 5), "abc");
+    }
+
+    #[test]
+    fn test_context_direction_from_name() {
+        assert_eq!(
+            ContextDirection::from_name("before"),
+            Some(ContextDirection::Before)
+        );
+        assert_eq!(
+            ContextDirection::from_name("after"),
+            Some(ContextDirection::After)
+        );
+        assert_eq!(
+            ContextDirection::from_name("both"),
+            Some(ContextDirection::Both)
+        );
+        assert_eq!(ContextDirection::from_name("up"), None);
+    }
+
+    #[test]
+    fn test_poll_timing_from_millis() {
+        let default = PollTiming::default();
+        assert_eq!(default.interval, Duration::from_millis(10));
+        assert_eq!(default.timeout, Duration::from_millis(200));
+
+        let custom = PollTiming::from_millis(Some(50), Some(2000));
+        assert_eq!(custom.interval, Duration::from_millis(50));
+        assert_eq!(custom.timeout, Duration::from_millis(2000));
+
+        // A zero interval is clamped so polling can't spin
+        assert_eq!(
+            PollTiming::from_millis(Some(0), None).interval,
+            Duration::from_millis(1)
+        );
+    }
+
+    #[test]
+    fn test_timing_overrides_parse() {
+        let command: Command = serde_json::from_str(
+            r#"{"command":"get-cursor-context","poll_timeout_ms":900,"keystrokeDelayMs":8,"copySettleMs":60,"requestId":"r12"}"#,
+        )
+        .unwrap();
+        let Command::GetCursorContext { timing, .. } = command else {
+            panic!("expected get-cursor-context");
+        };
+        let timing = AppStrategy::default().timing(&timing);
+        assert_eq!(timing.timeout, Duration::from_millis(900));
+        assert_eq!(timing.interval, Duration::from_millis(10));
+        assert_eq!(timing.keystroke_delay, Duration::from_millis(8));
+        assert_eq!(timing.copy_settle, Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_text_format_from_name() {
+        assert_eq!(TextFormat::from_name("plain"), Some(TextFormat::Plain));
+        assert_eq!(TextFormat::from_name("html"), Some(TextFormat::Html));
+        assert_eq!(TextFormat::from_name("rtf"), Some(TextFormat::Rtf));
+        assert_eq!(
+            TextFormat::from_name("markdown"),
+            Some(TextFormat::Markdown)
+        );
+        assert_eq!(TextFormat::from_name("docx"), None);
+        assert_eq!(
+            serde_json::to_string(&TextFormat::Markdown).unwrap(),
+            "\"markdown\""
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Per-app tuning for reading text.
+//!
+//! Copy timing and method that work in one app fail in another: Electron
+//! editors don't expose their selection through accessibility and update the
+//! clipboard late, and remote desktop clients round-trip every copy over the
+//! network. GetText and GetCursorContext look the app up here, by the
+//! request's `appHint` or the frontmost app's name, and fall back to the
+//! defaults for apps not in the table. The host can add entries or override
+//! fields of existing ones with `configure-strategies`.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::{
+    PollTiming, TimingOverrides, DEFAULT_COPY_SETTLE_MS, DEFAULT_KEYSTROKE_DELAY_MS,
+    DEFAULT_POLL_INTERVAL_MS, DEFAULT_POLL_TIMEOUT_MS,
+};
+use crate::SelectionMethod;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct AppStrategy {
+    /// Read the selection through accessibility (PRIMARY on Linux) before
+    /// falling back to a copy, when GetText doesn't name a method
+    pub ax_first: bool,
+    pub poll_interval_ms: u64,
+    pub poll_timeout_ms: u64,
+    pub keystroke_delay_ms: u64,
+    pub copy_settle_ms: u64,
+    /// Cut the selection instead of copying it when the request doesn't say
+    pub cut: bool,
+}
+
+impl Default for AppStrategy {
+    fn default() -> Self {
+        AppStrategy {
+            ax_first: true,
+            poll_interval_ms: DEFAULT_POLL_INTERVAL_MS,
+            poll_timeout_ms: DEFAULT_POLL_TIMEOUT_MS,
+            keystroke_delay_ms: DEFAULT_KEYSTROKE_DELAY_MS,
+            copy_settle_ms: DEFAULT_COPY_SETTLE_MS,
+            cut: false,
+        }
+    }
+}
+
+/// Fields of an [`AppStrategy`] set by `configure-strategies`, merged over
+/// the app's current entry so anything left out keeps its value
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StrategyOverride {
+    pub ax_first: Option<bool>,
+    pub poll_interval_ms: Option<u64>,
+    pub poll_timeout_ms: Option<u64>,
+    pub keystroke_delay_ms: Option<u64>,
+    pub copy_settle_ms: Option<u64>,
+    pub cut: Option<bool>,
+}
+
+impl AppStrategy {
+    fn apply(&mut self, fields: StrategyOverride) {
+        self.ax_first = fields.ax_first.unwrap_or(self.ax_first);
+        self.poll_interval_ms = fields.poll_interval_ms.unwrap_or(self.poll_interval_ms);
+        self.poll_timeout_ms = fields.poll_timeout_ms.unwrap_or(self.poll_timeout_ms);
+        self.keystroke_delay_ms = fields.keystroke_delay_ms.unwrap_or(self.keystroke_delay_ms);
+        self.copy_settle_ms = fields.copy_settle_ms.unwrap_or(self.copy_settle_ms);
+        self.cut = fields.cut.unwrap_or(self.cut);
+    }
+
+    pub fn method(&self) -> SelectionMethod {
+        if self.ax_first {
+            SelectionMethod::Auto
+        } else {
+            SelectionMethod::Clipboard
+        }
+    }
+
+    /// Timing for this app, with any values given in the request taking
+    /// precedence
+    pub fn timing(&self, overrides: &TimingOverrides) -> PollTiming {
+        let millis = |value: Option<u64>, default| Duration::from_millis(value.unwrap_or(default));
+        PollTiming {
+            keystroke_delay: millis(overrides.keystroke_delay_ms, self.keystroke_delay_ms),
+            copy_settle: millis(overrides.copy_settle_ms, self.copy_settle_ms),
+            ..PollTiming::from_millis(
+                Some(overrides.poll_interval_ms.unwrap_or(self.poll_interval_ms)),
+                Some(overrides.poll_timeout_ms.unwrap_or(self.poll_timeout_ms)),
+            )
+        }
+    }
+}
+
+/// Strategies keyed by lowercased bundle id or app name
+pub struct StrategyTable {
+    entries: BTreeMap<String, AppStrategy>,
+}
+
+impl StrategyTable {
+    pub fn builtin() -> Self {
+        let electron = AppStrategy {
+            ax_first: false,
+            poll_timeout_ms: 400,
+            copy_settle_ms: 50,
+            ..AppStrategy::default()
+        };
+        let remote = AppStrategy {
+            ax_first: false,
+            poll_timeout_ms: 1000,
+            keystroke_delay_ms: 10,
+            copy_settle_ms: 100,
+            ..AppStrategy::default()
+        };
+        let mut table = StrategyTable {
+            entries: BTreeMap::new(),
+        };
+        for app in [
+            "com.microsoft.VSCode",
+            "Code",
+            "Visual Studio Code",
+            "com.todesktop.230313mzl4w4u92",
+            "Cursor",
+            "com.tinyspeck.slackmacgap",
+            "Slack",
+        ] {
+            table.insert(app, electron.clone());
+        }
+        for app in [
+            "com.microsoft.rdc.macos",
+            "Microsoft Remote Desktop",
+            "Windows App",
+            "mstsc",
+        ] {
+            table.insert(app, remote.clone());
+        }
+        table
+    }
+
+    fn insert(&mut self, app: &str, strategy: AppStrategy) {
+        self.entries.insert(app.to_lowercase(), strategy);
+    }
+
+    /// Merges each override over the app's entry, or the defaults for an app
+    /// not in the table, leaving the rest of the table alone
+    pub fn configure(&mut self, strategies: BTreeMap<String, StrategyOverride>) {
+        for (app, fields) in strategies {
+            self.entries
+                .entry(app.to_lowercase())
+                .or_default()
+                .apply(fields);
+        }
+    }
+
+    pub fn lookup(&self, app: Option<&str>) -> AppStrategy {
+        app.and_then(|app| self.entries.get(&app.to_lowercase()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn entries(&self) -> &BTreeMap<String, AppStrategy> {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_and_configure() {
+        let mut table = StrategyTable::builtin();
+        let code = table.lookup(Some("com.microsoft.vscode"));
+        assert_eq!(code.method(), SelectionMethod::Clipboard);
+        let overrides = TimingOverrides {
+            poll_timeout_ms: Some(50),
+            ..TimingOverrides::default()
+        };
+        let timing = code.timing(&overrides);
+        assert_eq!(timing.timeout, Duration::from_millis(50));
+        assert_eq!(timing.copy_settle, Duration::from_millis(50));
+        assert_eq!(timing.keystroke_delay, Duration::from_millis(1));
+        assert_eq!(table.lookup(Some("Notes")), AppStrategy::default());
+        assert_eq!(table.lookup(None), AppStrategy::default());
+
+        let overrides: BTreeMap<String, StrategyOverride> = serde_json::from_str(
+            r#"{"Notes": {"keystrokeDelayMs": 5, "cut": true}, "Slack": {"pollTimeoutMs": 800}}"#,
+        )
+        .unwrap();
+        table.configure(overrides);
+        let notes = table.lookup(Some("notes"));
+        assert!(notes.cut && notes.ax_first);
+        assert_eq!(
+            notes.timing(&TimingOverrides::default()).keystroke_delay,
+            Duration::from_millis(5)
+        );
+        // A partial override keeps the entry's other fields
+        let slack = table.lookup(Some("Slack"));
+        assert_eq!(slack.poll_timeout_ms, 800);
+        assert!(!slack.ax_first);
+        assert_eq!(slack.copy_settle_ms, 50);
+        // Built-in entries not named are kept
+        assert_eq!(table.lookup(Some("Cursor")).poll_timeout_ms, 400);
+    }
+}
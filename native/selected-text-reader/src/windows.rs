@@ -1,21 +1,52 @@
+use crate::clipboard::{wait_for_clipboard_change, PollTiming};
+use crate::{ContextSide, SelectionMethod};
 use arboard::Clipboard;
 use selection::get_text;
 use std::thread;
 use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
 
-// Count characters as the editor sees them (CRLF = 1 cursor position on
-// Windows)
+// Count characters as the editor sees them. The cursor moves by grapheme
+// cluster, which also covers CRLF being a single cursor position on Windows
 pub fn count_editor_chars(text: &str) -> usize {
-    // On Windows, editors treat CRLF as a single cursor position when navigating
-    // with arrow keys
-    text.replace("\r\n", "\n").chars().count()
+    text.graphemes(true).count()
 }
 
-pub fn get_selected_text() -> Result<String, Box<dyn std::error::Error>> {
+// The selection crate already prefers UI Automation and falls back to the
+// clipboard internally, so there's no method or timing choice to make here
+pub fn get_selected_text(
+    _method: SelectionMethod,
+    _timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
     let selected_text = get_text();
     Ok(selected_text)
 }
 
+// Read the "Rich Text Format" flavor the source app put on the clipboard, if any
+pub fn read_clipboard_rtf() -> Option<String> {
+    use clipboard_win::{formats, get_clipboard, register_format};
+
+    let format = register_format("Rich Text Format")?;
+    let data: Vec<u8> = get_clipboard(formats::RawData(format.get())).ok()?;
+    // RTF is 7-bit ASCII, but drop the trailing NUL the flavor usually carries
+    let text = String::from_utf8_lossy(&data);
+    Some(text.trim_end_matches('\0').to_string())
+}
+
+// SendInput blocked by policy or UIPI only shows up per target window, so
+// this can only tell that the input backend is usable at all
+pub fn can_send_synthetic_input() -> bool {
+    use enigo::{Enigo, Settings};
+
+    match Enigo::new(&Settings::default()) {
+        Ok(_) => true,
+        Err(e) => {
+            log::warn!("Synthetic input unavailable: {}", e);
+            false
+        }
+    }
+}
+
 pub fn copy_selected_text() -> Result<(), Box<dyn std::error::Error>> {
     use enigo::{Direction, Enigo, Key, Keyboard, Settings};
 
@@ -27,52 +58,139 @@ pub fn copy_selected_text() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+pub fn cut_selected_text() -> Result<(), Box<dyn std::error::Error>> {
+    use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Unicode('x'), Direction::Click)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
+// Extend the selection to the start (Shift+Ctrl+Home) or end (Shift+Ctrl+End)
+// of the document
+pub fn select_to_document_edge(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+
+    let edge = match side {
+        ContextSide::Before => Key::Home,
+        ContextSide::After => Key::End,
+    };
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Shift, Direction::Press)?;
+    enigo.key(edge, Direction::Click)?;
+    enigo.key(Key::Shift, Direction::Release)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
+// A bare arrow press collapses the selection to that end, which is where the
+// cursor was before select_to_document_edge
+pub fn collapse_selection(side: ContextSide) -> Result<(), Box<dyn std::error::Error>> {
+    use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+
+    let arrow = match side {
+        ContextSide::Before => Key::RightArrow,
+        ContextSide::After => Key::LeftArrow,
+    };
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(arrow, Direction::Click)?;
+
+    Ok(())
+}
+
+pub fn paste_clipboard() -> Result<(), Box<dyn std::error::Error>> {
+    use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+
+    let mut enigo = Enigo::new(&Settings::default())?;
+    enigo.key(Key::Control, Direction::Press)?;
+    enigo.key(Key::Unicode('v'), Direction::Click)?;
+    enigo.key(Key::Control, Direction::Release)?;
+
+    Ok(())
+}
+
 // Simple function to select previous N characters and copy them
 pub fn select_previous_chars_and_copy(
     char_count: usize,
     clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    select_chars_and_copy(enigo::Key::LeftArrow, char_count, clipboard, timing)
+}
+
+// Same as select_previous_chars_and_copy, but selecting forward with
+// Shift+Right
+pub fn select_next_chars_and_copy(
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
+) -> Result<String, Box<dyn std::error::Error>> {
+    select_chars_and_copy(enigo::Key::RightArrow, char_count, clipboard, timing)
+}
+
+fn select_chars_and_copy(
+    arrow: enigo::Key,
+    char_count: usize,
+    clipboard: &mut Clipboard,
+    timing: PollTiming,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Send Shift+Left N times to select precursor text
+    // Send Shift+Arrow N times to select context text
     for _ in 0..char_count {
+        crate::cancel::check()?;
         #[cfg(target_os = "windows")]
         {
             use enigo::{Direction, Enigo, Key, Keyboard, Settings};
             let mut enigo = Enigo::new(&Settings::default())?;
             enigo.key(Key::Shift, Direction::Press)?;
-            enigo.key(Key::LeftArrow, Direction::Click)?;
+            enigo.key(arrow, Direction::Click)?;
             enigo.key(Key::Shift, Direction::Release)?;
         }
 
         // Brief pause between selections
-        thread::sleep(Duration::from_millis(1));
+        thread::sleep(timing.keystroke_delay);
     }
 
     // Allow selection to complete
     thread::sleep(Duration::from_millis(10));
 
+    // Anything other than this counts as the copy having landed
+    let baseline = clipboard.get_text().ok();
+
     copy_selected_text()?;
 
     // Adaptively wait for and get text from clipboard
-    let mut context_text = String::new();
-    let max_retries = 20; // Poll for a maximum of 20 * 10ms = 200ms
-    for _ in 0..max_retries {
-        // Give a tiny bit of time for the clipboard to update
-        thread::sleep(Duration::from_millis(10));
-
-        if let Ok(text) = clipboard.get_text() {
-            if !text.is_empty() {
-                context_text = text;
-                break; // Success! We got the text.
-            }
-        }
-    }
-
-    Ok(context_text)
+    Ok(wait_for_clipboard_change(
+        clipboard,
+        baseline.as_deref(),
+        timing,
+    ))
 }
 
 // Shift cursor right while deselecting text
 pub fn shift_cursor_right_with_deselect(
     char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    shift_cursor_with_deselect(enigo::Key::RightArrow, char_count, keystroke_delay)
+}
+
+// Shift cursor left while deselecting text selected with Shift+Right
+pub fn shift_cursor_left_with_deselect(
+    char_count: usize,
+    keystroke_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    shift_cursor_with_deselect(enigo::Key::LeftArrow, char_count, keystroke_delay)
+}
+
+fn shift_cursor_with_deselect(
+    arrow: enigo::Key,
+    char_count: usize,
+    keystroke_delay: Duration,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if char_count == 0 {
         return Ok(());
@@ -83,12 +201,12 @@ pub fn shift_cursor_right_with_deselect(
             use enigo::{Direction, Enigo, Key, Keyboard, Settings};
             let mut enigo = Enigo::new(&Settings::default())?;
             enigo.key(Key::Shift, Direction::Press)?;
-            enigo.key(Key::RightArrow, Direction::Click)?;
+            enigo.key(arrow, Direction::Click)?;
             enigo.key(Key::Shift, Direction::Release)?;
         }
         // Brief pause between movements
         if char_count > 1 {
-            thread::sleep(Duration::from_millis(1));
+            thread::sleep(keystroke_delay);
         }
     }
 
@@ -0,0 +1,113 @@
+//! Overlap removal between successive chunks typed into the same window.
+//!
+//! Transcription chunks sometimes repeat the end of the previous chunk, which
+//! shows up as duplicated text, most visibly in terminals. With
+//! `--dedupe-overlap` the tail of what was typed is remembered per target
+//! window in a temp file, and the longest prefix of the new chunk that repeats
+//! it is dropped before typing.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+// How much of the typed text is remembered, in chars
+const TAIL_CHARS: usize = 200;
+// Anything shorter than this is as likely to be a legitimate repeat ("the" /
+// "the") as a duplicated fragment
+const MIN_OVERLAP_CHARS: usize = 3;
+// A tail from an earlier dictation session says nothing about this chunk
+const TAIL_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Remove the longest prefix of `next` that `prev` ends with. The overlap can
+/// start or end mid-word, e.g. "quick bro" + "brown fox" gives "wn fox"
+pub fn strip_overlap<'a>(prev: &str, next: &'a str) -> &'a str {
+    let overlap = next
+        .char_indices()
+        .map(|(i, _)| i)
+        .skip(1)
+        .chain(std::iter::once(next.len()))
+        .filter(|&end| prev.ends_with(&next[..end]))
+        .filter(|&end| next[..end].chars().count() >= MIN_OVERLAP_CHARS)
+        .last()
+        .unwrap_or(0);
+    &next[overlap..]
+}
+
+/// Last `TAIL_CHARS` of `prev` followed by `typed`, to remember for the next
+/// chunk
+pub fn next_tail(prev: &str, typed: &str) -> String {
+    let combined = format!("{}{}", prev, typed);
+    let skip = combined.chars().count().saturating_sub(TAIL_CHARS);
+    combined.chars().skip(skip).collect()
+}
+
+/// Temp file remembering the tail typed into `window_key`
+pub fn tail_path(window_key: &str) -> PathBuf {
+    let key: String = window_key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    std::env::temp_dir()
+        .join("ito-text-writer")
+        .join(format!("tail-{}", key))
+}
+
+/// The remembered tail for `window_key`, if one was written recently
+pub fn load_tail(window_key: &str) -> Option<String> {
+    let path = tail_path(window_key);
+    let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    if age > TAIL_MAX_AGE {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+pub fn save_tail(window_key: &str, tail: &str) {
+    let path = tail_path(window_key);
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, tail));
+    if let Err(e) = result {
+        log::warn!("Failed to save typed tail to {:?}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_overlap() {
+        // Whole and partial word overlaps
+        assert_eq!(strip_overlap("I went to the", " to the store"), " store");
+        assert_eq!(strip_overlap("the quick bro", "brown fox"), "wn fox");
+        assert_eq!(strip_overlap("hello world", "hello world"), "");
+        // The longest overlap wins
+        assert_eq!(strip_overlap("abcabcabc", "abcabcd"), "d");
+        // Too short to tell apart from a real repeat
+        assert_eq!(strip_overlap("say it", "it again"), "it again");
+        // No overlap, or only in the middle
+        assert_eq!(strip_overlap("foo bar", "baz"), "baz");
+        assert_eq!(
+            strip_overlap("one two three four", "two three five"),
+            "two three five"
+        );
+        assert_eq!(strip_overlap("", "text"), "text");
+        // Multibyte chars never split
+        assert_eq!(strip_overlap("voilà café", "café crème"), " crème");
+    }
+
+    #[test]
+    fn test_next_tail_keeps_the_end() {
+        assert_eq!(next_tail("abc", "def"), "abcdef");
+        let long = "x".repeat(TAIL_CHARS);
+        assert_eq!(
+            next_tail(&long, "é!"),
+            format!("{}é!", "x".repeat(TAIL_CHARS - 2))
+        );
+    }
+}
@@ -1,16 +1,287 @@
 #[cfg(target_os = "macos")]
+use crate::{jittered_char_delay, type_lines, Mode, Restore, RestoreMode, TypeOutcome};
 use cocoa::appkit::{NSPasteboard, NSPasteboardTypeString};
-use cocoa::base::nil;
-use cocoa::foundation::{NSAutoreleasePool, NSString};
+use cocoa::base::{id, nil};
+use cocoa::foundation::{NSArray, NSAutoreleasePool, NSData, NSString};
+use core_foundation::base::{CFType, CFTypeRef, TCFType};
+use core_foundation::string::{CFString, CFStringRef};
 use core_graphics::event::{CGEvent, CGEventFlags};
 use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+use ito_ipc::synthetic;
+use objc::{class, msg_send, sel, sel_impl};
+use std::ffi::c_void;
+use std::ptr;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+const RESTORE_ATTEMPTS: u32 = 5;
+
+// How long a verified paste has to show up in the focused field
+const VERIFY_TIMEOUT: Duration = Duration::from_millis(500);
+const VERIFY_INTERVAL: Duration = Duration::from_millis(20);
+
+type AXUIElementRef = CFTypeRef;
+const AX_ERROR_SUCCESS: i32 = 0;
+
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXUIElementCreateSystemWide() -> AXUIElementRef;
+    fn AXUIElementCopyAttributeValue(
+        element: AXUIElementRef,
+        attribute: CFStringRef,
+        value: *mut CFTypeRef,
+    ) -> i32;
+}
+
+// Every (type, data) pair of one pasteboard item
+type PasteboardItem = Vec<(String, Vec<u8>)>;
+
+/// Saves the pasteboard contents on creation and puts them back when dropped,
+/// so the restore also runs when typing bails out early with an error
+struct ClipboardRestore {
+    items: Vec<PasteboardItem>,
+    // Text read back to verify a sync restore, None when there was no text
+    original: Option<String>,
+    // What we last put on the pasteboard, anything else there by the time
+    // of the restore was copied by the user and is left alone
+    pasted: Option<String>,
+    restore: Restore,
+    restored: bool,
+}
+
+impl ClipboardRestore {
+    fn save(restore: Restore) -> Self {
+        ClipboardRestore {
+            original: unsafe { pasteboard_string() },
+            items: unsafe { snapshot_items() },
+            pasted: None,
+            restore,
+            restored: false,
+        }
+    }
+
+    /// Ok(false) when the restore was skipped because the clipboard no
+    /// longer holds what was pasted
+    fn restore(&mut self) -> Result<bool, String> {
+        if self.restored {
+            return Ok(true);
+        }
+        self.restored = true;
+
+        thread::sleep(self.restore.delay);
+
+        if unsafe { pasteboard_string() } != self.pasted {
+            log::info!("Clipboard changed since the paste, leaving it as is");
+            return Ok(false);
+        }
+
+        if self.restore.mode == RestoreMode::Delayed {
+            self.write_original();
+            return Ok(true);
+        }
+
+        // Sync mode: only report success once the original reads back
+        for attempt in 1..=RESTORE_ATTEMPTS {
+            self.write_original();
+            if unsafe { pasteboard_string() } == self.original {
+                return Ok(true);
+            }
+            thread::sleep(Duration::from_millis(10 * attempt as u64));
+        }
+
+        Err(format!(
+            "Clipboard did not match original after {} restore attempts",
+            RESTORE_ATTEMPTS
+        ))
+    }
+
+    fn write_original(&self) {
+        unsafe {
+            let _pool = NSAutoreleasePool::new(nil);
+            let pasteboard = NSPasteboard::generalPasteboard(nil);
+            pasteboard.clearContents();
+
+            let items: Vec<id> = self
+                .items
+                .iter()
+                .map(|types| {
+                    let item: id = msg_send![class!(NSPasteboardItem), new];
+                    let item: id = msg_send![item, autorelease];
+                    for (pasteboard_type, data) in types {
+                        let ns_type = NSString::alloc(nil).init_str(pasteboard_type);
+                        let ns_data = NSData::dataWithBytes_length_(
+                            nil,
+                            data.as_ptr() as *const c_void,
+                            data.len() as u64,
+                        );
+                        cocoa::appkit::NSPasteboardItem::setData_forType(item, ns_data, ns_type);
+                    }
+                    item
+                })
+                .collect();
+
+            if !items.is_empty() {
+                pasteboard.writeObjects(NSArray::arrayWithObjects(nil, &items));
+            }
+        }
+    }
+}
+
+impl Drop for ClipboardRestore {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::warn!("{}", e);
+        }
+    }
+}
+
+/// Copy the data of every type of every pasteboard item, so rich content such
+/// as images, files and RTF survives the paste, not just the plain text
+unsafe fn snapshot_items() -> Vec<PasteboardItem> {
+    let _pool = NSAutoreleasePool::new(nil);
+    let pasteboard = NSPasteboard::generalPasteboard(nil);
+    let items = pasteboard.pasteboardItems();
+    if items == nil {
+        return Vec::new();
+    }
+
+    (0..NSArray::count(items))
+        .map(|i| {
+            let item = NSArray::objectAtIndex(items, i);
+            let types = cocoa::appkit::NSPasteboardItem::types(item);
+            (0..NSArray::count(types))
+                .filter_map(|j| {
+                    let pasteboard_type = NSArray::objectAtIndex(types, j);
+                    let data = cocoa::appkit::NSPasteboardItem::dataForType(item, pasteboard_type);
+                    if data == nil {
+                        return None;
+                    }
+                    let length = NSData::length(data) as usize;
+                    let bytes = if length == 0 {
+                        Vec::new()
+                    } else {
+                        std::slice::from_raw_parts(NSData::bytes(data) as *const u8, length)
+                            .to_vec()
+                    };
+                    Some((ns_string_to_string(pasteboard_type), bytes))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Read the general pasteboard's text as a Rust String, which unlike the
+/// NSString can be held across the paste
+unsafe fn pasteboard_string() -> Option<String> {
+    let _pool = NSAutoreleasePool::new(nil);
+    let pasteboard = NSPasteboard::generalPasteboard(nil);
+    let contents: id = pasteboard.stringForType(NSPasteboardTypeString);
+    if contents == nil {
+        return None;
+    }
+    Some(ns_string_to_string(contents))
+}
+
+unsafe fn ns_string_to_string(string: id) -> String {
+    let c_str = NSString::UTF8String(string);
+    std::ffi::CStr::from_ptr(c_str)
+        .to_string_lossy()
+        .into_owned()
+}
 
 /// Type text on macOS using clipboard paste approach
 /// This avoids character-by-character typing which can cause issues in some
 /// apps
-pub fn type_text_macos(text: &str, _char_delay: u64) -> Result<(), String> {
+pub fn type_text_macos(
+    text: &str,
+    char_delay: u64,
+    restore: Option<Restore>,
+    verify_insert: bool,
+    soft_breaks: bool,
+) -> TypeOutcome {
+    // Store current clipboard contents to restore once, after the last line
+    let mut old_contents = restore.map(ClipboardRestore::save);
+
+    let outcome = type_lines(text, soft_breaks, |line| {
+        paste_line(line, char_delay, old_contents.as_mut(), verify_insert)
+    });
+    let restored = old_contents.as_mut().map(ClipboardRestore::restore);
+    TypeOutcome {
+        method: outcome.method,
+        ..TypeOutcome::pasted(outcome.result, restored, restore)
+    }
+}
+
+fn paste_line(
+    line: &str,
+    char_delay: u64,
+    old_contents: Option<&mut ClipboardRestore>,
+    verify_insert: bool,
+) -> TypeOutcome {
+    // Without a readable value beforehand there's nothing to compare against,
+    // and retyping on a guess could insert the text twice
+    let before = if verify_insert { focused_value() } else { None };
+
+    if let Some(old_contents) = old_contents {
+        old_contents.pasted = Some(line.to_string());
+    }
+    let result = paste_text(line);
+    let landed = match (&result, &before) {
+        (Ok(()), Some(before)) => wait_for_value_change(before),
+        _ => true,
+    };
+
+    if !landed {
+        log::info!("Paste left the focused field unchanged, typing instead");
+        return TypeOutcome {
+            method: Some(Mode::Keystroke),
+            ..TypeOutcome::typed(type_keystrokes_macos(line, char_delay, 0))
+        };
+    }
+    TypeOutcome {
+        method: before.is_some().then_some(Mode::Paste),
+        ..TypeOutcome::typed(result)
+    }
+}
+
+/// The focused element's AXValue, when it's text
+fn focused_value() -> Option<String> {
+    unsafe fn copy_attribute(element: AXUIElementRef, attribute: &str) -> Option<CFType> {
+        let attribute = CFString::new(attribute);
+        let mut value: CFTypeRef = ptr::null();
+        let error =
+            AXUIElementCopyAttributeValue(element, attribute.as_concrete_TypeRef(), &mut value);
+        (error == AX_ERROR_SUCCESS && !value.is_null())
+            .then(|| CFType::wrap_under_create_rule(value))
+    }
+
+    unsafe {
+        let system_wide = AXUIElementCreateSystemWide();
+        if system_wide.is_null() {
+            return None;
+        }
+        let system_wide = CFType::wrap_under_create_rule(system_wide);
+        let focused = copy_attribute(system_wide.as_CFTypeRef(), "AXFocusedUIElement")?;
+        copy_attribute(focused.as_CFTypeRef(), "AXValue")?
+            .downcast::<CFString>()
+            .map(|value| value.to_string())
+    }
+}
+
+/// Poll the focused field until its value differs from `before`. The app
+/// handles the paste asynchronously, so a single read would be too early
+fn wait_for_value_change(before: &str) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < VERIFY_TIMEOUT {
+        thread::sleep(VERIFY_INTERVAL);
+        if focused_value().as_deref() != Some(before) {
+            return true;
+        }
+    }
+    false
+}
+
+fn paste_text(text: &str) -> Result<(), String> {
     unsafe {
         // Create an autorelease pool for memory management
         let _pool = NSAutoreleasePool::new(nil);
@@ -18,9 +289,6 @@ pub fn type_text_macos(text: &str, _char_delay: u64) -> Result<(), String> {
         // Get the general pasteboard
         let pasteboard = NSPasteboard::generalPasteboard(nil);
 
-        // Store current clipboard contents to restore later
-        let old_contents = pasteboard.stringForType(NSPasteboardTypeString);
-
         // Clear the pasteboard and set our text
         pasteboard.clearContents();
         let ns_string = NSString::alloc(nil).init_str(text);
@@ -62,29 +330,80 @@ pub fn type_text_macos(text: &str, _char_delay: u64) -> Result<(), String> {
         key_v_down.set_flags(CGEventFlags::CGEventFlagCommand);
         key_v_up.set_flags(CGEventFlags::CGEventFlagCommand);
 
+        mark_synthetic(&key_v_down);
+        mark_synthetic(&key_v_up);
+
         // Post the events
         key_v_down.post(core_graphics::event::CGEventTapLocation::HID);
         thread::sleep(Duration::from_millis(10));
         key_v_up.post(core_graphics::event::CGEventTapLocation::HID);
+    }
 
-        // Restore old clipboard contents in background after delay in separate thread
-        // to not block
-        if old_contents != nil {
-            // Convert Objective-C string to Rust String to make it Send-safe
-            let old_contents_str = {
-                let c_str = cocoa::foundation::NSString::UTF8String(old_contents);
-                std::ffi::CStr::from_ptr(c_str)
-                    .to_string_lossy()
-                    .into_owned()
-            };
+    Ok(())
+}
 
-            thread::sleep(Duration::from_secs(1));
-            let pasteboard = NSPasteboard::generalPasteboard(nil);
-            pasteboard.clearContents();
-            let ns_string = NSString::alloc(nil).init_str(&old_contents_str);
-            pasteboard.setString_forType(ns_string, NSPasteboardTypeString);
-        }
+// Key code 36 is Return
+pub fn press_shift_enter() -> Result<(), String> {
+    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+        .map_err(|_| "Failed to create event source")?;
+    let key_down = CGEvent::new_keyboard_event(source.clone(), 36, true)
+        .map_err(|_| "Failed to create key down event")?;
+    let key_up = CGEvent::new_keyboard_event(source, 36, false)
+        .map_err(|_| "Failed to create key up event")?;
+
+    key_down.set_flags(CGEventFlags::CGEventFlagShift);
+    key_up.set_flags(CGEventFlags::CGEventFlagShift);
+
+    mark_synthetic(&key_down);
+    mark_synthetic(&key_up);
+
+    key_down.post(core_graphics::event::CGEventTapLocation::HID);
+    thread::sleep(Duration::from_millis(10));
+    key_up.post(core_graphics::event::CGEventTapLocation::HID);
+    Ok(())
+}
+
+// Tag an event we post so it can be told apart from the user's keystrokes.
+// global-key-listener doesn't check it: rdev doesn't hand its callback the
+// CGEvent, so it still relies on ignoring Cmd+C/Cmd+X
+fn mark_synthetic(event: &CGEvent) {
+    event.set_integer_value_field(synthetic::MARKER_FIELD, synthetic::MARKER);
+}
+
+/// Type text on macOS by posting a unicode keyboard event per character,
+/// leaving the clipboard untouched
+pub fn type_keystrokes_macos(text: &str, char_delay: u64, jitter: u64) -> Result<(), String> {
+    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+        .map_err(|_| "Failed to create event source")?;
+
+    let mut utf16_buf = [0u16; 2];
+    for ch in text.chars() {
+        // The key code is ignored once a unicode string is attached
+        let key_down = CGEvent::new_keyboard_event(source.clone(), 0, true)
+            .map_err(|_| "Failed to create key down event")?;
+        let key_up = CGEvent::new_keyboard_event(source.clone(), 0, false)
+            .map_err(|_| "Failed to create key up event")?;
+
+        let utf16 = ch.encode_utf16(&mut utf16_buf);
+        key_down.set_string_from_utf16_unchecked(utf16);
+        key_up.set_string_from_utf16_unchecked(utf16);
 
-        Ok(())
+        // Don't let a modifier the user is still holding turn this into a
+        // shortcut
+        key_down.set_flags(CGEventFlags::CGEventFlagNull);
+        key_up.set_flags(CGEventFlags::CGEventFlagNull);
+
+        mark_synthetic(&key_down);
+        mark_synthetic(&key_up);
+
+        key_down.post(core_graphics::event::CGEventTapLocation::HID);
+        key_up.post(core_graphics::event::CGEventTapLocation::HID);
+
+        let delay = jittered_char_delay(char_delay, jitter);
+        if delay > 0 {
+            thread::sleep(Duration::from_millis(delay));
+        }
     }
+
+    Ok(())
 }
@@ -1,28 +1,87 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use rand::Rng;
+use serde::Serialize;
+use std::io::{self, Read};
 use std::process;
 use std::thread;
 use std::time::Duration;
 
-#[cfg(target_os = "linux")]
+mod dedupe;
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
 use enigo::{Enigo, Key, Keyboard, Settings};
 
 #[cfg(target_os = "macos")]
 mod macos_writer;
 #[cfg(target_os = "macos")]
-use macos_writer::type_text_macos;
+use macos_writer::{type_keystrokes_macos, type_text_macos};
+
+#[cfg(target_os = "linux")]
+mod wayland_writer;
+#[cfg(target_os = "linux")]
+use wayland_writer::{is_wayland, type_text_wayland};
 
 #[cfg(target_os = "windows")]
 mod windows_writer;
 #[cfg(target_os = "windows")]
 use windows_writer::type_text_windows;
 
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Put the text on the clipboard and send Cmd/Ctrl+V
+    Paste,
+    /// Type each character with synthesized key events, without touching the
+    /// clipboard. Works in fields that reject pastes
+    Keystroke,
+}
+
+/// How the paste mode puts the user's clipboard back
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RestoreMode {
+    /// Restore after the paste lands, without checking it took
+    Delayed,
+    /// Read the clipboard back and fail unless the restore completed before
+    /// exiting
+    Sync,
+}
+
+/// How the paste mode restores the clipboard, None with --no-restore
+#[derive(Clone, Copy, Debug)]
+pub struct Restore {
+    pub mode: RestoreMode,
+    // Time the target app gets to read the pasted text first
+    pub delay: Duration,
+}
+
+/// What happens to line breaks in the text
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum NewlineMode {
+    /// Insert them along with the rest of the text
+    Paste,
+    /// Press Shift+Enter for each, a soft break in chat apps where a plain
+    /// newline sends the message
+    ShiftEnter,
+    /// Replace each with a space
+    Strip,
+}
+
 #[derive(Parser)]
 #[command(name = "text-writer")]
 #[command(about = "A cross-platform text typing utility")]
-#[command(version = "0.1.0")]
+#[command(version = ito_ipc::version_string!())]
 struct Args {
-    #[arg(help = "Text to type")]
-    text: String,
+    #[arg(help = "Text to type", required_unless_present = "stdin")]
+    text: Option<String>,
+
+    // Long text hits argv length limits and shows up in process listings, so
+    // callers can pipe it in instead
+    #[arg(
+        long,
+        conflicts_with = "text",
+        help = "Read the text to type from stdin (until EOF) instead of the argument"
+    )]
+    stdin: bool,
 
     #[arg(
         short,
@@ -39,69 +98,667 @@ struct Args {
         help = "Delay between characters (milliseconds)"
     )]
     char_delay: u64,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 0,
+        help = "Randomize the keystroke-mode delay between characters by up to this much (milliseconds)"
+    )]
+    jitter: u64,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = Mode::Paste,
+        help = "How to insert the text"
+    )]
+    mode: Mode,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = RestoreMode::Delayed,
+        help = "How to restore the clipboard after pasting"
+    )]
+    restore_clipboard: RestoreMode,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        help = "How long to wait after pasting before restoring the clipboard (milliseconds)"
+    )]
+    restore_delay_ms: u64,
+
+    // For callers that save and restore the clipboard themselves
+    #[arg(
+        long,
+        conflicts_with_all = ["restore_clipboard", "restore_delay_ms"],
+        help = "Leave the pasted text on the clipboard instead of restoring it"
+    )]
+    no_restore: bool,
+
+    #[arg(
+        long,
+        help = "Skip the start of the text that repeats the end of the text last typed into the same window"
+    )]
+    dedupe_overlap: bool,
+
+    #[arg(
+        long,
+        requires = "dedupe_overlap",
+        help = "End of the previously typed text to dedupe against, instead of the remembered one"
+    )]
+    prev_tail: Option<String>,
+
+    // Focus can move during --delay or while the host was preparing the
+    // text, and pasting into whatever took it is worse than not typing
+    #[arg(
+        long,
+        value_name = "TITLE",
+        help = "Fail without typing unless the focused window has exactly this title"
+    )]
+    expect_window: Option<String>,
+
+    #[arg(
+        long,
+        help = "Do everything except send the paste or keystrokes, and report the text that would have been typed"
+    )]
+    dry_run: bool,
+
+    // Some secure fields ignore pastes without any error
+    #[arg(
+        long,
+        help = "Check that a paste changed the focused field and type the text instead if it didn't (macOS only)"
+    )]
+    verify_insert: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = NewlineMode::Paste,
+        help = "How to insert line breaks in the text"
+    )]
+    newline_mode: NewlineMode,
+}
+
+impl Args {
+    fn restore(&self) -> Option<Restore> {
+        (!self.no_restore).then(|| Restore {
+            mode: self.restore_clipboard,
+            delay: Duration::from_millis(self.restore_delay_ms),
+        })
+    }
+}
+
+/// Outcome of typing with one of the platform writers
+pub struct TypeOutcome {
+    pub result: Result<(), String>,
+    // None when the clipboard was never touched or left as pasted, false when
+    // the restore failed or was skipped because the clipboard had changed
+    pub clipboard_restored: Option<bool>,
+    // How the text went in, only set when a paste was verified and may have
+    // been retyped
+    pub method: Option<Mode>,
+}
+
+impl TypeOutcome {
+    fn typed(result: Result<(), String>) -> Self {
+        TypeOutcome {
+            result,
+            clipboard_restored: None,
+            method: None,
+        }
+    }
+
+    /// `restored` is the restore's result, Ok(false) when it was skipped and
+    /// None when `restore` is None and the pasted text was left on the
+    /// clipboard
+    pub fn pasted(
+        result: Result<(), String>,
+        restored: Option<Result<bool, String>>,
+        restore: Option<Restore>,
+    ) -> Self {
+        let Some(restored) = restored else {
+            return TypeOutcome::typed(result);
+        };
+        let clipboard_restored = Some(restored == Ok(true));
+        let result = match restored {
+            // Only sync mode promises the restore completed, so only it fails
+            // the run
+            Err(e) if restore.is_some_and(|restore| restore.mode == RestoreMode::Sync) => {
+                result.and(Err(e))
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+                result
+            }
+            Ok(_) => result,
+        };
+
+        TypeOutcome {
+            result,
+            clipboard_restored,
+            method: None,
+        }
+    }
+}
+
+/// Single JSON line written to stdout before exiting
+#[derive(Serialize, Debug)]
+struct Report {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chars: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<Mode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(rename = "clipboardRestored", skip_serializing_if = "Option::is_none")]
+    clipboard_restored: Option<bool>,
+    // Chars dropped from the start of the text as overlap, only with
+    // --dedupe-overlap
+    #[serde(rename = "dedupedChars", skip_serializing_if = "Option::is_none")]
+    deduped_chars: Option<usize>,
+    // --dry-run only: the text after dedupe, and the title of the window it
+    // would have gone to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    would_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    // --verify-insert only: the mode that actually inserted the text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<Mode>,
+}
+
+impl Report {
+    fn failure(error: String) -> Self {
+        Report {
+            success: false,
+            chars: None,
+            mode: None,
+            error: Some(error),
+            clipboard_restored: None,
+            deduped_chars: None,
+            would_type: None,
+            target: None,
+            method: None,
+        }
+    }
 }
 
 fn main() {
+    ito_ipc::logging::init("text-writer");
     let args = Args::parse();
 
-    if args.text.is_empty() {
-        eprintln!("Error: Text cannot be empty");
+    let report = run(args);
+    if let Some(e) = &report.error {
+        log::error!("{}", e);
+    }
+    match serde_json::to_string(&report) {
+        Ok(json) => println!("{}", json),
+        Err(e) => log::error!("Error serializing report: {}", e),
+    }
+
+    if !report.success {
         process::exit(1);
     }
+}
+
+fn run(args: Args) -> Report {
+    let restore = args.restore();
+    let text = if args.stdin {
+        let mut text = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut text) {
+            return Report::failure(format!("Failed to read text from stdin: {}", e));
+        }
+        text
+    } else {
+        args.text.unwrap_or_default()
+    };
+
+    if text.is_empty() {
+        return Report::failure("Text cannot be empty".to_string());
+    }
 
     if args.delay > 0 {
         thread::sleep(Duration::from_millis(args.delay));
     }
 
-    // Use platform-specific implementation
-    #[cfg(target_os = "macos")]
-    {
-        if let Err(e) = type_text_macos(&args.text, args.char_delay) {
-            eprintln!("Error typing text: {}", e);
-            process::exit(1);
+    // Resolved after the delay, which is when focus has settled on the
+    // window the text is going to
+    let window_key = if args.dedupe_overlap {
+        active_window_key()
+    } else {
+        None
+    };
+    let prev_tail = args
+        .prev_tail
+        .or_else(|| window_key.as_deref().and_then(dedupe::load_tail));
+    let to_type = match &prev_tail {
+        Some(prev) => dedupe::strip_overlap(prev, &text),
+        None => &text,
+    };
+    let deduped_chars = args
+        .dedupe_overlap
+        .then(|| text.chars().count() - to_type.chars().count());
+    // The remembered tail stays the dictated text, so only what's typed
+    // changes
+    let stripped;
+    let typed = if args.newline_mode == NewlineMode::Strip {
+        stripped = split_lines(to_type).join(" ");
+        stripped.as_str()
+    } else {
+        to_type
+    };
+
+    // Only the length is logged, the text itself is the user's dictation
+    log::debug!(
+        "Typing {} chars in {:?} mode (restore: {:?}, deduped: {:?})",
+        typed.chars().count(),
+        args.mode,
+        restore,
+        deduped_chars
+    );
+    let title = (args.expect_window.is_some() || args.dry_run)
+        .then(|| active_win_pos_rs::get_active_window().ok())
+        .flatten()
+        .map(|window| window.title);
+    if let Some(expected) = &args.expect_window {
+        if let Err(e) = check_focus(expected, title.as_deref()) {
+            return Report::failure(e);
         }
     }
 
-    #[cfg(target_os = "windows")]
-    {
-        if let Err(e) = type_text_windows(&args.text, args.char_delay) {
-            eprintln!("Error typing text: {}", e);
-            process::exit(1);
+    if args.dry_run {
+        return Report {
+            success: true,
+            chars: Some(typed.chars().count()),
+            mode: Some(args.mode),
+            error: None,
+            clipboard_restored: None,
+            deduped_chars,
+            would_type: Some(typed.to_string()),
+            target: title,
+            method: None,
+        };
+    }
+
+    let outcome = if typed.is_empty() {
+        // The whole chunk was already typed
+        TypeOutcome::typed(Ok(()))
+    } else {
+        type_text(
+            typed,
+            args.mode,
+            args.char_delay,
+            args.jitter,
+            restore,
+            args.verify_insert,
+            args.newline_mode == NewlineMode::ShiftEnter,
+        )
+    };
+    let (success, chars, error) = match outcome.result {
+        Ok(()) => (true, Some(typed.chars().count()), None),
+        Err(e) => (false, None, Some(format!("Failed to type text: {}", e))),
+    };
+
+    if success {
+        if let Some(key) = &window_key {
+            let prev = prev_tail.as_deref().unwrap_or_default();
+            dedupe::save_tail(key, &dedupe::next_tail(prev, to_type));
         }
     }
 
-    #[cfg(target_os = "linux")]
-    {
-        let mut enigo = match Enigo::new(&Settings::default()) {
-            Ok(enigo) => enigo,
-            Err(e) => {
-                eprintln!("Error initializing enigo: {}", e);
-                process::exit(1);
-            }
-        };
+    Report {
+        success,
+        chars,
+        mode: Some(args.mode),
+        error,
+        clipboard_restored: outcome.clipboard_restored,
+        deduped_chars,
+        would_type: None,
+        target: None,
+        method: outcome.method,
+    }
+}
 
-        if args.char_delay > 0 {
-            for ch in args.text.chars() {
-                if let Err(e) = enigo.text(&ch.to_string()) {
-                    eprintln!("Error typing character '{}': {}", ch, e);
-                    process::exit(1);
-                }
-                thread::sleep(Duration::from_millis(args.char_delay));
-            }
-        } else {
-            if let Err(e) = enigo.text(&args.text) {
-                eprintln!("Error typing text: {}", e);
-                process::exit(1);
+/// Identifies the focused window, so remembered tails don't leak between
+/// windows
+fn active_window_key() -> Option<String> {
+    let window = active_win_pos_rs::get_active_window().ok()?;
+    Some(format!("{}-{}", window.process_id, window.window_id))
+}
+
+/// Whether the focused window, titled `actual`, is the one the text is meant
+/// for
+fn check_focus(expected: &str, actual: Option<&str>) -> Result<(), String> {
+    match actual {
+        Some(title) if title == expected => Ok(()),
+        Some(title) => Err(format!(
+            "Focus changed to '{}', expected '{}'; nothing was typed",
+            title, expected
+        )),
+        None => Err(format!(
+            "Couldn't read the focused window to check it is '{}'; nothing was typed",
+            expected
+        )),
+    }
+}
+
+/// The text between line breaks, taking CRLF as one break
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .collect()
+}
+
+/// Type the text in one go, or with `soft_breaks` each line on its own with
+/// Shift+Enter pressed between them
+fn type_lines(
+    text: &str,
+    soft_breaks: bool,
+    mut type_line: impl FnMut(&str) -> TypeOutcome,
+) -> TypeOutcome {
+    if soft_breaks {
+        type_with_soft_breaks(text, type_line, press_shift_enter)
+    } else {
+        type_line(text)
+    }
+}
+
+/// Type each line on its own with a soft break pressed between them,
+/// stopping at the first failure
+fn type_with_soft_breaks(
+    text: &str,
+    mut type_line: impl FnMut(&str) -> TypeOutcome,
+    mut soft_break: impl FnMut() -> Result<(), String>,
+) -> TypeOutcome {
+    let mut combined = TypeOutcome::typed(Ok(()));
+    for (i, line) in split_lines(text).into_iter().enumerate() {
+        if i > 0 {
+            if let Err(e) = soft_break() {
+                combined.result = Err(e);
+                return combined;
             }
         }
+        if line.is_empty() {
+            continue;
+        }
+        let outcome = type_line(line);
+        combined.clipboard_restored =
+            match (combined.clipboard_restored, outcome.clipboard_restored) {
+                (Some(all), Some(restored)) => Some(all && restored),
+                (all, restored) => all.or(restored),
+            };
+        // One retyped line means the text didn't all go in as pasted
+        if combined.method != Some(Mode::Keystroke) {
+            combined.method = outcome.method.or(combined.method);
+        }
+        if outcome.result.is_err() {
+            combined.result = outcome.result;
+            return combined;
+        }
+    }
+    combined
+}
+
+#[cfg(target_os = "macos")]
+fn press_shift_enter() -> Result<(), String> {
+    macos_writer::press_shift_enter()
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn press_shift_enter() -> Result<(), String> {
+    use enigo::Direction;
+
+    #[cfg(target_os = "linux")]
+    if is_wayland() {
+        return wayland_writer::press_shift_enter();
+    }
+    let mut enigo = new_enigo()?;
+    let pressed = enigo
+        .key(Key::Shift, Direction::Press)
+        .and_then(|_| enigo.key(Key::Return, Direction::Click));
+    // Always let go of Shift, even when Enter failed
+    let released = enigo.key(Key::Shift, Direction::Release);
+    pressed
+        .and(released)
+        .map_err(|e| format!("Failed to press Shift+Enter: {}", e))
+}
+
+/// Delay before the next keystroke-mode character, drawn uniformly from
+/// `char_delay ± jitter` and clamped at 0. Uniform pacing looks robotic and
+/// some rate-limited apps drop characters that arrive too evenly
+pub fn jittered_char_delay(char_delay: u64, jitter: u64) -> u64 {
+    if jitter == 0 {
+        return char_delay;
+    }
+
+    let low = char_delay as i64 - jitter as i64;
+    let high = char_delay as i64 + jitter as i64;
+    rand::thread_rng().gen_range(low..=high).max(0) as u64
+}
+
+// Use platform-specific implementation
+#[cfg(target_os = "macos")]
+fn type_text(
+    text: &str,
+    mode: Mode,
+    char_delay: u64,
+    jitter: u64,
+    restore: Option<Restore>,
+    verify_insert: bool,
+    soft_breaks: bool,
+) -> TypeOutcome {
+    match mode {
+        Mode::Paste => type_text_macos(text, char_delay, restore, verify_insert, soft_breaks),
+        Mode::Keystroke => type_lines(text, soft_breaks, |line| {
+            TypeOutcome::typed(type_keystrokes_macos(line, char_delay, jitter))
+        }),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn type_text(
+    text: &str,
+    mode: Mode,
+    char_delay: u64,
+    jitter: u64,
+    restore: Option<Restore>,
+    _verify_insert: bool,
+    soft_breaks: bool,
+) -> TypeOutcome {
+    let mut enigo = match new_enigo() {
+        Ok(enigo) => enigo,
+        Err(e) => return TypeOutcome::typed(Err(e)),
+    };
+    match mode {
+        Mode::Paste => type_text_windows(&mut enigo, text, char_delay, restore, soft_breaks),
+        Mode::Keystroke => type_lines(text, soft_breaks, |line| {
+            TypeOutcome::typed(type_keystrokes_enigo(&mut enigo, line, char_delay, jitter))
+        }),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn type_text(
+    text: &str,
+    _mode: Mode,
+    char_delay: u64,
+    jitter: u64,
+    _restore: Option<Restore>,
+    _verify_insert: bool,
+    soft_breaks: bool,
+) -> TypeOutcome {
+    // There is no paste path on Linux, so both modes type with key events
+    if is_wayland() {
+        return type_lines(text, soft_breaks, |line| {
+            TypeOutcome::typed(type_text_wayland(line, char_delay, jitter))
+        });
+    }
+    let mut enigo = match new_enigo() {
+        Ok(enigo) => enigo,
+        Err(e) => return TypeOutcome::typed(Err(e)),
+    };
+    type_lines(text, soft_breaks, |line| {
+        TypeOutcome::typed(type_keystrokes_enigo(&mut enigo, line, char_delay, jitter))
+    })
+}
+
+/// The one Enigo connection used for the whole run. Creating one is slow, and
+/// on Wayland every new connection re-grabs input
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn new_enigo() -> Result<Enigo, String> {
+    Enigo::new(&Settings::default()).map_err(|e| format!("Failed to initialize enigo: {}", e))
+}
+
+/// Type text with synthesized key events, leaving the clipboard untouched
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+fn type_keystrokes_enigo(
+    enigo: &mut Enigo,
+    text: &str,
+    char_delay: u64,
+    jitter: u64,
+) -> Result<(), String> {
+    if char_delay > 0 || jitter > 0 {
+        for ch in text.chars() {
+            enigo
+                .text(&ch.to_string())
+                .map_err(|e| format!("Failed to type character '{}': {}", ch, e))?;
+            thread::sleep(Duration::from_millis(jittered_char_delay(
+                char_delay, jitter,
+            )));
+        }
+    } else {
+        enigo
+            .text(text)
+            .map_err(|e| format!("Failed to type text: {}", e))?;
+    }
+
+    // Patch fix: Send 'A' key release to clean up any phantom stuck KeyA events
+    // This addresses a bug where synthetic events from text typing can cause
+    // the global key listener to receive keydown events without corresponding keyup
+    // events
+    if let Err(e) = enigo.key(Key::Unicode('a'), enigo::Direction::Release) {
+        // Don't fail on this error since it's just a cleanup operation
+        log::warn!("Failed to send cleanup 'a' key release: {}", e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_focus() {
+        assert!(check_focus("Notes", Some("Notes")).is_ok());
+        let error = check_focus("Notes", Some("Slack")).unwrap_err();
+        assert!(error.contains("'Slack'"), "{}", error);
+        assert!(check_focus("Notes", None).is_err());
+    }
+
+    #[test]
+    fn test_dry_run_reports_without_typing() {
+        let args = Args::parse_from([
+            "text-writer",
+            "--dry-run",
+            "--prev-tail",
+            "I went to the",
+            "--dedupe-overlap",
+            " to the store",
+        ]);
+        let report = serde_json::to_value(run(args)).unwrap();
+        assert_eq!(report["success"], true);
+        assert_eq!(report["would_type"], " store");
+        assert_eq!(report["mode"], "paste");
+        assert_eq!(report["dedupedChars"], 7);
+        assert!(report.get("clipboardRestored").is_none());
+    }
+
+    #[test]
+    fn test_restore_options() {
+        let restore =
+            Args::parse_from(["text-writer", "--restore-delay-ms", "250", "hi"]).restore();
+        assert_eq!(restore.unwrap().delay, Duration::from_millis(250));
+        assert!(Args::parse_from(["text-writer", "--no-restore", "hi"])
+            .restore()
+            .is_none());
+        assert!(Args::try_parse_from([
+            "text-writer",
+            "--no-restore",
+            "--restore-delay-ms",
+            "0",
+            "hi"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_skipped_restore_is_not_reported_as_restored() {
+        let restore = Some(Restore {
+            mode: RestoreMode::Sync,
+            delay: Duration::ZERO,
+        });
+        let outcome = TypeOutcome::pasted(Ok(()), Some(Ok(false)), restore);
+        assert!(outcome.result.is_ok());
+        assert_eq!(outcome.clipboard_restored, Some(false));
+
+        let outcome = TypeOutcome::pasted(Ok(()), Some(Err("busy".to_string())), restore);
+        assert!(outcome.result.is_err());
+        assert_eq!(outcome.clipboard_restored, Some(false));
+    }
+
+    #[test]
+    fn test_soft_breaks_between_lines() {
+        let sent = std::cell::RefCell::new(Vec::new());
+        let outcome = type_with_soft_breaks(
+            "first\r\nsecond\n\nthird",
+            |line| {
+                sent.borrow_mut().push(line.to_string());
+                TypeOutcome::pasted(
+                    Ok(()),
+                    Some(Ok(true)),
+                    Some(Restore {
+                        mode: RestoreMode::Delayed,
+                        delay: Duration::ZERO,
+                    }),
+                )
+            },
+            || {
+                sent.borrow_mut().push("<shift-enter>".to_string());
+                Ok(())
+            },
+        );
+        assert!(outcome.result.is_ok());
+        assert_eq!(outcome.clipboard_restored, Some(true));
+        assert_eq!(
+            sent.into_inner(),
+            [
+                "first",
+                "<shift-enter>",
+                "second",
+                "<shift-enter>",
+                "<shift-enter>",
+                "third"
+            ]
+        );
+        assert_eq!(split_lines("one\ntwo\r\n").join(" "), "one two ");
+    }
+
+    #[test]
+    fn test_jittered_char_delay_stays_in_range() {
+        assert_eq!(jittered_char_delay(20, 0), 20);
+
+        for _ in 0..1000 {
+            let delay = jittered_char_delay(20, 5);
+            assert!((15..=25).contains(&delay));
 
-        // Patch fix: Send 'A' key release to clean up any phantom stuck KeyA events
-        // This addresses a bug where synthetic events from text typing can cause
-        // the global key listener to receive keydown events without corresponding keyup
-        // events
-        if let Err(e) = enigo.key(Key::Unicode('a'), enigo::Direction::Release) {
-            // Don't exit on this error since it's just a cleanup operation
-            eprintln!("Warning: Failed to send cleanup 'a' key release: {}", e);
+            // The low end clamps at 0 instead of underflowing
+            assert!(jittered_char_delay(3, 10) <= 13);
         }
     }
 }
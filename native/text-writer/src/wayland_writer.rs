@@ -0,0 +1,52 @@
+//! Typing on Wayland through `wtype`, which injects keys with the
+//! virtual-keyboard protocol. enigo synthesizes X11 input, which never
+//! reaches native Wayland windows and gives no error when it doesn't.
+
+use crate::jittered_char_delay;
+use std::io;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// Whether this is a Wayland session, where typing has to go through wtype
+pub fn is_wayland() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some_and(|display| !display.is_empty())
+}
+
+pub fn type_text_wayland(text: &str, char_delay: u64, jitter: u64) -> Result<(), String> {
+    if jitter == 0 {
+        return wtype(&["-d", &char_delay.to_string(), "--", text]);
+    }
+    // wtype only has a fixed delay, so jitter means one call per character
+    for ch in text.chars() {
+        wtype(&["--", &ch.to_string()])?;
+        thread::sleep(Duration::from_millis(jittered_char_delay(
+            char_delay, jitter,
+        )));
+    }
+    Ok(())
+}
+
+pub fn press_shift_enter() -> Result<(), String> {
+    wtype(&["-M", "shift", "-k", "Return", "-m", "shift"])
+}
+
+fn wtype(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("wtype")
+        .args(args)
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                "No way to type on Wayland: wtype is not installed".to_string()
+            }
+            _ => format!("Failed to run wtype: {}", e),
+        })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    // e.g. GNOME, which doesn't implement the virtual-keyboard protocol
+    Err(format!(
+        "wtype failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
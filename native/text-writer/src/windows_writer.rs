@@ -1,16 +1,162 @@
 #[cfg(target_os = "windows")]
-use clipboard_win::{formats, get_clipboard, set_clipboard};
-use enigo::{Enigo, Key, Keyboard, Settings};
+use crate::{type_lines, Restore, RestoreMode, TypeOutcome};
+use clipboard_win::{formats, get_clipboard, raw, set_clipboard, Clipboard};
+use enigo::{Enigo, Key, Keyboard};
 use std::thread;
 use std::time::Duration;
 
+const RESTORE_ATTEMPTS: u32 = 5;
+
+// Formats whose clipboard data is a GDI or owner-managed handle rather than
+// global memory, so their bytes can't be copied out and put back
+fn is_handle_format(format: u32) -> bool {
+    matches!(
+        format,
+        formats::CF_BITMAP
+            | formats::CF_METAFILEPICT
+            | formats::CF_PALETTE
+            | formats::CF_ENHMETAFILE
+            | formats::CF_OWNERDISPLAY
+            | formats::CF_DSPBITMAP
+            | formats::CF_DSPMETAFILEPICT
+            | formats::CF_DSPENHMETAFILE
+    ) || (formats::CF_PRIVATEFIRST..=formats::CF_PRIVATELAST).contains(&format)
+        || (formats::CF_GDIOBJFIRST..=formats::CF_GDIOBJLAST).contains(&format)
+}
+
+/// Copy the raw bytes of every format on the clipboard, so rich content such
+/// as HTML, images (as DIB) and file drop lists survives the paste
+fn snapshot_formats() -> Vec<(u32, Vec<u8>)> {
+    let Ok(_clipboard) = Clipboard::new_attempts(10) else {
+        return Vec::new();
+    };
+
+    raw::EnumFormats::new()
+        .filter(|format| !is_handle_format(*format))
+        .filter_map(|format| {
+            let mut data = Vec::new();
+            raw::get_vec(format, &mut data).ok().map(|_| (format, data))
+        })
+        .collect()
+}
+
+/// Saves the clipboard contents on creation and puts it back when dropped, so the
+/// restore also runs when typing bails out early with an error
+struct ClipboardRestore {
+    formats: Vec<(u32, Vec<u8>)>,
+    // Text read back to verify a sync restore, None when there was no text
+    original: Option<String>,
+    // What we last put on the clipboard, anything else there by the time of
+    // the restore was copied by the user and is left alone
+    pasted: Option<String>,
+    restore: Restore,
+    restored: bool,
+}
+
+impl ClipboardRestore {
+    fn save(restore: Restore) -> Self {
+        ClipboardRestore {
+            original: get_clipboard(formats::Unicode).ok(),
+            formats: snapshot_formats(),
+            pasted: None,
+            restore,
+            restored: false,
+        }
+    }
+
+    /// Ok(false) when the restore was skipped because the clipboard no
+    /// longer holds what was pasted
+    fn restore(&mut self) -> Result<bool, String> {
+        if self.restored {
+            return Ok(true);
+        }
+        self.restored = true;
+
+        thread::sleep(self.restore.delay);
+
+        let current: Option<String> = get_clipboard(formats::Unicode).ok();
+        if current != self.pasted {
+            log::info!("Clipboard changed since the paste, leaving it as is");
+            return Ok(false);
+        }
+
+        if self.restore.mode == RestoreMode::Delayed {
+            return self.write_original().map(|()| true);
+        }
+
+        // Sync mode: only report success once the original reads back
+        for attempt in 1..=RESTORE_ATTEMPTS {
+            if self.write_original().is_ok() && self.original_is_current() {
+                return Ok(true);
+            }
+            thread::sleep(Duration::from_millis(10 * attempt as u64));
+        }
+
+        Err(format!(
+            "Clipboard did not match original after {} restore attempts",
+            RESTORE_ATTEMPTS
+        ))
+    }
+
+    fn write_original(&self) -> Result<(), String> {
+        let _clipboard = Clipboard::new_attempts(10)
+            .map_err(|e| format!("Failed to open clipboard: {:?}", e))?;
+        raw::empty().map_err(|e| format!("Failed to empty clipboard: {:?}", e))?;
+
+        // Put back every format we could read, even if one of them fails
+        let mut result = Ok(());
+        for (format, data) in &self.formats {
+            if let Err(e) = raw::set_without_clear(*format, data) {
+                result = Err(format!(
+                    "Failed to restore clipboard format {}: {:?}",
+                    format, e
+                ));
+            }
+        }
+        result
+    }
+
+    fn original_is_current(&self) -> bool {
+        let current: Option<String> = get_clipboard(formats::Unicode).ok();
+        match &self.original {
+            Some(text) => current.as_ref() == Some(text),
+            None => current.is_none_or(|text| text.is_empty()),
+        }
+    }
+}
+
+impl Drop for ClipboardRestore {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::warn!("{}", e);
+        }
+    }
+}
+
 /// Type text on Windows using clipboard paste approach
 /// This mimics the macOS implementation to avoid character-by-character typing
 /// issues
-pub fn type_text_windows(text: &str, _char_delay: u64) -> Result<(), String> {
-    // Store current clipboard contents to restore later
-    let old_contents: Result<String, _> = get_clipboard(formats::Unicode);
+pub fn type_text_windows(
+    enigo: &mut Enigo,
+    text: &str,
+    _char_delay: u64,
+    restore: Option<Restore>,
+    soft_breaks: bool,
+) -> TypeOutcome {
+    // Store current clipboard contents to restore once, after the last line
+    let mut old_contents = restore.map(ClipboardRestore::save);
+
+    let outcome = type_lines(text, soft_breaks, |line| {
+        if let Some(old_contents) = old_contents.as_mut() {
+            old_contents.pasted = Some(line.to_string());
+        }
+        TypeOutcome::typed(paste_text(enigo, line))
+    });
+    let restored = old_contents.as_mut().map(ClipboardRestore::restore);
+    TypeOutcome::pasted(outcome.result, restored, restore)
+}
 
+fn paste_text(enigo: &mut Enigo, text: &str) -> Result<(), String> {
     // Set our text to clipboard
     set_clipboard(formats::Unicode, text)
         .map_err(|e| format!("Failed to set clipboard: {:?}", e))?;
@@ -30,10 +176,6 @@ pub fn type_text_windows(text: &str, _char_delay: u64) -> Result<(), String> {
         }
     }
 
-    // Initialize enigo for keyboard simulation
-    let mut enigo = Enigo::new(&Settings::default())
-        .map_err(|e| format!("Failed to initialize enigo: {}", e))?;
-
     // Simulate Ctrl+V (paste)
     // Press Ctrl
     enigo
@@ -58,10 +200,5 @@ pub fn type_text_windows(text: &str, _char_delay: u64) -> Result<(), String> {
         .key(Key::Control, enigo::Direction::Release)
         .map_err(|e| format!("Failed to release Ctrl: {}", e))?;
 
-    if let Ok(old_text) = old_contents {
-        thread::sleep(Duration::from_secs(1));
-        let _ = set_clipboard(formats::Unicode, &old_text);
-    }
-
     Ok(())
 }